@@ -25,6 +25,9 @@ pub struct PackageSpec {
     pub source: String,
 
     /// Version constraint (semver format: "^1.0.0", "~2.1", ">=1.0").
+    ///
+    /// May be omitted when `rev` pins an exact commit.
+    #[serde(default)]
     pub version: String,
 
     /// Subpath within the repository (optional).
@@ -34,12 +37,22 @@ pub struct PackageSpec {
     /// Specific branch to use instead of tags.
     #[serde(default)]
     pub branch: Option<String>,
+
+    /// Exact commit SHA to install instead of resolving a version.
+    #[serde(default)]
+    pub rev: Option<String>,
 }
 
 impl LuneConfig {
     /// Parse config from JSON string.
+    ///
+    /// Comments and trailing commas are allowed, since the config is hand-edited.
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
-        serde_json::from_str(json)
+        let value =
+            jsonc_parser::parse_to_serde_value(json, &jsonc_parser::ParseOptions::default())
+                .map_err(serde::de::Error::custom)?
+                .unwrap_or_default();
+        serde_json::from_value(value)
     }
 }
 
@@ -61,4 +74,41 @@ mod tests {
         let config: LuneConfig = serde_json::from_str(json).unwrap();
         assert!(config.packages.contains_key("discord"));
     }
+
+    #[test]
+    fn parse_config_with_comments_and_trailing_commas() {
+        let json = r#"{
+            // Dependencies
+            "packages": {
+                "discord": {
+                    "source": "github:user/discord-luau",
+                    "version": "^1.0.0", /* pinned major */
+                },
+            },
+        }"#;
+
+        let config = LuneConfig::from_json(json).unwrap();
+        assert_eq!(config.packages["discord"].version, "^1.0.0");
+        assert!(LuneConfig::from_json("{ \"packages\": ").is_err());
+    }
+
+    #[test]
+    fn parse_config_with_rev() {
+        let json = r#"{
+            "packages": {
+                "discord": {
+                    "source": "github:user/discord-luau",
+                    "rev": "0123456789abcdef0123456789abcdef01234567"
+                }
+            }
+        }"#;
+
+        let config = LuneConfig::from_json(json).unwrap();
+        let spec = &config.packages["discord"];
+        assert_eq!(
+            spec.rev.as_deref(),
+            Some("0123456789abcdef0123456789abcdef01234567")
+        );
+        assert!(spec.version.is_empty());
+    }
 }
@@ -1,6 +1,9 @@
 //! Git operations for cloning repositories.
 
-use git2::{FetchOptions, Repository, build::RepoBuilder};
+use git2::{
+    FetchOptions, Oid, Repository,
+    build::{CheckoutBuilder, RepoBuilder},
+};
 use lune_utils::{AbsolutePath, InstallError};
 
 /// Clone a repository with shallow depth.
@@ -24,6 +27,75 @@ pub fn clone_shallow(
         })
 }
 
+/// Check that `rev` is a full commit SHA (SHA-1 or SHA-256, hex encoded).
+///
+/// Abbreviated hashes are rejected since remotes only serve exact object ids.
+pub fn validate_rev(rev: &str) -> Result<(), InstallError> {
+    let is_hex = rev.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex && (rev.len() == 40 || rev.len() == 64) {
+        Ok(())
+    } else {
+        Err(InstallError::InvalidConfig {
+            path: String::new(),
+            reason: format!("Invalid rev '{rev}': expected a full 40-character commit SHA"),
+        })
+    }
+}
+
+/// Clone a repository and check out the exact commit `rev`.
+///
+/// Only that commit is fetched when the remote allows it, otherwise
+/// the full history is fetched and the commit is looked up locally.
+pub fn clone_at_rev(
+    url: &str,
+    target: &AbsolutePath,
+    rev: &str,
+) -> Result<Repository, InstallError> {
+    let clone_failed = |e: git2::Error| InstallError::GitCloneFailed {
+        url: url.to_owned(),
+        message: e.message().to_owned(),
+    };
+
+    validate_rev(rev)?;
+    let oid = Oid::from_str(rev).map_err(clone_failed)?;
+
+    let repo = Repository::init(target.as_path()).map_err(clone_failed)?;
+    {
+        let mut fetch_opts = FetchOptions::new();
+        fetch_opts.depth(1);
+        let shallow = repo
+            .remote("origin", url)
+            .and_then(|mut remote| remote.fetch(&[rev], Some(&mut fetch_opts), None));
+        if shallow.is_err() {
+            // A fresh remote is needed, since a failed shallow fetch keeps its depth
+            repo.find_remote("origin")
+                .and_then(|mut remote| {
+                    remote.fetch(
+                        &[
+                            "+refs/heads/*:refs/remotes/origin/*",
+                            "+refs/tags/*:refs/tags/*",
+                        ],
+                        None,
+                        None,
+                    )
+                })
+                .map_err(clone_failed)?;
+        }
+
+        let commit = repo
+            .find_commit(oid)
+            .map_err(|_| InstallError::GitCloneFailed {
+                url: url.to_owned(),
+                message: format!("Commit {rev} not found in remote"),
+            })?;
+        repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))
+            .map_err(clone_failed)?;
+    }
+    repo.set_head_detached(oid).map_err(clone_failed)?;
+
+    Ok(repo)
+}
+
 /// List remote tags from a repository URL.
 #[allow(dead_code)]
 pub fn list_remote_tags(url: &str) -> Result<Vec<String>, InstallError> {
@@ -49,3 +121,77 @@ pub fn list_remote_tags(url: &str) -> Result<Vec<String>, InstallError> {
 
     Ok(tags)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::path::{Path, PathBuf};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("lune-installer-git-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn commit_file(repo: &Repository, dir: &Path, contents: &str) -> Oid {
+        std::fs::write(dir.join("init.luau"), contents).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("init.luau")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let sig = Signature::now("lune", "lune@example.com").unwrap();
+        let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, contents, &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn validate_rev_format() {
+        assert!(validate_rev("0123456789abcdef0123456789abcdef01234567").is_ok());
+        assert!(validate_rev("0123456").is_err());
+        assert!(validate_rev("main").is_err());
+        assert!(validate_rev("g123456789abcdef0123456789abcdef01234567").is_err());
+    }
+
+    #[test]
+    fn clone_specific_commit() {
+        let source = temp_dir("source");
+        let repo = Repository::init(&source).unwrap();
+        let first = commit_file(&repo, &source, "return 1");
+        let second = commit_file(&repo, &source, "return 2");
+        assert_ne!(first, second);
+
+        let target = temp_dir("target");
+        let url = source.to_string_lossy().into_owned();
+        let cloned = clone_at_rev(
+            &url,
+            &AbsolutePath::new(target.clone()).unwrap(),
+            &first.to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(cloned.head().unwrap().target(), Some(first));
+        assert!(cloned.head_detached().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(target.join("init.luau")).unwrap(),
+            "return 1"
+        );
+
+        let missing = temp_dir("missing");
+        let err = clone_at_rev(
+            &url,
+            &AbsolutePath::new(missing.clone()).unwrap(),
+            &"f".repeat(40),
+        );
+        assert!(err.is_err());
+
+        let _ = std::fs::remove_dir_all(source);
+        let _ = std::fs::remove_dir_all(target);
+        let _ = std::fs::remove_dir_all(missing);
+    }
+}
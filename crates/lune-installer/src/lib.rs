@@ -59,6 +59,7 @@ impl PackageInstaller {
                     installed.packages.push(InstalledPackage {
                         name: name.clone(),
                         version: spec.version.clone(),
+                        rev: spec.rev.clone(),
                         path,
                     });
                 }
@@ -101,13 +102,21 @@ impl PackageInstaller {
             return Ok(target_dir);
         }
 
-        // Fetch from registry and resolve version
-        let manifest = self.registry.fetch_manifest(&spec.source).await?;
-        let resolved = PackageResolver::resolve(&manifest, &spec.version)?;
-
-        // Clone repository
-        git::clone_shallow(&resolved.clone_url, &target_dir, &resolved.tag)?;
-        rollback_paths.push(target_dir.clone());
+        if let Some(rev) = &spec.rev {
+            // Pinned commit, only the repository URL is needed from the registry
+            git::validate_rev(rev)?;
+            let manifest = self.registry.fetch_manifest(&spec.source).await?;
+            rollback_paths.push(target_dir.clone());
+            git::clone_at_rev(&manifest.repository, &target_dir, rev)?;
+        } else {
+            // Fetch from registry and resolve version
+            let manifest = self.registry.fetch_manifest(&spec.source).await?;
+            let resolved = PackageResolver::resolve(&manifest, &spec.version)?;
+
+            // Clone repository
+            git::clone_shallow(&resolved.clone_url, &target_dir, &resolved.tag)?;
+            rollback_paths.push(target_dir.clone());
+        }
 
         Ok(target_dir)
     }
@@ -133,5 +142,7 @@ pub struct InstalledPackages {
 pub struct InstalledPackage {
     pub name: String,
     pub version: String,
+    /// Commit SHA the package was pinned to, if any.
+    pub rev: Option<String>,
     pub path: AbsolutePath,
 }
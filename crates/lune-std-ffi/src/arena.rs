@@ -8,7 +8,7 @@
 //! - Pointers become invalid when the arena is dropped
 
 use mlua::prelude::*;
-use std::alloc::{Layout, alloc_zeroed, dealloc};
+use std::alloc::{Layout, alloc, alloc_zeroed, dealloc};
 use std::cell::RefCell;
 
 use crate::pointer::{RawPointer, next_arena_id};
@@ -56,8 +56,19 @@ impl Arena {
         self.alloc_aligned(size, 8)
     }
 
+    /// Allocate memory from the arena without zeroing it.
+    ///
+    /// The contents are unspecified until written.
+    pub fn alloc_uninit(&self, size: usize) -> LuaResult<RawPointer> {
+        self.alloc_raw(size, 8, false)
+    }
+
     /// Allocate aligned memory from the arena
     pub fn alloc_aligned(&self, size: usize, align: usize) -> LuaResult<RawPointer> {
+        self.alloc_raw(size, align, true)
+    }
+
+    fn alloc_raw(&self, size: usize, align: usize, zeroed: bool) -> LuaResult<RawPointer> {
         if size == 0 {
             return Err(LuaError::external("Cannot allocate 0 bytes"));
         }
@@ -65,7 +76,11 @@ impl Arena {
         let layout = Layout::from_size_align(size, align.max(1))
             .map_err(|e| LuaError::external(format!("Invalid layout: {}", e)))?;
 
-        let ptr = unsafe { alloc_zeroed(layout) };
+        let ptr = if zeroed {
+            unsafe { alloc_zeroed(layout) }
+        } else {
+            unsafe { alloc(layout) }
+        };
         if ptr.is_null() {
             return Err(LuaError::external("Allocation failed: out of memory"));
         }
@@ -143,6 +158,11 @@ impl LuaUserData for Arena {
         // alloc(size) -> RawPointer
         methods.add_method("alloc", |_, this, size: usize| this.alloc(size));
 
+        // allocUninit(size) -> RawPointer - contents are garbage until written
+        methods.add_method("allocUninit", |_, this, size: usize| {
+            this.alloc_uninit(size)
+        });
+
         // allocAligned(size, align) -> RawPointer
         methods.add_method("allocAligned", |_, this, (size, align): (usize, usize)| {
             this.alloc_aligned(size, align)
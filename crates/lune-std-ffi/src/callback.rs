@@ -9,13 +9,49 @@ use std::ffi::c_void;
 use std::ptr::{self, addr_of_mut};
 
 use libffi::low::{
-    CodePtr, closure_alloc, closure_free, ffi_cif, ffi_closure, ffi_type, prep_cif,
-    prep_closure_mut,
+    CodePtr, closure_alloc, closure_free, ffi_arg, ffi_cif, ffi_closure, ffi_sarg, ffi_type,
+    prep_cif, prep_closure_mut,
 };
 use libffi::raw::ffi_abi_FFI_DEFAULT_ABI;
 use mlua::prelude::*;
 
-use crate::types::CType;
+use crate::struct_mapper::{StructDefinition, StructView};
+use crate::types::{CType, unsigned_into_lua};
+
+/// Declared type of a callback argument.
+///
+/// Struct and union definitions are passed by pointer and handed to Lua as a
+/// `StructView` or `UnionView`.
+#[derive(Debug, Clone)]
+pub enum CallbackArg {
+    Value(CType),
+    Struct(StructDefinition),
+}
+
+impl CallbackArg {
+    fn ffi_type(&self) -> *mut ffi_type {
+        match self {
+            Self::Value(ctype) => ctype_to_ffi_type(*ctype),
+            Self::Struct(_) => addr_of_mut!(libffi::low::types::pointer),
+        }
+    }
+}
+
+impl FromLua for CallbackArg {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::UserData(ud) => {
+                let def = StructDefinition::from_userdata(&ud).map_err(|_| {
+                    LuaError::external(
+                        "Expected CType, StructDefinition or UnionDefinition for callback argument",
+                    )
+                })?;
+                Ok(Self::Struct(def))
+            }
+            other => CType::from_lua(other, lua).map(Self::Value),
+        }
+    }
+}
 
 /// Convert CType to libffi ffi_type pointer
 fn ctype_to_ffi_type(ctype: CType) -> *mut ffi_type {
@@ -48,7 +84,7 @@ fn ctype_to_ffi_type(ctype: CType) -> *mut ffi_type {
 struct CallbackData {
     func_key: LuaRegistryKey,
     lua_ptr: *const Lua,
-    arg_types: Vec<CType>,
+    arg_types: Vec<CallbackArg>,
     ret_type: CType,
 }
 
@@ -77,6 +113,33 @@ unsafe extern "C" fn callback_trampoline(
         let mut lua_args = Vec::with_capacity(data.arg_types.len());
         for (i, arg_type) in data.arg_types.iter().enumerate() {
             let arg_ptr = *args.add(i);
+            let arg_type = match arg_type {
+                CallbackArg::Value(ctype) => ctype,
+                CallbackArg::Struct(def) => {
+                    let struct_ptr = *(arg_ptr as *const *mut c_void);
+                    let lua_val = if struct_ptr.is_null() {
+                        LuaValue::Nil
+                    } else {
+                        let view = StructView {
+                            ptr: struct_ptr,
+                            def: def.clone(),
+                            arena_id: 0, // Unmanaged
+                        };
+                        match view.into_lua_view(lua) {
+                            Ok(ud) => LuaValue::UserData(ud),
+                            Err(e) => {
+                                eprintln!(
+                                    "[FFI CALLBACK ERROR] Failed to create struct view: {}",
+                                    e
+                                );
+                                LuaValue::Nil
+                            }
+                        }
+                    };
+                    lua_args.push(lua_val);
+                    continue;
+                }
+            };
             let lua_val = match arg_type {
                 CType::Void => LuaValue::Nil,
                 CType::Bool => LuaValue::Boolean(*(arg_ptr as *const i8) != 0),
@@ -87,9 +150,12 @@ unsafe extern "C" fn callback_trampoline(
                 CType::I32 => LuaValue::Integer(i64::from(*(arg_ptr as *const i32))),
                 CType::U32 => LuaValue::Integer(i64::from(*(arg_ptr as *const u32))),
                 CType::I64 => LuaValue::Integer(*(arg_ptr as *const i64)),
-                CType::U64 => LuaValue::Number(*(arg_ptr as *const u64) as f64),
+                CType::U64 => {
+                    unsigned_into_lua(lua, *(arg_ptr as *const u64)).unwrap_or(LuaValue::Nil)
+                }
                 CType::ISize => LuaValue::Integer(*(arg_ptr as *const isize) as i64),
-                CType::USize => LuaValue::Integer(*(arg_ptr as *const usize) as i64),
+                CType::USize => unsigned_into_lua(lua, *(arg_ptr as *const usize) as u64)
+                    .unwrap_or(LuaValue::Nil),
                 CType::F32 => LuaValue::Number(f64::from(*(arg_ptr as *const f32))),
                 CType::F64 => LuaValue::Number(*(arg_ptr as *const f64)),
                 CType::Pointer => {
@@ -123,26 +189,36 @@ unsafe extern "C" fn callback_trampoline(
                 let first = values.into_iter().next().unwrap_or(LuaValue::Nil);
                 match data.ret_type {
                     CType::Void => {}
+                    // libffi expects integers narrower than a register to be
+                    // written as a whole, extended ffi_arg, so that no stale
+                    // upper bytes reach callers that read the full register
                     CType::Bool => {
-                        *(ret_ptr as *mut i8) = i8::from(first.as_boolean().unwrap_or(false));
+                        *(ret_ptr as *mut ffi_arg) =
+                            ffi_arg::from(first.as_boolean().unwrap_or(false));
                     }
                     CType::I8 => {
-                        *(ret_ptr as *mut i8) = first.as_integer().unwrap_or(0) as i8;
+                        *(ret_ptr as *mut ffi_sarg) =
+                            first.as_integer().unwrap_or(0) as i8 as ffi_sarg;
                     }
                     CType::U8 => {
-                        *(ret_ptr as *mut u8) = first.as_integer().unwrap_or(0) as u8;
+                        *(ret_ptr as *mut ffi_arg) =
+                            first.as_integer().unwrap_or(0) as u8 as ffi_arg;
                     }
                     CType::I16 => {
-                        *(ret_ptr as *mut i16) = first.as_integer().unwrap_or(0) as i16;
+                        *(ret_ptr as *mut ffi_sarg) =
+                            first.as_integer().unwrap_or(0) as i16 as ffi_sarg;
                     }
                     CType::U16 => {
-                        *(ret_ptr as *mut u16) = first.as_integer().unwrap_or(0) as u16;
+                        *(ret_ptr as *mut ffi_arg) =
+                            first.as_integer().unwrap_or(0) as u16 as ffi_arg;
                     }
                     CType::I32 => {
-                        *(ret_ptr as *mut i32) = first.as_integer().unwrap_or(0) as i32;
+                        *(ret_ptr as *mut ffi_sarg) =
+                            first.as_integer().unwrap_or(0) as i32 as ffi_sarg;
                     }
                     CType::U32 => {
-                        *(ret_ptr as *mut u32) = first.as_integer().unwrap_or(0) as u32;
+                        *(ret_ptr as *mut ffi_arg) =
+                            first.as_integer().unwrap_or(0) as u32 as ffi_arg;
                     }
                     CType::I64 => {
                         *(ret_ptr as *mut i64) = first.as_integer().unwrap_or(0);
@@ -201,17 +277,12 @@ impl FfiCallback {
         lua: &Lua,
         func: LuaFunction,
         ret_type: CType,
-        arg_types: Vec<CType>,
+        arg_types: Vec<CallbackArg>,
     ) -> LuaResult<Self> {
-        if arg_types.len() > 16 {
-            eprintln!("[FFI ERROR] Callbacks with more than 16 arguments not supported");
-            return Err(LuaError::external("Callbacks with >16 args not supported"));
-        }
-
         let func_key = lua.create_registry_value(func)?;
 
         let arg_types_ffi: Vec<*mut ffi_type> =
-            arg_types.iter().map(|t| ctype_to_ffi_type(*t)).collect();
+            arg_types.iter().map(CallbackArg::ffi_type).collect();
 
         let ret_type_ffi = ctype_to_ffi_type(ret_type);
 
@@ -311,7 +382,45 @@ pub fn create_callback(
     lua: &Lua,
     func: LuaFunction,
     ret_type: CType,
-    arg_types: Vec<CType>,
+    arg_types: Vec<CallbackArg>,
 ) -> LuaResult<FfiCallback> {
     FfiCallback::new(lua, func, ret_type, arg_types)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn callback(lua: &Lua, source: &str, ret_type: CType, args: Vec<CallbackArg>) -> FfiCallback {
+        let func = lua.load(source).into_function().unwrap();
+        FfiCallback::new(lua, func, ret_type, args).unwrap()
+    }
+
+    #[test]
+    fn bool_returns_reach_c_as_zero_or_one() {
+        let lua = Lua::new();
+        for (source, expected) in [("return true", 1), ("return false", 0), ("return nil", 0)] {
+            let cb = callback(&lua, source, CType::Bool, Vec::new());
+            // Read back the whole byte, a C caller only gets 0 or 1 for a bool
+            let f: extern "C" fn() -> u8 = unsafe { std::mem::transmute(cb.as_ptr()) };
+            assert_eq!(f(), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn bool_args_are_truthy_for_any_nonzero_byte() {
+        let lua = Lua::new();
+        let cb = callback(
+            &lua,
+            "return ...",
+            CType::Bool,
+            vec![CallbackArg::Value(CType::Bool)],
+        );
+        // C code may hand over a bool stored as any nonzero byte
+        let f: extern "C" fn(u8) -> bool = unsafe { std::mem::transmute(cb.as_ptr()) };
+        assert!(f(1));
+        assert!(f(2));
+        assert!(f(0x80));
+        assert!(!f(0));
+    }
+}
@@ -1,10 +1,11 @@
 //! Dynamic function caller using libffi for arbitrary function signatures.
 
-use libffi::middle::{Arg, Builder, CodePtr, Type as FfiType};
+use libffi::middle::{Arg, Builder, Cif, CodePtr, Type as FfiType};
 use mlua::prelude::*;
-use std::ffi::{CStr, CString, c_void};
+use std::ffi::{CStr, CString, c_char, c_void};
 
-use crate::types::{Buffer, CType};
+use crate::callback::FfiCallback;
+use crate::types::{Buffer, CType, unsigned_from_lua, unsigned_into_lua};
 
 /// Convert `CType` to libffi Type
 fn ctype_to_ffi(ctype: CType) -> FfiType {
@@ -43,7 +44,10 @@ enum ArgValue {
     F32(f32),
     F64(f64),
     Pointer(*mut c_void),
-    CStringVal(CString),
+    /// Code pointer of an `FfiCallback`, holding on to the callback for the call
+    Callback(*mut c_void, LuaAnyUserData),
+    /// Owned string, with its pointer kept alongside so `as_arg` can refer to it
+    CStringVal(CString, *const c_char),
 }
 
 impl ArgValue {
@@ -62,8 +66,30 @@ impl ArgValue {
             Self::USize(v) => Arg::new(v),
             Self::F32(v) => Arg::new(v),
             Self::F64(v) => Arg::new(v),
-            Self::Pointer(v) => Arg::new(v),
-            Self::CStringVal(v) => Arg::new(&v.as_ptr()),
+            Self::Pointer(v) | Self::Callback(v, _) => Arg::new(v),
+            Self::CStringVal(_, ptr) => Arg::new(ptr),
+        }
+    }
+
+    /// Apply C's default argument promotions, as for the `...` of a variadic function:
+    /// integers narrower than `int` widen to `int`, and `float` widens to `double`
+    fn promoted(self) -> (Self, CType) {
+        match self {
+            Self::Bool(v) => (Self::I32(i32::from(v)), CType::I32),
+            Self::I8(v) => (Self::I32(i32::from(v)), CType::I32),
+            Self::U8(v) => (Self::I32(i32::from(v)), CType::I32),
+            Self::I16(v) => (Self::I32(i32::from(v)), CType::I32),
+            Self::U16(v) => (Self::I32(i32::from(v)), CType::I32),
+            Self::F32(v) => (Self::F64(f64::from(v)), CType::F64),
+            Self::I32(_) => (self, CType::I32),
+            Self::U32(_) => (self, CType::U32),
+            Self::I64(_) => (self, CType::I64),
+            Self::U64(_) => (self, CType::U64),
+            Self::ISize(_) => (self, CType::ISize),
+            Self::USize(_) => (self, CType::USize),
+            Self::F64(_) => (self, CType::F64),
+            Self::Pointer(_) | Self::Callback(..) => (self, CType::Pointer),
+            Self::CStringVal(..) => (self, CType::CString),
         }
     }
 }
@@ -101,18 +127,12 @@ fn lua_to_arg(lua: &Lua, value: LuaValue, ctype: CType) -> LuaResult<ArgValue> {
             let v: i64 = FromLua::from_lua(value, lua)?;
             ArgValue::I64(v)
         }
-        CType::U64 => {
-            let v: f64 = FromLua::from_lua(value, lua)?;
-            ArgValue::U64(v as u64)
-        }
+        CType::U64 => ArgValue::U64(unsigned_from_lua(&value)?),
         CType::ISize => {
             let v: i64 = FromLua::from_lua(value, lua)?;
             ArgValue::ISize(v as isize)
         }
-        CType::USize => {
-            let v: i64 = FromLua::from_lua(value, lua)?;
-            ArgValue::USize(v as usize)
-        }
+        CType::USize => ArgValue::USize(unsigned_from_lua(&value)? as usize),
         CType::F32 => {
             let v: f64 = FromLua::from_lua(value, lua)?;
             ArgValue::F32(v as f32)
@@ -127,8 +147,12 @@ fn lua_to_arg(lua: &Lua, value: LuaValue, ctype: CType) -> LuaResult<ArgValue> {
             LuaValue::UserData(ud) => {
                 if let Ok(buf) = ud.borrow::<Buffer>() {
                     ArgValue::Pointer(buf.as_ptr().cast::<c_void>())
+                } else if let Ok(callback) = ud.borrow::<FfiCallback>() {
+                    ArgValue::Callback(callback.as_ptr(), ud.clone())
                 } else {
-                    return Err(LuaError::external("Expected pointer, buffer, or nil"));
+                    return Err(LuaError::external(
+                        "Expected pointer, buffer, callback, or nil",
+                    ));
                 }
             }
             LuaValue::Integer(i) => ArgValue::Pointer(i as usize as *mut c_void),
@@ -141,7 +165,8 @@ fn lua_to_arg(lua: &Lua, value: LuaValue, ctype: CType) -> LuaResult<ArgValue> {
             let bytes: Vec<u8> = borrowed.to_vec();
             let cstr =
                 CString::new(bytes).map_err(|_| LuaError::external("String contains null byte"))?;
-            ArgValue::CStringVal(cstr)
+            let ptr = cstr.as_ptr();
+            ArgValue::CStringVal(cstr, ptr)
         }
     })
 }
@@ -149,7 +174,7 @@ fn lua_to_arg(lua: &Lua, value: LuaValue, ctype: CType) -> LuaResult<ArgValue> {
 /// Convert a return value based on `CType`
 fn call_and_convert(
     lua: &Lua,
-    cif: &libffi::middle::Cif,
+    cif: &Cif,
     code_ptr: CodePtr,
     args: &[Arg],
     ret_type: CType,
@@ -160,6 +185,7 @@ fn call_and_convert(
             LuaValue::Nil
         }
         CType::Bool => {
+            // Only the low byte of a bool return is defined, so read just that byte
             let result: i8 = unsafe { cif.call(code_ptr, args) };
             LuaValue::Boolean(result != 0)
         }
@@ -193,7 +219,7 @@ fn call_and_convert(
         }
         CType::U64 => {
             let result: u64 = unsafe { cif.call(code_ptr, args) };
-            (result as f64).into_lua(lua)?
+            unsigned_into_lua(lua, result)?
         }
         CType::ISize => {
             let result: isize = unsafe { cif.call(code_ptr, args) };
@@ -201,7 +227,7 @@ fn call_and_convert(
         }
         CType::USize => {
             let result: usize = unsafe { cif.call(code_ptr, args) };
-            (result as i64).into_lua(lua)?
+            unsigned_into_lua(lua, result as u64)?
         }
         CType::F32 => {
             let result: f32 = unsafe { cif.call(code_ptr, args) };
@@ -256,12 +282,74 @@ pub fn dynamic_call(
         .into_cif();
 
     // Convert arguments
-    let arg_values: Vec<ArgValue> = args
-        .into_iter()
+    let arg_values = convert_args(lua, args, arg_types)?;
+
+    let ffi_args: Vec<Arg> = arg_values.iter().map(ArgValue::as_arg).collect();
+
+    // Call
+    let code_ptr = CodePtr::from_ptr(fn_ptr);
+    call_and_convert(lua, &cif, code_ptr, &ffi_args, ret_type)
+}
+
+/// Perform a dynamic call to a variadic function, such as `printf`.
+///
+/// `fixed_types` and `args` are the named parameters, and `var_args` the
+/// values passed for `...` along with their types. Variadic arguments go
+/// through C's default argument promotions before the call.
+pub fn dynamic_call_variadic(
+    lua: &Lua,
+    fn_ptr: *const c_void,
+    ret_type: CType,
+    fixed_types: &[CType],
+    args: Vec<LuaValue>,
+    var_args: Vec<(CType, LuaValue)>,
+) -> LuaResult<LuaValue> {
+    if args.len() != fixed_types.len() {
+        let msg = format!(
+            "Expected {} fixed arguments, got {}",
+            fixed_types.len(),
+            args.len()
+        );
+        eprintln!("[FFI ERROR] Argument count mismatch: {}", msg);
+        return Err(LuaError::external(msg));
+    }
+
+    // Convert arguments, promoting the variadic ones
+    let mut arg_values = convert_args(lua, args, fixed_types)?;
+    let mut ffi_arg_types: Vec<FfiType> = fixed_types.iter().map(|t| ctype_to_ffi(*t)).collect();
+    for (i, (ctype, value)) in var_args.into_iter().enumerate() {
+        let (value, ctype) = lua_to_arg(lua, value, ctype)
+            .map_err(|e| {
+                LuaError::external(format!(
+                    "Variadic argument {} conversion failed (expected {:?}): {}",
+                    i + 1,
+                    ctype,
+                    e
+                ))
+            })?
+            .promoted();
+        arg_values.push(value);
+        ffi_arg_types.push(ctype_to_ffi(ctype));
+    }
+
+    // Only the first `fixed_types.len()` arguments are named, which matters
+    // on ABIs that pass variadic arguments differently, like Apple arm64
+    let cif = Cif::new_variadic(ffi_arg_types, fixed_types.len(), ctype_to_ffi(ret_type));
+
+    let ffi_args: Vec<Arg> = arg_values.iter().map(ArgValue::as_arg).collect();
+
+    // Call
+    let code_ptr = CodePtr::from_ptr(fn_ptr);
+    call_and_convert(lua, &cif, code_ptr, &ffi_args, ret_type)
+}
+
+/// Convert call arguments from Lua, one per type
+fn convert_args(lua: &Lua, args: Vec<LuaValue>, arg_types: &[CType]) -> LuaResult<Vec<ArgValue>> {
+    args.into_iter()
         .zip(arg_types.iter())
         .enumerate()
         .map(|(i, (v, t))| {
-            lua_to_arg(lua, v.clone(), *t).map_err(|e| {
+            lua_to_arg(lua, v, *t).map_err(|e| {
                 eprintln!(
                     "[FFI ERROR] Argument {} conversion failed (expected {:?}): {}",
                     i, t, e
@@ -269,11 +357,5 @@ pub fn dynamic_call(
                 e
             })
         })
-        .collect::<LuaResult<Vec<_>>>()?;
-
-    let ffi_args: Vec<Arg> = arg_values.iter().map(ArgValue::as_arg).collect();
-
-    // Call
-    let code_ptr = CodePtr::from_ptr(fn_ptr);
-    call_and_convert(lua, &cif, code_ptr, &ffi_args, ret_type)
+        .collect()
 }
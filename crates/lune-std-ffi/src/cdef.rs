@@ -0,0 +1,967 @@
+//! C declaration parser for `ffi.cdef`.
+//!
+//! Understands the subset of C found in most library headers: function
+//! prototypes, `typedef`s and `struct` or `union` definitions, built from primitive
+//! types, pointers and fixed-size arrays. Anything else is rejected with an
+//! error pointing at the offending line, rather than silently misread.
+//!
+//! Declarations are kept per Lua state, so that libraries from `ffi.load`
+//! can bind declared functions by name, and `ffi.typeof` can look up
+//! declared types. Struct layouts are computed by [`StructDefinition`].
+
+use std::collections::HashMap;
+use std::ffi::c_long;
+use std::sync::Arc;
+
+use libloading::Library;
+use mlua::prelude::*;
+
+use crate::smart_library::SmartBoundFunction;
+use crate::struct_mapper::{Endian, FieldSpec, Pointee, StructDefinition, UnionDefinition};
+use crate::types::CType;
+
+/// A type name declared with `typedef`
+#[derive(Debug, Clone)]
+enum Alias {
+    Scalar(CType),
+    /// A struct, by its key in [`CdefRegistry::structs`]
+    Struct(String),
+}
+
+/// A function prototype
+#[derive(Debug, Clone)]
+struct FunctionDecl {
+    ret: CType,
+    args: Vec<CType>,
+}
+
+/// Everything declared through `ffi.cdef` in a Lua state.
+///
+/// Later declarations replace earlier ones with the same name.
+#[derive(Debug, Clone, Default)]
+pub struct CdefRegistry {
+    aliases: HashMap<String, Alias>,
+    /// Structs by tag, `None` while only declared and not yet defined
+    structs: HashMap<String, Option<StructDefinition>>,
+    functions: HashMap<String, FunctionDecl>,
+}
+
+/// A type resolved by `ffi.typeof`
+pub enum DeclaredType {
+    Scalar(CType),
+    Struct(StructDefinition),
+}
+
+impl IntoLua for DeclaredType {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        match self {
+            Self::Scalar(ctype) => ctype.into_lua(lua),
+            Self::Struct(def) if def.is_union => UnionDefinition(def).into_lua(lua),
+            Self::Struct(def) => def.into_lua(lua),
+        }
+    }
+}
+
+/// Parse `source` and register its declarations.
+///
+/// Nothing is registered if any declaration fails to parse.
+pub fn declare(lua: &Lua, source: &str) -> LuaResult<()> {
+    let mut parser = Parser::new("ffi.cdef", source, current_registry(lua))?;
+    while !parser.at_end() {
+        parser.parse_declaration()?;
+    }
+    lua.set_app_data(parser.registry);
+    Ok(())
+}
+
+/// Resolve a type name such as `"Point"`, `"struct Point"` or `"unsigned int"`.
+pub fn type_of(lua: &Lua, name: &str) -> LuaResult<DeclaredType> {
+    let mut parser = Parser::new("ffi.typeof", name, current_registry(lua))?;
+    let ty = parser.parse_type_name()?;
+    if !parser.at_end() {
+        return Err(parser.error("expected a single type name"));
+    }
+    Ok(ty)
+}
+
+/// Bind the function `name` from `library`, if it was declared through `ffi.cdef`.
+pub fn bind_declared(
+    lua: &Lua,
+    library: &Arc<Library>,
+    name: &str,
+) -> LuaResult<Option<SmartBoundFunction>> {
+    let decl = lua
+        .app_data_ref::<CdefRegistry>()
+        .and_then(|registry| registry.functions.get(name).cloned());
+    let Some(decl) = decl else {
+        return Ok(None);
+    };
+    SmartBoundFunction::from_declaration(Arc::clone(library), name, decl.ret, &decl.args).map(Some)
+}
+
+/// Scalar type named by a `typedef` or a standard header, such as `uint32_t`.
+pub fn lookup_scalar(lua: &Lua, name: &str) -> Option<CType> {
+    if let Some(ctype) = standard_typedef(name) {
+        return Some(ctype);
+    }
+    match lua.app_data_ref::<CdefRegistry>()?.aliases.get(name)? {
+        Alias::Scalar(ctype) => Some(*ctype),
+        Alias::Struct(_) => None,
+    }
+}
+
+fn current_registry(lua: &Lua) -> CdefRegistry {
+    lua.app_data_ref::<CdefRegistry>()
+        .map(|registry| registry.clone())
+        .unwrap_or_default()
+}
+
+/// Integer types from `stdint.h` and `stddef.h`
+fn standard_typedef(name: &str) -> Option<CType> {
+    Some(match name {
+        "int8_t" => CType::I8,
+        "uint8_t" => CType::U8,
+        "int16_t" => CType::I16,
+        "uint16_t" => CType::U16,
+        "int32_t" => CType::I32,
+        "uint32_t" => CType::U32,
+        "int64_t" => CType::I64,
+        "uint64_t" => CType::U64,
+        "size_t" | "uintptr_t" => CType::USize,
+        "ssize_t" | "intptr_t" | "ptrdiff_t" => CType::ISize,
+        _ => return None,
+    })
+}
+
+// ============================================================================
+// Tokenizer
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(u64),
+    Punct(char),
+    Ellipsis,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ident(name) => write!(f, "'{name}'"),
+            Self::Number(n) => write!(f, "'{n}'"),
+            Self::Punct(c) => write!(f, "'{c}'"),
+            Self::Ellipsis => write!(f, "'...'"),
+        }
+    }
+}
+
+/// Split `source` into tokens, each paired with the line it starts on.
+fn tokenize(context: &str, source: &str) -> LuaResult<Vec<(Token, usize)>> {
+    let error = |line: usize, message: String| {
+        LuaError::external(format!("{context}: line {line}: {message}"))
+    };
+
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    let mut line = 1;
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek().is_some_and(|(_, next)| *next == '/') => {
+                while chars.next_if(|(_, next)| *next != '\n').is_some() {}
+            }
+            '/' if chars.peek().is_some_and(|(_, next)| *next == '*') => {
+                chars.next();
+                let comment_line = line;
+                let mut prev = ' ';
+                loop {
+                    let Some((_, next)) = chars.next() else {
+                        return Err(error(comment_line, "unterminated comment".to_owned()));
+                    };
+                    if next == '\n' {
+                        line += 1;
+                    }
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+            '#' => {
+                return Err(error(
+                    line,
+                    "preprocessor directives are not supported".to_owned(),
+                ));
+            }
+            '.' => {
+                if source[start..].starts_with("...") {
+                    chars.next();
+                    chars.next();
+                    tokens.push((Token::Ellipsis, line));
+                } else {
+                    return Err(error(line, "unexpected '.'".to_owned()));
+                }
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ';' | ',' | '*' | ':' => {
+                tokens.push((Token::Punct(c), line));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, next)) =
+                    chars.next_if(|(_, next)| next.is_ascii_alphanumeric() || *next == '_')
+                {
+                    end = i + next.len_utf8();
+                }
+                tokens.push((Token::Ident(source[start..end].to_owned()), line));
+            }
+            c if c.is_ascii_digit() => {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars.next_if(|(_, next)| next.is_ascii_alphanumeric()) {
+                    end = i + 1;
+                }
+                let literal = &source[start..end];
+                let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+                let value = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => digits.parse(),
+                };
+                let value =
+                    value.map_err(|_| error(line, format!("invalid number '{literal}'")))?;
+                tokens.push((Token::Number(value), line));
+            }
+            c => return Err(error(line, format!("unexpected character '{c}'"))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// ============================================================================
+// Parser
+// ============================================================================
+
+/// Type named by the specifiers of a declaration, before any `*` or `[]`
+#[derive(Debug, Clone)]
+enum BaseType {
+    Scalar(CType),
+    /// Plain `char`, whose pointers are passed and read as strings
+    Char,
+    /// A struct, by its key in [`CdefRegistry::structs`]
+    Struct(String),
+}
+
+/// The part of a declaration naming one thing, such as `*name[4]`
+#[derive(Debug, Default)]
+struct Declarator {
+    name: Option<String>,
+    pointers: usize,
+    /// `Some` for arrays, holding the length unless it was left out as in `name[]`
+    array: Option<Option<usize>>,
+    /// Pointer to a function, as in `(*name)(int)`
+    function_pointer: bool,
+}
+
+/// Words that may be mixed to name a primitive type, as in `unsigned long long`
+const PRIMITIVE_WORDS: &[&str] = &[
+    "void", "_Bool", "bool", "char", "short", "int", "long", "signed", "unsigned", "float",
+    "double",
+];
+
+/// Words that change nothing about the layout or calling convention of a type
+const QUALIFIERS: &[&str] = &[
+    "const",
+    "volatile",
+    "restrict",
+    "__restrict",
+    "extern",
+    "static",
+    "register",
+];
+
+struct Parser {
+    context: &'static str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    registry: CdefRegistry,
+    anonymous_structs: usize,
+}
+
+impl Parser {
+    fn new(context: &'static str, source: &str, registry: CdefRegistry) -> LuaResult<Self> {
+        Ok(Self {
+            context,
+            tokens: tokenize(context, source)?,
+            pos: 0,
+            registry,
+            anonymous_structs: 0,
+        })
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(token, _)| token)
+    }
+
+    fn peek_ident(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Ident(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn error(&self, message: impl std::fmt::Display) -> LuaError {
+        let line = self
+            .tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line);
+        LuaError::external(format!("{}: line {line}: {message}", self.context))
+    }
+
+    fn unexpected(&self, expected: &str) -> LuaError {
+        match self.peek() {
+            Some(token) => self.error(format!("expected {expected}, got {token}")),
+            None => self.error(format!("expected {expected}, got the end of input")),
+        }
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, c: char) -> LuaResult<()> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{c}'")))
+        }
+    }
+
+    fn expect_ident(&mut self, expected: &str) -> LuaResult<String> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn expect_number(&mut self, expected: &str) -> LuaResult<u64> {
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                let n = *n;
+                self.pos += 1;
+                Ok(n)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn skip_qualifiers(&mut self) {
+        while self
+            .peek_ident()
+            .is_some_and(|word| QUALIFIERS.contains(&word))
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// Parse a function prototype, `typedef` or struct definition, up to and including its `;`.
+    fn parse_declaration(&mut self) -> LuaResult<()> {
+        if self.eat_punct(';') {
+            return Ok(());
+        }
+        if self.peek_ident() == Some("typedef") {
+            self.pos += 1;
+            return self.parse_typedef();
+        }
+
+        let base = self.parse_specifiers()?;
+        if self.eat_punct(';') {
+            // A struct definition or declaration on its own
+            return match base {
+                BaseType::Struct(_) => Ok(()),
+                _ => Err(self.error("expected a name before ';'")),
+            };
+        }
+
+        loop {
+            let pointers = self.parse_pointers();
+            let name = self.expect_ident("a function name")?;
+            if self.peek() != Some(&Token::Punct('(')) {
+                return Err(self.error(format!(
+                    "'{name}' is not a function, only functions, typedefs and structs can be declared"
+                )));
+            }
+            let args = self.parse_params(&name)?;
+            let ret = self.value_type(&base, pointers, || format!("return type of '{name}'"))?;
+            self.registry
+                .functions
+                .insert(name, FunctionDecl { ret, args });
+            if !self.eat_punct(',') {
+                break;
+            }
+        }
+        self.expect_punct(';')
+    }
+
+    fn parse_typedef(&mut self) -> LuaResult<()> {
+        let base = self.parse_specifiers()?;
+        loop {
+            let decl = self.parse_declarator()?;
+            let Some(name) = decl.name else {
+                return Err(self.unexpected("a name for the typedef"));
+            };
+            if decl.array.is_some() {
+                return Err(self.error(format!("array typedef '{name}' is not supported")));
+            }
+            let alias = match &base {
+                _ if decl.function_pointer => Alias::Scalar(CType::Pointer),
+                BaseType::Struct(key) if decl.pointers == 0 => Alias::Struct(key.clone()),
+                base => Alias::Scalar(
+                    self.value_type(base, decl.pointers, || format!("typedef '{name}'"))?,
+                ),
+            };
+            self.registry.aliases.insert(name, alias);
+            if !self.eat_punct(',') {
+                break;
+            }
+        }
+        self.expect_punct(';')
+    }
+
+    /// Parse a type name on its own, as given to `ffi.typeof`.
+    fn parse_type_name(&mut self) -> LuaResult<DeclaredType> {
+        let base = self.parse_specifiers()?;
+        let pointers = self.parse_pointers();
+        match base {
+            BaseType::Struct(key) if pointers == 0 => match self.registry.structs.get(&key) {
+                Some(Some(def)) => Ok(DeclaredType::Struct(def.clone())),
+                _ => Err(self.error(format!("struct '{key}' is declared but never defined"))),
+            },
+            base => Ok(DeclaredType::Scalar(self.value_type(
+                &base,
+                pointers,
+                || "type".to_owned(),
+            )?)),
+        }
+    }
+
+    /// Parse the type specifiers and qualifiers that start a declaration.
+    fn parse_specifiers(&mut self) -> LuaResult<BaseType> {
+        let mut words: Vec<String> = Vec::new();
+        let mut base = None;
+
+        while let Some(word) = self.peek_ident() {
+            if QUALIFIERS.contains(&word) {
+                self.pos += 1;
+            } else if PRIMITIVE_WORDS.contains(&word) && base.is_none() {
+                words.push(word.to_owned());
+                self.pos += 1;
+            } else if matches!(word, "struct" | "union") && base.is_none() && words.is_empty() {
+                let is_union = word == "union";
+                self.pos += 1;
+                base = Some(self.parse_struct_specifier(is_union)?);
+            } else if word == "enum" {
+                return Err(self.error(format!("{word}s are not supported")));
+            } else if base.is_some() || !words.is_empty() {
+                // The name being declared
+                break;
+            } else if let Some(ctype) = standard_typedef(word) {
+                base = Some(BaseType::Scalar(ctype));
+                self.pos += 1;
+            } else if let Some(alias) = self.registry.aliases.get(word) {
+                base = Some(match alias {
+                    Alias::Scalar(ctype) => BaseType::Scalar(*ctype),
+                    Alias::Struct(key) => BaseType::Struct(key.clone()),
+                });
+                self.pos += 1;
+            } else {
+                return Err(self.error(format!("unknown type '{word}'")));
+            }
+        }
+
+        match base {
+            Some(base) => Ok(base),
+            None if words.is_empty() => Err(self.unexpected("a type")),
+            None => primitive_type(&words)
+                .ok_or_else(|| self.error(format!("invalid type '{}'", words.join(" ")))),
+        }
+    }
+
+    /// Parse a `struct` or `union` specifier, after the keyword, returning its registry key.
+    ///
+    /// Unions share the tag namespace with structs, as they do in C.
+    fn parse_struct_specifier(&mut self, is_union: bool) -> LuaResult<BaseType> {
+        let kind = if is_union { "union" } else { "struct" };
+        let tag = match self.peek() {
+            Some(Token::Ident(_)) => Some(self.expect_ident(&format!("a {kind} name"))?),
+            _ => None,
+        };
+
+        if !self.eat_punct('{') {
+            let Some(tag) = tag else {
+                return Err(self.unexpected(&format!("a {kind} name or '{{'")));
+            };
+            // Mentioning a struct declares it, so that pointers to it can be used
+            self.registry.structs.entry(tag.clone()).or_insert(None);
+            return Ok(BaseType::Struct(tag));
+        }
+
+        let key = tag.clone().unwrap_or_else(|| {
+            self.anonymous_structs += 1;
+            format!("<anonymous {kind} {}>", self.anonymous_structs)
+        });
+        self.registry.structs.entry(key.clone()).or_insert(None);
+
+        let mut fields = Vec::new();
+        while !self.eat_punct('}') {
+            let base = self.parse_specifiers()?;
+            loop {
+                let decl = self.parse_declarator()?;
+                let bits = if self.eat_punct(':') {
+                    let width = self.expect_number("a bitfield width")?;
+                    Some(u32::try_from(width).map_err(|_| self.error("bitfield is too wide"))?)
+                } else {
+                    None
+                };
+                fields.push(self.field_spec(&key, &base, decl, bits)?);
+                if !self.eat_punct(',') {
+                    break;
+                }
+            }
+            self.expect_punct(';')?;
+        }
+
+        if fields.is_empty() {
+            return Err(self.error(format!("{kind} '{key}' has no fields")));
+        }
+        let def = if is_union {
+            StructDefinition::union_from_fields(tag, fields)
+        } else {
+            StructDefinition::from_fields(tag, fields)
+        }
+        .map_err(|e| self.error(format!("in {kind} '{key}': {e}")))?;
+        self.registry.structs.insert(key.clone(), Some(def));
+        Ok(BaseType::Struct(key))
+    }
+
+    fn parse_pointers(&mut self) -> usize {
+        let mut pointers = 0;
+        while self.eat_punct('*') {
+            pointers += 1;
+            self.skip_qualifiers();
+        }
+        pointers
+    }
+
+    /// Parse a declarator, whose name may be left out as in parameter lists.
+    fn parse_declarator(&mut self) -> LuaResult<Declarator> {
+        let mut decl = Declarator {
+            pointers: self.parse_pointers(),
+            ..Declarator::default()
+        };
+
+        if self.peek() == Some(&Token::Punct('(')) && self.peek_at(1) == Some(&Token::Punct('*')) {
+            self.pos += 2;
+            self.skip_qualifiers();
+            if let Some(Token::Ident(_)) = self.peek() {
+                decl.name = Some(self.expect_ident("a name")?);
+            }
+            self.expect_punct(')')?;
+            // Only the address is passed around, so the signature is not kept
+            self.parse_params("function pointer")?;
+            decl.function_pointer = true;
+            return Ok(decl);
+        }
+
+        if let Some(Token::Ident(_)) = self.peek() {
+            decl.name = Some(self.expect_ident("a name")?);
+        }
+
+        if self.eat_punct('[') {
+            let len = if self.eat_punct(']') {
+                None
+            } else {
+                let len = self.expect_number("an array length")?;
+                self.expect_punct(']')?;
+                Some(usize::try_from(len).map_err(|_| self.error("array is too long"))?)
+            };
+            if self.peek() == Some(&Token::Punct('[')) {
+                return Err(self.error("multidimensional arrays are not supported"));
+            }
+            decl.array = Some(len);
+        }
+
+        Ok(decl)
+    }
+
+    /// Parse a parenthesized parameter list, returning the type of each parameter.
+    fn parse_params(&mut self, function: &str) -> LuaResult<Vec<CType>> {
+        self.expect_punct('(')?;
+        let mut args = Vec::new();
+
+        if self.eat_punct(')') {
+            return Ok(args);
+        }
+        if self.peek_ident() == Some("void") && self.peek_at(1) == Some(&Token::Punct(')')) {
+            self.pos += 2;
+            return Ok(args);
+        }
+
+        loop {
+            if self.peek() == Some(&Token::Ellipsis) {
+                return Err(self.error(format!(
+                    "variadic function '{function}' is not supported, call it with lib:callVariadic"
+                )));
+            }
+            let base = self.parse_specifiers()?;
+            let decl = self.parse_declarator()?;
+            let index = args.len() + 1;
+            let ctype = if decl.function_pointer {
+                CType::Pointer
+            } else {
+                // Array parameters are passed as pointers to their first element
+                let pointers = decl.pointers + usize::from(decl.array.is_some());
+                self.value_type(&base, pointers, || {
+                    format!("parameter {index} of '{function}'")
+                })?
+            };
+            if ctype == CType::Void {
+                return Err(self.error(format!("parameter {index} of '{function}' cannot be void")));
+            }
+            args.push(ctype);
+
+            if !self.eat_punct(',') {
+                break;
+            }
+        }
+
+        self.expect_punct(')')?;
+        Ok(args)
+    }
+
+    /// Type of a value passed to or returned from a function, or named by a typedef.
+    fn value_type(
+        &self,
+        base: &BaseType,
+        pointers: usize,
+        what: impl FnOnce() -> String,
+    ) -> LuaResult<CType> {
+        Ok(match (base, pointers) {
+            (BaseType::Char, 0) => CType::I8,
+            (BaseType::Char, 1) => CType::CString,
+            (BaseType::Scalar(ctype), 0) => *ctype,
+            (BaseType::Struct(_), 0) => {
+                return Err(self.error(format!(
+                    "{} is a struct passed by value, which is not supported, use a pointer instead",
+                    what()
+                )));
+            }
+            _ => CType::Pointer,
+        })
+    }
+
+    /// Field of the struct being defined under `key`.
+    fn field_spec(
+        &self,
+        key: &str,
+        base: &BaseType,
+        decl: Declarator,
+        bits: Option<u32>,
+    ) -> LuaResult<FieldSpec> {
+        let Some(name) = decl.name else {
+            return Err(self.unexpected("a field name"));
+        };
+        let array_len = match decl.array {
+            Some(Some(len)) => Some(len),
+            Some(None) => {
+                return Err(self.error(format!("field '{name}' must have an array length")));
+            }
+            None => None,
+        };
+
+        let mut pointee = None;
+        let mut nested = None;
+        let ctype = match (base, decl.pointers) {
+            _ if decl.function_pointer => CType::Pointer,
+            (BaseType::Scalar(CType::Void), 0) => {
+                return Err(self.error(format!("field '{name}' cannot be void")));
+            }
+            // Structs are embedded by value, which needs their full definition
+            (BaseType::Struct(target), 0) => {
+                if target == key {
+                    return Err(self.error(format!("struct '{key}' cannot contain itself")));
+                }
+                let Some(Some(def)) = self.registry.structs.get(target) else {
+                    return Err(self.error(format!(
+                        "field '{name}' has incomplete type 'struct {target}', which must be defined first"
+                    )));
+                };
+                nested = Some(def.clone());
+                CType::Void
+            }
+            // Pointers to structs read back as StructPointers, unless in an array
+            (BaseType::Struct(target), 1) if array_len.is_none() => {
+                pointee = if target == key {
+                    Some(Pointee::SelfRef)
+                } else {
+                    self.registry
+                        .structs
+                        .get(target)
+                        .cloned()
+                        .flatten()
+                        .map(|def| Pointee::Struct(Box::new(def)))
+                };
+                CType::Pointer
+            }
+            (base, pointers) => self.value_type(base, pointers, || format!("field '{name}'"))?,
+        };
+
+        Ok(FieldSpec {
+            name,
+            ctype,
+            array_len,
+            endian: Endian::Native,
+            bits,
+            pointee,
+            nested,
+        })
+    }
+}
+
+/// Resolve a combination of primitive type words, as in `unsigned long long`.
+fn primitive_type(words: &[String]) -> Option<BaseType> {
+    let count = |word: &str| words.iter().filter(|w| *w == word).count();
+    let (signed, unsigned, longs) = (count("signed"), count("unsigned"), count("long"));
+    let (short, char, int) = (count("short"), count("char"), count("int"));
+    let (float, double, void) = (count("float"), count("double"), count("void"));
+    let bool = count("bool") + count("_Bool");
+
+    let sign = signed + unsigned;
+    if sign > 1 || short > 1 || char > 1 || int > 1 || longs > 2 || float + double + void + bool > 1
+    {
+        return None;
+    }
+
+    let ctype = if void + bool + float + double > 0 {
+        if sign + short + char + int + longs > 0 {
+            return None;
+        }
+        if void > 0 {
+            CType::Void
+        } else if bool > 0 {
+            CType::Bool
+        } else if float > 0 {
+            CType::F32
+        } else {
+            CType::F64
+        }
+    } else if char > 0 {
+        if short + int + longs > 0 {
+            return None;
+        }
+        match (signed, unsigned) {
+            (0, 0) => return Some(BaseType::Char),
+            (_, 0) => CType::I8,
+            _ => CType::U8,
+        }
+    } else if short > 0 {
+        if longs > 0 {
+            return None;
+        }
+        if unsigned > 0 { CType::U16 } else { CType::I16 }
+    } else if longs == 2 || (longs == 1 && size_of::<c_long>() == 8) {
+        if unsigned > 0 { CType::U64 } else { CType::I64 }
+    } else if unsigned > 0 {
+        CType::U32
+    } else {
+        CType::I32
+    };
+
+    Some(BaseType::Scalar(ctype))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> LuaResult<CdefRegistry> {
+        let mut parser = Parser::new("ffi.cdef", source, CdefRegistry::default())?;
+        while !parser.at_end() {
+            parser.parse_declaration()?;
+        }
+        Ok(parser.registry)
+    }
+
+    fn parse_err(source: &str) -> String {
+        parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn function_prototypes() {
+        let registry = parse(
+            "
+            size_t strlen(const char *s);
+            int abs(int), labs_like(long long n);
+            void *memcpy(void *restrict dest, const void *restrict src, size_t n);
+            unsigned char get_byte(void);
+            int qsort_r(void *base, int (*compar)(const void *, const void *), char buf[]);
+            ",
+        )
+        .unwrap();
+
+        let strlen = &registry.functions["strlen"];
+        assert_eq!(strlen.ret, CType::USize);
+        assert_eq!(strlen.args, [CType::CString]);
+
+        assert_eq!(registry.functions["abs"].args, [CType::I32]);
+        assert_eq!(registry.functions["labs_like"].args, [CType::I64]);
+        assert_eq!(registry.functions["memcpy"].ret, CType::Pointer);
+        assert_eq!(registry.functions["get_byte"].ret, CType::U8);
+        assert!(registry.functions["get_byte"].args.is_empty());
+        assert_eq!(
+            registry.functions["qsort_r"].args,
+            [CType::Pointer, CType::Pointer, CType::CString]
+        );
+    }
+
+    #[test]
+    fn structs_and_typedefs() {
+        let registry = parse(
+            "
+            /* A point */
+            typedef struct Point { int x, y; } Point;
+            typedef struct {
+                uint8_t tag;
+                double values[4]; // padded to 8
+                struct Point *origin;
+                unsigned int flags : 3;
+                struct Node *next;
+            } Shape;
+            typedef unsigned long long u64_t;
+            typedef struct Opaque Opaque;
+            ",
+        )
+        .unwrap();
+
+        let Some(Alias::Struct(key)) = registry.aliases.get("Shape") else {
+            panic!("Expected Shape to be a struct");
+        };
+        let shape = registry.structs[key].as_ref().unwrap();
+        assert_eq!(shape.get_field("values").unwrap().offset, 8);
+        assert_eq!(shape.get_field("values").unwrap().array_len, Some(4));
+        assert!(matches!(
+            shape.get_field("origin").unwrap().pointee,
+            Some(Pointee::Struct(_))
+        ));
+        assert!(shape.get_field("flags").unwrap().bitfield.is_some());
+        assert!(shape.get_field("next").unwrap().pointee.is_none());
+
+        let point = registry.structs["Point"].as_ref().unwrap();
+        assert_eq!(point.size, 8);
+        assert!(matches!(
+            registry.aliases["u64_t"],
+            Alias::Scalar(CType::U64)
+        ));
+        assert!(registry.structs["Opaque"].is_none());
+    }
+
+    #[test]
+    fn nested_structs() {
+        let registry = parse(
+            "
+            typedef struct { float x, y; } Vec2;
+            struct Rect { char kind; Vec2 min, max; Vec2 corners[4]; };
+            ",
+        )
+        .unwrap();
+        let rect = registry.structs["Rect"].as_ref().unwrap();
+        assert_eq!(rect.get_field("min").unwrap().offset, 4);
+        assert_eq!(rect.get_field("max").unwrap().offset, 12);
+        assert_eq!(rect.get_field("corners").unwrap().size, 32);
+        assert_eq!(rect.size, 52);
+        assert_eq!(rect.resolve_path("max.y").unwrap().0, 16);
+    }
+
+    #[test]
+    fn unions() {
+        let registry = parse(
+            "
+            union Value { char tag; double number; int pair[3]; };
+            struct Tagged { char kind; union Value value; };
+            ",
+        )
+        .unwrap();
+        let value = registry.structs["Value"].as_ref().unwrap();
+        assert!(value.is_union);
+        assert!(value.fields.iter().all(|field| field.offset == 0));
+        assert_eq!(value.size, 16);
+        assert_eq!(value.alignment, 8);
+        let tagged = registry.structs["Tagged"].as_ref().unwrap();
+        assert_eq!(tagged.get_field("value").unwrap().offset, 8);
+        assert_eq!(tagged.resolve_path("value.pair").unwrap().0, 8);
+        assert_eq!(tagged.size, 24);
+    }
+
+    #[test]
+    fn self_referencing_structs() {
+        let registry = parse("struct Node { int value; struct Node *next; };").unwrap();
+        let node = registry.structs["Node"].as_ref().unwrap();
+        assert!(matches!(
+            node.get_field("next").unwrap().pointee,
+            Some(Pointee::SelfRef)
+        ));
+    }
+
+    #[test]
+    fn primitive_combinations() {
+        let ctype = |words: &str| {
+            let words: Vec<String> = words.split(' ').map(str::to_owned).collect();
+            match primitive_type(&words) {
+                Some(BaseType::Scalar(ctype)) => Some(ctype),
+                Some(BaseType::Char) => Some(CType::I8),
+                _ => None,
+            }
+        };
+        assert_eq!(ctype("unsigned"), Some(CType::U32));
+        assert_eq!(ctype("signed char"), Some(CType::I8));
+        assert_eq!(ctype("unsigned short int"), Some(CType::U16));
+        assert_eq!(ctype("long long int"), Some(CType::I64));
+        assert_eq!(ctype("unsigned long long"), Some(CType::U64));
+        assert_eq!(ctype("unsigned float"), None);
+        assert_eq!(ctype("long long long"), None);
+        assert_eq!(ctype("long double"), None);
+        assert_eq!(ctype("short char"), None);
+    }
+
+    #[test]
+    fn unsupported_constructs() {
+        assert!(parse_err("int printf(const char *fmt, ...);").contains("variadic"));
+        assert!(parse_err("enum E { A, B };").contains("enums are not supported"));
+        assert!(parse_err("#include <stdio.h>").contains("preprocessor"));
+        assert!(parse_err("int counter;").contains("not a function"));
+        assert!(parse_err("struct A;\nstruct B { struct A a; };").contains("line 2"));
+        assert!(parse_err("struct C { struct C c; };").contains("cannot contain itself"));
+        assert!(parse_err("Widget make(void);").contains("unknown type 'Widget'"));
+        assert!(parse_err("int f(int").contains("end of input"));
+        assert!(parse_err("struct M { int m[2][2]; };").contains("multidimensional"));
+    }
+}
@@ -0,0 +1,274 @@
+//! Cursors for building and parsing binary data sequentially.
+//!
+//! Values are encoded with the host byte order, the same as `Buffer:write`,
+//! and may sit at any offset since they are copied byte by byte.
+
+use mlua::prelude::*;
+
+use crate::pointer;
+use crate::types::{Buffer, CType, DEFAULT_BUFFER_ALIGN};
+
+/// Default initial capacity for writers
+const DEFAULT_WRITER_CAPACITY: usize = 64;
+
+/// Numeric types with a dedicated cursor method, such as `writeI32`
+const NUMERIC_METHODS: [(&str, CType); 10] = [
+    ("I8", CType::I8),
+    ("U8", CType::U8),
+    ("I16", CType::I16),
+    ("U16", CType::U16),
+    ("I32", CType::I32),
+    ("U32", CType::U32),
+    ("I64", CType::I64),
+    ("U64", CType::U64),
+    ("F32", CType::F32),
+    ("F64", CType::F64),
+];
+
+/// Ensure a type can be stored inline, pointers and strings are not
+fn check_inline_type(ctype: CType) -> LuaResult<()> {
+    match ctype {
+        CType::Void | CType::Pointer | CType::CString => Err(LuaError::external(format!(
+            "Cannot use type {:?} with a cursor, expected a number or bool type",
+            ctype
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Encode a Lua value as the native bytes of `ctype`
+fn encode(lua: &Lua, ctype: CType, value: LuaValue) -> LuaResult<Vec<u8>> {
+    check_inline_type(ctype)?;
+    // Go through an aligned scratch value, cursor positions may be unaligned
+    let mut scratch = 0u64;
+    pointer::write_value_at(lua, (&raw mut scratch).cast(), ctype, value)?;
+    let bytes = scratch.to_ne_bytes();
+    Ok(bytes[..ctype.size()].to_vec())
+}
+
+/// Decode the native bytes of `ctype` into a Lua value
+fn decode(lua: &Lua, ctype: CType, bytes: &[u8]) -> LuaResult<LuaValue> {
+    check_inline_type(ctype)?;
+    let mut scratch = [0u8; 8];
+    scratch[..bytes.len()].copy_from_slice(bytes);
+    let mut scratch = u64::from_ne_bytes(scratch);
+    pointer::read_value_at(lua, (&raw mut scratch).cast(), ctype)
+}
+
+/// A cursor over a growable byte buffer
+pub struct BufferWriter {
+    data: Vec<u8>,
+}
+
+impl BufferWriter {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Append a value of the given type
+    pub fn write(&mut self, lua: &Lua, ctype: CType, value: LuaValue) -> LuaResult<()> {
+        let bytes = encode(lua, ctype, value)?;
+        self.data.extend_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Append raw bytes
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Append a null-terminated C string
+    pub fn write_cstring(&mut self, bytes: &[u8]) -> LuaResult<()> {
+        if bytes.contains(&0) {
+            return Err(LuaError::external(
+                "String contains an interior null byte, use writeBytes instead",
+            ));
+        }
+        self.data.extend_from_slice(bytes);
+        self.data.push(0);
+        Ok(())
+    }
+
+    /// Number of bytes written so far
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Copy the written bytes into a new buffer
+    pub fn to_buffer(&self) -> LuaResult<Buffer> {
+        let mut buffer = Buffer::allocate(self.data.len(), DEFAULT_BUFFER_ALIGN, false)?;
+        buffer.write_bytes(0, &self.data)?;
+        Ok(buffer)
+    }
+}
+
+impl LuaUserData for BufferWriter {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("size", |_, this| Ok(this.data.len()));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // write(type, value) - Append any number or bool type
+        methods.add_method_mut("write", |lua, this, (ctype, value): (CType, LuaValue)| {
+            this.write(lua, ctype, value)
+        });
+
+        // writeI8(value), writeU8(value), ... writeF64(value)
+        for (suffix, ctype) in NUMERIC_METHODS {
+            methods.add_method_mut(
+                format!("write{suffix}"),
+                move |lua, this, value: LuaValue| this.write(lua, ctype, value),
+            );
+        }
+
+        methods.add_method_mut("writeBytes", |_, this, bytes: LuaString| {
+            this.write_bytes(&bytes.as_bytes());
+            Ok(())
+        });
+
+        methods.add_method_mut("writeString", |_, this, s: LuaString| {
+            this.write_cstring(&s.as_bytes())
+        });
+
+        methods.add_method("position", |_, this, ()| Ok(this.position()));
+
+        // finish() -> Buffer
+        methods.add_method("finish", |_, this, ()| this.to_buffer());
+
+        // bytes() -> string
+        methods.add_method("bytes", |lua, this, ()| lua.create_string(&this.data));
+
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(format!("BufferWriter(position={})", this.data.len()))
+        });
+    }
+}
+
+/// A bounds-checked cursor over an existing buffer
+pub struct BufferReader {
+    buffer: LuaAnyUserData,
+    position: usize,
+}
+
+impl BufferReader {
+    pub fn new(buffer: LuaAnyUserData) -> LuaResult<Self> {
+        if !buffer.is::<Buffer>() {
+            return Err(LuaError::external("Expected Buffer"));
+        }
+        Ok(Self {
+            buffer,
+            position: 0,
+        })
+    }
+
+    fn size(&self) -> LuaResult<usize> {
+        Ok(self.buffer.borrow::<Buffer>()?.size())
+    }
+
+    /// Bytes left between the position and the end of the buffer
+    pub fn remaining(&self) -> LuaResult<usize> {
+        Ok(self.size()?.saturating_sub(self.position))
+    }
+
+    /// Copy the next `len` bytes and advance past them
+    pub fn take(&mut self, len: usize) -> LuaResult<Vec<u8>> {
+        let remaining = self.remaining()?;
+        if len > remaining {
+            return Err(LuaError::external(format!(
+                "Cannot read {} bytes at position {}, only {} remaining",
+                len, self.position, remaining
+            )));
+        }
+        let bytes = self
+            .buffer
+            .borrow::<Buffer>()?
+            .read_bytes(self.position, len)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Read a value of the given type and advance past it
+    pub fn read(&mut self, lua: &Lua, ctype: CType) -> LuaResult<LuaValue> {
+        check_inline_type(ctype)?;
+        let bytes = self.take(ctype.size())?;
+        decode(lua, ctype, &bytes)
+    }
+
+    /// Read a null-terminated string and advance past its terminator
+    pub fn read_cstring(&mut self) -> LuaResult<Vec<u8>> {
+        let rest = self
+            .buffer
+            .borrow::<Buffer>()?
+            .read_bytes(self.position, self.remaining()?)?;
+        let Some(len) = rest.iter().position(|&b| b == 0) else {
+            return Err(LuaError::external(format!(
+                "No null terminator found after position {}",
+                self.position
+            )));
+        };
+        self.position += len + 1;
+        Ok(rest[..len].to_vec())
+    }
+
+    /// Move to an absolute position, which may be the end of the buffer
+    pub fn seek(&mut self, position: usize) -> LuaResult<()> {
+        let size = self.size()?;
+        if position > size {
+            return Err(LuaError::external(format!(
+                "Cannot seek to position {} in a buffer of {} bytes",
+                position, size
+            )));
+        }
+        self.position = position;
+        Ok(())
+    }
+}
+
+impl LuaUserData for BufferReader {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("size", |_, this| this.size());
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // read(type) -> value - Read any number or bool type
+        methods.add_method_mut("read", |lua, this, ctype: CType| this.read(lua, ctype));
+
+        // readI8(), readU8(), ... readF64()
+        for (suffix, ctype) in NUMERIC_METHODS {
+            methods.add_method_mut(format!("read{suffix}"), move |lua, this, ()| {
+                this.read(lua, ctype)
+            });
+        }
+
+        methods.add_method_mut("readBytes", |lua, this, len: usize| {
+            let bytes = this.take(len)?;
+            lua.create_string(&bytes)
+        });
+
+        methods.add_method_mut("readCString", |lua, this, ()| {
+            let bytes = this.read_cstring()?;
+            lua.create_string(&bytes)
+        });
+
+        methods.add_method_mut("seek", |_, this, position: usize| this.seek(position));
+        methods.add_method("position", |_, this, ()| Ok(this.position));
+        methods.add_method("remaining", |_, this, ()| this.remaining());
+
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(format!(
+                "BufferReader(position={}, size={})",
+                this.position,
+                this.size()?
+            ))
+        });
+    }
+}
+
+/// Create a writer with an optional initial capacity
+pub fn create_writer(capacity: Option<usize>) -> BufferWriter {
+    BufferWriter::new(capacity.unwrap_or(DEFAULT_WRITER_CAPACITY))
+}
@@ -0,0 +1,110 @@
+//! Typed errors for library loading and symbol lookup.
+//!
+//! These are raised to Lua as `{ kind, name, detail }` tables so that
+//! scripts can tell a missing library apart from a missing function.
+
+use std::fmt;
+
+use mlua::prelude::*;
+
+/// What went wrong while resolving native code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiErrorKind {
+    LibraryNotFound,
+    SymbolNotFound,
+}
+
+impl FfiErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LibraryNotFound => "library_not_found",
+            Self::SymbolNotFound => "symbol_not_found",
+        }
+    }
+}
+
+/// A library load or symbol lookup failure.
+#[derive(Debug, Clone)]
+pub struct FfiError {
+    pub kind: FfiErrorKind,
+    /// Library path or symbol name that could not be resolved
+    pub name: String,
+    /// Underlying loader message
+    pub detail: String,
+}
+
+impl FfiError {
+    pub fn library_not_found(path: &str, detail: impl fmt::Display) -> Self {
+        Self {
+            kind: FfiErrorKind::LibraryNotFound,
+            name: path.to_owned(),
+            detail: detail.to_string(),
+        }
+    }
+
+    pub fn symbol_not_found(name: &str, detail: impl fmt::Display) -> Self {
+        Self {
+            kind: FfiErrorKind::SymbolNotFound,
+            name: name.to_owned(),
+            detail: detail.to_string(),
+        }
+    }
+
+    /// Convert into the `{ kind, name, detail }` table raised to Lua.
+    pub fn into_lua_table(self, lua: &Lua) -> LuaResult<LuaTable> {
+        let message = self.to_string();
+        let table = lua.create_table()?;
+        table.set("kind", self.kind.as_str())?;
+        table.set("name", self.name)?;
+        table.set("detail", self.detail)?;
+
+        let meta = lua.create_table()?;
+        meta.set(
+            "__tostring",
+            lua.create_function(move |_, _: LuaValue| Ok(message.clone()))?,
+        )?;
+        table.set_metatable(Some(meta))?;
+
+        Ok(table)
+    }
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FfiErrorKind::LibraryNotFound => {
+                write!(f, "Failed to load library '{}': {}", self.name, self.detail)
+            }
+            FfiErrorKind::SymbolNotFound => {
+                write!(f, "Symbol '{}' not found: {}", self.name, self.detail)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FfiError {}
+
+impl From<FfiError> for LuaError {
+    fn from(err: FfiError) -> Self {
+        LuaError::external(err)
+    }
+}
+
+/// Find an [`FfiError`] inside a (possibly wrapped) Lua error.
+fn find_ffi_error(err: &LuaError) -> Option<&FfiError> {
+    match err {
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => {
+            find_ffi_error(cause)
+        }
+        _ => err.downcast_ref::<FfiError>(),
+    }
+}
+
+/// Convert an [`FfiError`] raised by a wrapped function into its table.
+///
+/// Passed to [`lune_utils::structured_errors::with_structured_errors`].
+pub fn structured_error(lua: &Lua, err: &LuaError) -> LuaResult<Option<LuaTable>> {
+    find_ffi_error(err)
+        .map(|ffi_err| ffi_err.clone().into_lua_table(lua))
+        .transpose()
+}
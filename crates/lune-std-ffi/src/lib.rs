@@ -17,6 +17,7 @@
 #![allow(clippy::pedantic)]
 #![allow(clippy::nursery)]
 
+use lune_utils::structured_errors::with_structured_errors;
 use mlua::prelude::*;
 use std::ffi::c_void;
 use std::ptr;
@@ -24,20 +25,33 @@ use std::ptr;
 mod arena;
 mod callback;
 mod caller;
+mod cdef;
+mod cursor;
+mod error;
 mod library;
 mod pointer;
 mod scratch_arena;
 mod smart_library;
+mod struct_class;
 mod struct_mapper;
+mod symbolicate;
 mod types;
 
 pub use arena::Arena;
-pub use callback::FfiCallback;
-pub use library::{BoundFunction, NativeLibrary};
+pub use callback::{CallbackArg, FfiCallback};
+pub use cursor::{BufferReader, BufferWriter};
+pub use error::{FfiError, FfiErrorKind};
+pub use library::{BoundFunction, LoadFlags, LoadOptions, NativeLibrary};
 pub use pointer::{RawPointer, TypedPointer};
 pub use smart_library::{SmartBoundFunction, SmartLibrary};
-pub use struct_mapper::{StructDefinition, StructView};
-pub use types::{Buffer, CType};
+pub use struct_class::{StructClass, StructInstance};
+pub use struct_mapper::{
+    Pointee, StructDefinition, StructPointer, StructView, UnionDefinition, UnionView,
+};
+pub use types::{Buffer, BufferFromPtrOptions, BufferOptions, CType};
+
+/// Upper bound on entries scanned by `ffi.stringArray` before giving up.
+const MAX_STRING_ARRAY_LEN: usize = 65_536;
 
 /// Returns the type definitions for the FFI module.
 #[must_use]
@@ -54,56 +68,152 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     // Library Loading
     // ========================================================================
 
-    // ffi.load(path: string, interface?: table) -> NativeLibrary | SmartLibrary
+    // ffi.load(path: string, interface?: table, options?: table) -> NativeLibrary | SmartLibrary
     // When interface is provided, returns SmartLibrary with pre-bound functions
+    // options.flags = { global?, lazy? } maps to dlopen flags (ignored on Windows)
+    // Load and symbol failures are raised as { kind, name, detail } tables
+    let ffi_load = lua.create_function(|lua, args: LuaMultiValue| {
+        let mut args_iter = args.into_iter();
+
+        let path: String = match args_iter.next() {
+            Some(v) => FromLua::from_lua(v, lua)?,
+            None => return Err(LuaError::external("Missing path argument")),
+        };
+
+        let interface: Option<LuaTable> = match args_iter.next() {
+            Some(LuaValue::Table(t)) => Some(t),
+            Some(LuaValue::Nil) | None => None,
+            Some(_) => return Err(LuaError::external("Interface must be a table")),
+        };
+
+        let options = LoadOptions::from_lua(args_iter.next().unwrap_or(LuaValue::Nil), lua)?;
+
+        let native_lib = NativeLibrary::open_with_flags(&path, options.flags)?;
+
+        if let Some(iface) = interface {
+            // Create SmartLibrary with pre-bound functions
+            let smart = SmartLibrary::from_interface(native_lib.library_arc(), path, iface)?;
+            smart.into_lua(lua)
+        } else {
+            // Legacy mode - return NativeLibrary
+            native_lib.into_lua(lua)
+        }
+    })?;
     exports.set(
         "load",
-        lua.create_function(|lua, args: LuaMultiValue| {
-            let mut args_iter = args.into_iter();
-
-            let path: String = match args_iter.next() {
-                Some(v) => FromLua::from_lua(v, lua)?,
-                None => return Err(LuaError::external("Missing path argument")),
-            };
-
-            let interface: Option<LuaTable> = match args_iter.next() {
-                Some(LuaValue::Table(t)) => Some(t),
-                Some(LuaValue::Nil) | None => None,
-                Some(_) => return Err(LuaError::external("Interface must be a table")),
-            };
-
-            let native_lib = NativeLibrary::open(&path)?;
-
-            if let Some(iface) = interface {
-                // Create SmartLibrary with pre-bound functions
-                let smart = SmartLibrary::from_interface(native_lib.library_arc(), path, iface)?;
-                smart.into_lua(lua)
-            } else {
-                // Legacy mode - return NativeLibrary
-                native_lib.into_lua(lua)
-            }
-        })?,
+        with_structured_errors(&lua, "ffi.load", ffi_load, error::structured_error)?,
     )?;
 
-    // ffi.open(path: string) -> NativeLibrary (Legacy/Deprecated)
+    // ffi.open(path: string, options?: table) -> NativeLibrary (Legacy/Deprecated)
+    let ffi_open = lua.create_function(|_, (path, options): (String, LoadOptions)| {
+        NativeLibrary::open_with_flags(&path, options.flags)
+    })?;
     exports.set(
         "open",
-        lua.create_function(|_, path: String| NativeLibrary::open(&path))?,
+        with_structured_errors(&lua, "ffi.open", ffi_open, error::structured_error)?,
+    )?;
+
+    // ffi.callOnce(path, symbol, retType, argTypes, ...args) -> result
+    // Loads the library just for this call, keeping it around briefly for repeated calls
+    let ffi_call_once = lua.create_function(
+        |lua,
+         (path, name, ret_type, arg_types, args): (
+            String,
+            String,
+            CType,
+            LuaTable,
+            LuaMultiValue,
+        )| {
+            let arg_types: Vec<CType> = arg_types
+                .sequence_values::<CType>()
+                .collect::<LuaResult<Vec<_>>>()?;
+            NativeLibrary::call_once(lua, &path, &name, ret_type, &arg_types, args.into_vec())
+        },
+    )?;
+    exports.set(
+        "callOnce",
+        with_structured_errors(&lua, "ffi.callOnce", ffi_call_once, error::structured_error)?,
     )?;
 
     // ========================================================================
     // Memory Allocation
     // ========================================================================
 
-    // ffi.buffer(size: number) -> Buffer
+    // ffi.buffer(size: number, options?: number | { align?, zero? }) -> Buffer
     exports.set(
         "buffer",
-        lua.create_function(|_, size: usize| Ok(Buffer::new(size)))?,
+        lua.create_function(|_, (size, options): (usize, BufferOptions)| {
+            Buffer::allocate(size, options.align, options.zero)
+        })?,
+    )?;
+
+    // ffi.wrap(ptr, size: number) -> Buffer
+    // Non-owning, bounds-checked view over existing memory
+    exports.set(
+        "wrap",
+        lua.create_function(|_, (ptr, size): (LuaValue, usize)| {
+            let raw_ptr = match ptr {
+                LuaValue::LightUserData(lud) => lud.0,
+                LuaValue::UserData(ud) => get_raw_ptr(&ud)?,
+                _ => return Err(LuaError::external("Expected pointer")),
+            };
+            if raw_ptr.is_null() {
+                return Err(LuaError::external("Cannot wrap a null pointer"));
+            }
+            Ok(Buffer::from_ptr(raw_ptr.cast(), size))
+        })?,
+    )?;
+
+    // ffi.bufferFromPtr(ptr, size: number, options?: { copy? }) -> Buffer
+    // Owned copy of existing memory by default, or a non-owning view with copy = false
+    exports.set(
+        "bufferFromPtr",
+        lua.create_function(
+            |_, (ptr, size, options): (LuaValue, usize, BufferFromPtrOptions)| {
+                let (raw_ptr, known_size) = match ptr {
+                    LuaValue::LightUserData(lud) => (lud.0, None),
+                    LuaValue::UserData(ud) => (get_raw_ptr(&ud)?, get_known_size(&ud)),
+                    _ => return Err(LuaError::external("Expected pointer")),
+                };
+                if raw_ptr.is_null() {
+                    return Err(LuaError::external(
+                        "Cannot create a buffer from a null pointer",
+                    ));
+                }
+                if let Some(known) = known_size
+                    && size > known
+                {
+                    return Err(LuaError::external(format!(
+                        "Buffer of {} bytes exceeds the {} bytes behind the pointer",
+                        size, known
+                    )));
+                }
+                if options.copy {
+                    unsafe { Buffer::copy_from_ptr(raw_ptr.cast_const().cast(), size) }
+                } else {
+                    Ok(Buffer::from_ptr(raw_ptr.cast(), size))
+                }
+            },
+        )?,
     )?;
 
     // ffi.arena() -> Arena
     exports.set("arena", lua.create_function(|_, ()| Ok(Arena::new()))?)?;
 
+    // ffi.writer(initialCapacity?: number) -> BufferWriter
+    // Growable cursor for building binary messages
+    exports.set(
+        "writer",
+        lua.create_function(|_, capacity: Option<usize>| Ok(cursor::create_writer(capacity)))?,
+    )?;
+
+    // ffi.reader(buffer: Buffer) -> BufferReader
+    // Bounds-checked cursor for parsing binary data
+    exports.set(
+        "reader",
+        lua.create_function(|_, buffer: LuaAnyUserData| BufferReader::new(buffer))?,
+    )?;
+
     // ========================================================================
     // Zero-Copy Memory Access (Core Primitives)
     // ========================================================================
@@ -112,26 +222,33 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     // Direct memory read without buffer wrapper
     exports.set(
         "read",
-        lua.create_function(
-            |lua, (ptr, offset, ctype): (LuaAnyUserData, usize, CType)| {
-                // Try RawPointer first
-                if let Ok(raw) = ptr.borrow::<RawPointer>() {
-                    return raw.read(lua, offset, ctype);
-                }
-                // Try TypedPointer
-                if let Ok(typed) = ptr.borrow::<TypedPointer>() {
-                    let byte_ptr = unsafe { typed.addr.cast::<u8>().add(offset) };
+        lua.create_function(|lua, (ptr, offset, ctype): (LuaValue, usize, CType)| {
+            let ptr = match ptr {
+                // Raw addresses, such as pointer arguments passed to callbacks
+                LuaValue::LightUserData(lud) => {
+                    let byte_ptr = non_null_light_ptr(lud)?.wrapping_add(offset);
                     return pointer::read_value_at(lua, byte_ptr, ctype);
                 }
-                // Try Buffer
-                if let Ok(buf) = ptr.borrow::<Buffer>() {
-                    return buf.read(lua, offset, ctype);
-                }
-                Err(LuaError::external(
-                    "Expected RawPointer, TypedPointer, or Buffer",
-                ))
-            },
-        )?,
+                LuaValue::UserData(ud) => ud,
+                _ => return Err(LuaError::external("Expected pointer")),
+            };
+            // Try RawPointer first
+            if let Ok(raw) = ptr.borrow::<RawPointer>() {
+                return raw.read(lua, offset, ctype);
+            }
+            // Try TypedPointer
+            if let Ok(typed) = ptr.borrow::<TypedPointer>() {
+                let byte_ptr = unsafe { typed.addr.cast::<u8>().add(offset) };
+                return pointer::read_value_at(lua, byte_ptr, ctype);
+            }
+            // Try Buffer
+            if let Ok(buf) = ptr.borrow::<Buffer>() {
+                return buf.read(lua, offset, ctype);
+            }
+            Err(LuaError::external(
+                "Expected RawPointer, TypedPointer, Buffer, or light userdata",
+            ))
+        })?,
     )?;
 
     // ffi.write(ptr, offset, type, val) -> void
@@ -139,7 +256,16 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     exports.set(
         "write",
         lua.create_function(
-            |lua, (ptr, offset, ctype, value): (LuaAnyUserData, usize, CType, LuaValue)| {
+            |lua, (ptr, offset, ctype, value): (LuaValue, usize, CType, LuaValue)| {
+                let ptr = match ptr {
+                    // Raw addresses, such as out-parameters passed to callbacks
+                    LuaValue::LightUserData(lud) => {
+                        let byte_ptr = non_null_light_ptr(lud)?.wrapping_add(offset);
+                        return pointer::write_value_at(lua, byte_ptr, ctype, value);
+                    }
+                    LuaValue::UserData(ud) => ud,
+                    _ => return Err(LuaError::external("Expected pointer")),
+                };
                 // Try RawPointer first
                 if let Ok(raw) = ptr.borrow::<RawPointer>() {
                     return raw.write(lua, offset, ctype, value);
@@ -154,7 +280,7 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
                     return buf.write(lua, offset, ctype, value);
                 }
                 Err(LuaError::external(
-                    "Expected RawPointer, TypedPointer, or Buffer",
+                    "Expected RawPointer, TypedPointer, Buffer, or light userdata",
                 ))
             },
         )?,
@@ -210,11 +336,38 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         lua.create_function(|_, addr: usize| Ok(RawPointer::new(addr as *mut c_void)))?,
     )?;
 
-    // ffi.cast(ptr, type) -> TypedPointer or StructView
+    // ffi.cast(ptr, type) -> TypedPointer, StructView or UnionView
     // Cast raw pointer to typed pointer for array indexing
     exports.set(
         "cast",
         lua.create_function(|lua, (ptr, type_val): (LuaValue, LuaValue)| {
+            // Luau buffers are viewed in place, and kept alive by the view
+            if let LuaValue::Buffer(buf) = ptr {
+                let raw = luau_buffer_ptr(lua, &buf)?;
+                let view = match type_val {
+                    LuaValue::String(s) => {
+                        let type_str = s.to_str()?;
+                        let ctype = CType::from_str(&type_str).ok_or_else(|| {
+                            LuaError::external(format!("Unknown type: {}", type_str))
+                        })?;
+                        lua.create_userdata(TypedPointer::new(&raw, ctype))?
+                    }
+                    LuaValue::UserData(ud) => {
+                        let def = StructDefinition::from_userdata(&ud).map_err(|_| {
+                            LuaError::external("Expected type string or StructDefinition")
+                        })?;
+                        view_luau_buffer(lua, &raw, &def)?
+                    }
+                    _ => {
+                        return Err(LuaError::external(
+                            "Expected type string or StructDefinition",
+                        ));
+                    }
+                };
+                view.set_user_value(buf)?;
+                return Ok(LuaValue::UserData(view));
+            }
+
             // Get the raw pointer
             let raw = match ptr {
                 LuaValue::UserData(ud) => {
@@ -245,13 +398,12 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
                     TypedPointer::new(&raw, ctype).into_lua(lua)
                 }
                 LuaValue::UserData(ud) => {
-                    if let Ok(def) = ud.borrow::<StructDefinition>() {
-                        StructView::new(&raw, def.clone()).into_lua(lua)
-                    } else {
-                        Err(LuaError::external(
-                            "Expected type string or StructDefinition",
-                        ))
-                    }
+                    let def = StructDefinition::from_userdata(&ud).map_err(|_| {
+                        LuaError::external("Expected type string or StructDefinition")
+                    })?;
+                    StructView::new(&raw, def)
+                        .into_lua_view(lua)
+                        .map(LuaValue::UserData)
                 }
                 _ => Err(LuaError::external(
                     "Expected type string or StructDefinition",
@@ -260,6 +412,25 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         })?,
     )?;
 
+    // ffi.symbolicate(ptr) -> string?
+    // Best-effort "module+0xoffset (symbol+0xoffset)" for debugging
+    exports.set(
+        "symbolicate",
+        lua.create_function(|_, ptr: LuaValue| {
+            let addr = match ptr {
+                LuaValue::LightUserData(lud) => lud.0 as usize,
+                LuaValue::UserData(ud) => match ud.borrow::<FfiCallback>() {
+                    Ok(callback) => callback.as_ptr() as usize,
+                    Err(_) => get_raw_ptr(&ud)? as usize,
+                },
+                LuaValue::Integer(addr) => addr as usize,
+                LuaValue::Number(addr) => addr as usize,
+                _ => return Err(LuaError::external("Expected pointer or address")),
+            };
+            Ok(symbolicate::symbolicate(addr).map(|info| info.to_string()))
+        })?,
+    )?;
+
     // ========================================================================
     // Struct System
     // ========================================================================
@@ -270,10 +441,82 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         lua.create_function(|lua, schema: LuaTable| StructDefinition::from_schema(lua, schema))?,
     )?;
 
-    // ffi.view(ptr, structDef) -> StructView
+    // ffi.union(schema) -> UnionDefinition
+    // Same schema as ffi.struct, but every field starts at offset 0
+    exports.set(
+        "union",
+        lua.create_function(|lua, schema: LuaTable| {
+            StructDefinition::union_from_schema(lua, schema).map(UnionDefinition)
+        })?,
+    )?;
+
+    // ffi.ptrTo(structDef | "self") -> pointer field type for struct schemas
+    // "self" points at the struct being defined, for linked lists and trees
+    exports.set(
+        "ptrTo",
+        lua.create_function(|_, target: LuaValue| match target {
+            LuaValue::UserData(ud) => {
+                let def = ud.borrow::<StructDefinition>()?;
+                Ok(Pointee::Struct(Box::new(def.clone())))
+            }
+            LuaValue::String(s) if s.to_str()? == "self" => Ok(Pointee::SelfRef),
+            _ => Err(LuaError::external(
+                "Expected a StructDefinition or \"self\"",
+            )),
+        })?,
+    )?;
+
+    // ffi.structClass(schema) -> StructClass
+    // Same schema as ffi.struct, plus a `methods` table; calling the class allocates an instance
+    exports.set(
+        "structClass",
+        lua.create_function(|lua, schema: LuaTable| StructClass::from_schema(lua, schema))?,
+    )?;
+
+    // ffi.offsetOf(structDef, field: string) -> number
+    // Accepts dotted paths into nested structs, like "a.b.c"
+    exports.set(
+        "offsetOf",
+        lua.create_function(|_, (def, path): (LuaAnyUserData, String)| {
+            let def = StructDefinition::from_userdata(&def)?;
+            def.resolve_path(&path).map(|(offset, _)| offset)
+        })?,
+    )?;
+
+    // ffi.sizeOf(structDef, field: string) -> number
+    exports.set(
+        "sizeOf",
+        lua.create_function(|_, (def, path): (LuaAnyUserData, String)| {
+            let def = StructDefinition::from_userdata(&def)?;
+            def.resolve_path(&path).map(|(_, field)| field.size)
+        })?,
+    )?;
+
+    // ffi.alignOf(structDef, field: string) -> number
+    exports.set(
+        "alignOf",
+        lua.create_function(|_, (def, path): (LuaAnyUserData, String)| {
+            let def = StructDefinition::from_userdata(&def)?;
+            def.resolve_path(&path).map(|(_, field)| field.alignment())
+        })?,
+    )?;
+
+    // ffi.view(ptr, structDef | unionDef) -> StructView or UnionView
     exports.set(
         "view",
-        lua.create_function(|_, (ptr, def): (LuaAnyUserData, LuaAnyUserData)| {
+        lua.create_function(|lua, (ptr, def): (LuaValue, LuaAnyUserData)| {
+            let ptr = match ptr {
+                // Luau buffers are viewed in place, and kept alive by the view
+                LuaValue::Buffer(buf) => {
+                    let raw = luau_buffer_ptr(lua, &buf)?;
+                    let def = StructDefinition::from_userdata(&def)?;
+                    let view = view_luau_buffer(lua, &raw, &def)?;
+                    view.set_user_value(buf)?;
+                    return Ok(view);
+                }
+                LuaValue::UserData(ud) => ud,
+                _ => return Err(LuaError::external("Expected pointer")),
+            };
             let raw = if let Ok(r) = ptr.borrow::<RawPointer>() {
                 *r
             } else if let Ok(typed) = ptr.borrow::<TypedPointer>() {
@@ -286,8 +529,8 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
                 return Err(LuaError::external("Expected pointer"));
             };
 
-            let struct_def = def.borrow::<StructDefinition>()?;
-            Ok(StructView::new(&raw, struct_def.clone()))
+            let def = StructDefinition::from_userdata(&def)?;
+            StructView::new(&raw, def).into_lua_view(lua)
         })?,
     )?;
 
@@ -329,6 +572,49 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         })?,
     )?;
 
+    // ffi.stringArray(ptr: lightuserdata, count?: number) -> {string}
+    // Decodes a NULL-terminated char** (argv / environ style)
+    exports.set(
+        "stringArray",
+        lua.create_function(|lua, (ptr, count): (LuaValue, Option<usize>)| {
+            let raw_ptr = match ptr {
+                LuaValue::LightUserData(lud) => lud.0,
+                LuaValue::UserData(ud) => get_raw_ptr(&ud)?,
+                _ => return Err(LuaError::external("Expected pointer")),
+            };
+
+            let result = lua.create_table()?;
+            if raw_ptr.is_null() {
+                return Ok(result);
+            }
+
+            let limit = count.unwrap_or(MAX_STRING_ARRAY_LEN);
+            if limit > MAX_STRING_ARRAY_LEN {
+                return Err(LuaError::external(format!(
+                    "stringArray count {limit} exceeds maximum of {MAX_STRING_ARRAY_LEN}"
+                )));
+            }
+
+            let entries = raw_ptr.cast::<*const std::ffi::c_char>();
+            for i in 0..limit {
+                let entry = unsafe { *entries.add(i) };
+                if entry.is_null() {
+                    return Ok(result);
+                }
+                let cstr = unsafe { std::ffi::CStr::from_ptr(entry) };
+                result.raw_set(i + 1, lua.create_string(cstr.to_bytes())?)?;
+            }
+
+            if count.is_none() {
+                return Err(LuaError::external(format!(
+                    "stringArray found no NULL terminator within {MAX_STRING_ARRAY_LEN} entries"
+                )));
+            }
+
+            Ok(result)
+        })?,
+    )?;
+
     // ========================================================================
     // Null Pointer
     // ========================================================================
@@ -375,7 +661,11 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     )?;
 
     // ffi.types - type constants and utilities
-    exports.set("types", types::create_types_table(&lua)?)?;
+    // Composite constructors are aliased here so all type building lives under ffi.types
+    let types_table = types::create_types_table(&lua)?;
+    types_table.set("struct", exports.get::<LuaFunction>("struct")?)?;
+    types_table.set("union", exports.get::<LuaFunction>("union")?)?;
+    exports.set("types", types_table)?;
 
     // ffi.ctypes - type name strings (for use in signatures)
     let ctypes = lua.create_table()?;
@@ -401,19 +691,28 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
     // Callbacks
     // ========================================================================
 
-    // ffi.cdef - placeholder for C definition parsing
+    // ffi.cdef(def: string) - declare C functions, typedefs and structs
+    // Declared functions can then be called by name on libraries from ffi.load
     exports.set(
         "cdef",
-        lua.create_function(|_, _def: String| -> LuaResult<()> { Ok(()) })?,
+        lua.create_function(|lua, def: String| cdef::declare(lua, &def))?,
+    )?;
+
+    // ffi.typeof(name: string) -> StructDefinition | string
+    // Structs resolve to their definition, other types to their canonical name
+    exports.set(
+        "typeof",
+        lua.create_function(|lua, name: String| cdef::type_of(lua, &name))?,
     )?;
 
     // ffi.callback(fn, retType, argTypes) -> FfiCallback
+    // Struct definitions in argTypes are received as StructViews
     exports.set(
         "callback",
         lua.create_function(
             |lua, (func, ret_type, arg_types): (LuaFunction, CType, LuaTable)| {
-                let arg_types: Vec<CType> = arg_types
-                    .sequence_values::<CType>()
+                let arg_types: Vec<callback::CallbackArg> = arg_types
+                    .sequence_values::<callback::CallbackArg>()
                     .collect::<LuaResult<Vec<_>>>()?;
                 callback::create_callback(lua, func, ret_type, arg_types)
             },
@@ -502,3 +801,57 @@ fn get_raw_ptr(ud: &LuaAnyUserData) -> LuaResult<*mut c_void> {
         "Expected RawPointer, TypedPointer, or Buffer",
     ))
 }
+
+/// Helper to get a pointer to the contents of a Luau buffer, bounded to its length
+fn luau_buffer_ptr(lua: &Lua, buf: &mlua::Buffer) -> LuaResult<RawPointer> {
+    let len = buf.len();
+    // Luau buffers never move, so the data pointer stays valid while the buffer is alive
+    let data: LuaLightUserData = unsafe {
+        lua.exec_raw(buf, |state| {
+            let data = mlua::ffi::lua_tobuffer(state, -1, std::ptr::null_mut());
+            mlua::ffi::lua_pop(state, 1);
+            mlua::ffi::lua_pushlightuserdata(state, data);
+        })?
+    };
+    Ok(RawPointer::managed(data.0, 0, len))
+}
+
+/// Helper to view a Luau buffer as a struct or union, which must fit inside of it
+fn view_luau_buffer(
+    lua: &Lua,
+    raw: &RawPointer,
+    def: &StructDefinition,
+) -> LuaResult<LuaAnyUserData> {
+    if def.size > raw.size_hint {
+        return Err(LuaError::external(format!(
+            "Buffer of {} bytes is too small for a struct of {} bytes",
+            raw.size_hint, def.size
+        )));
+    }
+    StructView::new(raw, def.clone()).into_lua_view(lua)
+}
+
+/// Helper to get how many bytes are known to be valid behind a pointer, if any
+fn get_known_size(ud: &LuaAnyUserData) -> Option<usize> {
+    if let Ok(raw) = ud.borrow::<RawPointer>() {
+        return (raw.size_hint > 0).then_some(raw.size_hint);
+    }
+    if let Ok(typed) = ud.borrow::<TypedPointer>() {
+        let size = typed.element_count * typed.stride;
+        return (size > 0).then_some(size);
+    }
+    if let Ok(buf) = ud.borrow::<Buffer>() {
+        return Some(buf.size());
+    }
+    None
+}
+
+/// Helper to get the byte address behind a light userdata, rejecting null
+fn non_null_light_ptr(lud: LuaLightUserData) -> LuaResult<*mut u8> {
+    if lud.0.is_null() {
+        return Err(LuaError::external(
+            "Cannot access memory through a null pointer",
+        ));
+    }
+    Ok(lud.0.cast())
+}
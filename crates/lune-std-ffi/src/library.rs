@@ -1,12 +1,15 @@
 //! Native library wrapper for loading DLLs/SOs with dynamic function calling.
 
+use std::cell::RefCell;
 use std::ffi::CString;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use libloading::Library;
 use mlua::prelude::*;
 
-use crate::caller::dynamic_call;
+use crate::caller::{dynamic_call, dynamic_call_variadic};
+use crate::error::FfiError;
 use crate::types::CType;
 
 /// Export info from a native library
@@ -14,6 +17,143 @@ use crate::types::CType;
 pub struct ExportInfo {
     pub name: String,
     pub ordinal: Option<u32>,
+    pub kind: ExportKind,
+}
+
+/// Whether an export is code or data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    Function,
+    Data,
+}
+
+impl ExportKind {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Function => "function",
+            Self::Data => "data",
+        }
+    }
+
+    /// Exports in executable sections are functions, anything else is data.
+    fn from_executable(executable: bool) -> Self {
+        if executable {
+            Self::Function
+        } else {
+            Self::Data
+        }
+    }
+}
+
+/// Flags controlling how a library is loaded by `dlopen`.
+///
+/// Windows has no equivalent, so these are ignored there.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadFlags {
+    /// Make the library's symbols available to libraries loaded after it (`RTLD_GLOBAL`).
+    pub global: bool,
+    /// Resolve function symbols on first call rather than at load time (`RTLD_LAZY`).
+    pub lazy: bool,
+}
+
+impl Default for LoadFlags {
+    fn default() -> Self {
+        // Matches what `Library::new` uses
+        Self {
+            global: false,
+            lazy: true,
+        }
+    }
+}
+
+impl FromLua for LoadFlags {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Table(tab) => {
+                let mut this = Self::default();
+                if let Some(global) = tab.get::<Option<bool>>("global")? {
+                    this.global = global;
+                }
+                if let Some(lazy) = tab.get::<Option<bool>>("lazy")? {
+                    this.lazy = lazy;
+                }
+                Ok(this)
+            }
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("LoadFlags"),
+                message: None,
+            }),
+        }
+    }
+}
+
+/// Options accepted by `ffi.load` and `ffi.open`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadOptions {
+    pub flags: LoadFlags,
+}
+
+impl FromLua for LoadOptions {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Table(tab) => Ok(Self {
+                flags: LoadFlags::from_lua(tab.get("flags")?, lua)?,
+            }),
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("LoadOptions"),
+                message: None,
+            }),
+        }
+    }
+}
+
+#[cfg(unix)]
+unsafe fn load_library(path: &str, flags: LoadFlags) -> Result<Library, libloading::Error> {
+    use libloading::os::unix;
+
+    let mode = if flags.lazy {
+        unix::RTLD_LAZY
+    } else {
+        unix::RTLD_NOW
+    };
+    let scope = if flags.global {
+        unix::RTLD_GLOBAL
+    } else {
+        unix::RTLD_LOCAL
+    };
+    unsafe { unix::Library::open(Some(path), mode | scope) }.map(Library::from)
+}
+
+#[cfg(not(unix))]
+unsafe fn load_library(path: &str, _flags: LoadFlags) -> Result<Library, libloading::Error> {
+    unsafe { Library::new(path) }
+}
+
+/// Conventional entry points probed for when a library's exports cannot be parsed.
+const PROBED_EXPORTS: &[&str] = &[
+    "DllMain",
+    "DllCanUnloadNow",
+    "DllGetClassObject",
+    "DllGetVersion",
+    "DllRegisterServer",
+    "DllUnregisterServer",
+    "JNI_OnLoad",
+    "JNI_OnUnload",
+    "_init",
+    "_fini",
+];
+
+/// How long `ffi.callOnce` keeps its most recent library loaded after a call.
+const CALL_ONCE_KEEP_ALIVE: Duration = Duration::from_secs(2);
+
+thread_local! {
+    /// The library most recently loaded by `ffi.callOnce`, and when it was last used.
+    static CALL_ONCE_CACHE: RefCell<Option<(NativeLibrary, Instant)>> = const { RefCell::new(None) };
 }
 
 /// A loaded native library with full dynamic calling capabilities.
@@ -26,9 +166,15 @@ impl NativeLibrary {
     /// Open a native library by path.
     #[allow(clippy::missing_errors_doc)]
     pub fn open(path: &str) -> LuaResult<Self> {
-        let library = unsafe { Library::new(path) }.map_err(|e| {
+        Self::open_with_flags(path, LoadFlags::default())
+    }
+
+    /// Open a native library by path, with explicit `dlopen` flags.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn open_with_flags(path: &str, flags: LoadFlags) -> LuaResult<Self> {
+        let library = unsafe { load_library(path, flags) }.map_err(|e| {
             eprintln!("[FFI ERROR] Failed to load library '{}': {}", path, e);
-            LuaError::external(format!("Failed to load library '{path}': {e}"))
+            LuaError::from(FfiError::library_not_found(path, e))
         })?;
 
         Ok(Self {
@@ -37,6 +183,40 @@ impl NativeLibrary {
         })
     }
 
+    /// Load a library, call one of its functions and release it again.
+    ///
+    /// The library is kept loaded for a short while after the call, so calling
+    /// into the same library in a loop does not reload it every time.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn call_once(
+        lua: &Lua,
+        path: &str,
+        name: &str,
+        ret_type: CType,
+        arg_types: &[CType],
+        args: Vec<LuaValue>,
+    ) -> LuaResult<LuaValue> {
+        let library = CALL_ONCE_CACHE.with_borrow_mut(|cache| {
+            let now = Instant::now();
+            match cache.take() {
+                Some((lib, used)) if lib.path == path && now - used < CALL_ONCE_KEEP_ALIVE => {
+                    *cache = Some((lib.clone(), now));
+                    Ok(lib)
+                }
+                // Anything else is dropped here, unloading it unless still referenced
+                _ => {
+                    let lib = Self::open(path)?;
+                    *cache = Some((lib.clone(), now));
+                    Ok::<_, LuaError>(lib)
+                }
+            }
+        })?;
+
+        // The cache lock is released before calling, so callbacks may use callOnce too
+        let fn_ptr = library.get_symbol_ptr(name)?;
+        dynamic_call(lua, fn_ptr, ret_type, arg_types, args)
+    }
+
     /// Get the library Arc for sharing with SmartLibrary.
     pub fn library_arc(&self) -> Arc<Library> {
         Arc::clone(&self.library)
@@ -58,49 +238,70 @@ impl NativeLibrary {
                         "[FFI ERROR] Symbol '{}' not found in '{}': {}",
                         name, self.path, e
                     );
-                    LuaError::external(format!("Symbol '{name}' not found: {e}"))
+                    LuaError::from(FfiError::symbol_not_found(name, e))
                 })
         }
     }
 
     /// List all exported symbols from the library
-    pub fn list_exports(&self) -> LuaResult<Vec<ExportInfo>> {
-        let bytes = std::fs::read(&self.path).map_err(|e| {
-            eprintln!(
-                "[FFI ERROR] Failed to read library file '{}': {}",
-                self.path, e
-            );
-            LuaError::external(format!("Failed to read library file: {e}"))
-        })?;
+    ///
+    /// Never fails: when the library file cannot be read, such as when it was
+    /// loaded by bare name from a system path, the list is empty. When it cannot
+    /// be parsed, only conventional entry points found with `dlsym` are listed.
+    pub fn list_exports(&self) -> Vec<ExportInfo> {
+        let bytes = match std::fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!(
+                    "[FFI WARNING] Cannot read library file '{}' to list exports: {}",
+                    self.path, e
+                );
+                return Vec::new();
+            }
+        };
 
         match goblin::Object::parse(&bytes) {
             Ok(goblin::Object::PE(pe)) => {
                 let mut exports = Vec::new();
-                for export in pe.exports {
+                for export in &pe.exports {
                     if let Some(name) = export.name {
                         exports.push(ExportInfo {
                             name: name.to_string(),
                             ordinal: None, // ordinal not directly available in goblin PE
+                            kind: pe_export_kind(&pe, export.rva),
                         });
                     }
                 }
-                Ok(exports)
+                exports
             }
             Ok(goblin::Object::Elf(elf)) => {
+                use goblin::elf::section_header::SHN_UNDEF;
+                use goblin::elf::sym::{STB_GLOBAL, STT_OBJECT};
+
                 let mut exports = Vec::new();
                 for sym in elf.dynsyms.iter() {
-                    if sym.is_function() && sym.st_bind() == goblin::elf::sym::STB_GLOBAL {
-                        if let Some(name) = elf.dynstrtab.get_at(sym.st_name) {
-                            if !name.is_empty() {
-                                exports.push(ExportInfo {
-                                    name: name.to_string(),
-                                    ordinal: None,
-                                });
-                            }
-                        }
+                    // Undefined symbols are imports from other libraries
+                    if sym.st_bind() != STB_GLOBAL || sym.st_shndx == SHN_UNDEF as usize {
+                        continue;
+                    }
+                    let kind = if sym.is_function() {
+                        ExportKind::Function
+                    } else if sym.st_type() == STT_OBJECT {
+                        ExportKind::Data
+                    } else {
+                        continue;
+                    };
+                    if let Some(name) = elf.dynstrtab.get_at(sym.st_name)
+                        && !name.is_empty()
+                    {
+                        exports.push(ExportInfo {
+                            name: name.to_string(),
+                            ordinal: None,
+                            kind,
+                        });
                     }
                 }
-                Ok(exports)
+                exports
             }
             Ok(goblin::Object::Mach(mach)) => {
                 let mut exports = Vec::new();
@@ -109,7 +310,8 @@ impl NativeLibrary {
                         if let Ok(syms) = macho.exports() {
                             for exp in syms {
                                 exports.push(ExportInfo {
-                                    name: exp.name.clone(),
+                                    kind: macho_export_kind(&macho, &exp.info),
+                                    name: exp.name,
                                     ordinal: None,
                                 });
                             }
@@ -117,14 +319,86 @@ impl NativeLibrary {
                     }
                     _ => {}
                 }
-                Ok(exports)
+                exports
             }
             _ => {
-                eprintln!("[FFI ERROR] Unsupported binary format for '{}'", self.path);
-                Err(LuaError::external("Unsupported binary format"))
+                eprintln!(
+                    "[FFI WARNING] Unsupported binary format for '{}', only listing common entry points",
+                    self.path
+                );
+                self.probe_exports()
             }
         }
     }
+
+    /// Find which of [`PROBED_EXPORTS`] the loaded library resolves.
+    fn probe_exports(&self) -> Vec<ExportInfo> {
+        PROBED_EXPORTS
+            .iter()
+            .filter(|name| {
+                let cname = CString::new(**name).expect("probed names contain no nul bytes");
+                unsafe {
+                    self.library
+                        .get::<*const std::ffi::c_void>(cname.as_bytes_with_nul())
+                        .is_ok()
+                }
+            })
+            .map(|name| ExportInfo {
+                name: (*name).to_string(),
+                ordinal: None,
+                kind: ExportKind::Function,
+            })
+            .collect()
+    }
+}
+
+/// Kind of a PE export, from the characteristics of the section its address is in.
+fn pe_export_kind(pe: &goblin::pe::PE, rva: usize) -> ExportKind {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_MEM_EXECUTE};
+
+    let executable = pe.sections.iter().any(|section| {
+        let start = section.virtual_address as usize;
+        let end = start + section.virtual_size.max(section.size_of_raw_data) as usize;
+        (start..end).contains(&rva)
+            && section.characteristics & (IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE) != 0
+    });
+    ExportKind::from_executable(executable)
+}
+
+/// Kind of a Mach-O export, from the attributes of the section its address is in.
+fn macho_export_kind(
+    macho: &goblin::mach::MachO,
+    info: &goblin::mach::exports::ExportInfo,
+) -> ExportKind {
+    use goblin::mach::constants::{S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS};
+    use goblin::mach::exports::ExportInfo as MachExportInfo;
+
+    let address = match info {
+        MachExportInfo::Regular { address, .. } => *address,
+        // Stubs are resolved to code at load time, and re-exports live in
+        // another image, where they are almost always functions as well
+        MachExportInfo::Stub { .. } | MachExportInfo::Reexport { .. } => {
+            return ExportKind::Function;
+        }
+    };
+
+    // Export addresses are relative to the start of the image, which is where __TEXT is mapped
+    let base = macho
+        .segments
+        .iter()
+        .find(|segment| segment.name().is_ok_and(|name| name == "__TEXT"))
+        .map_or(0, |segment| segment.vmaddr);
+    let address = base + address;
+
+    let executable = macho.segments.iter().any(|segment| {
+        segment.sections().is_ok_and(|sections| {
+            sections.iter().any(|(section, _)| {
+                (section.addr..section.addr + section.size).contains(&address)
+                    && section.flags & (S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS) != 0
+            })
+        })
+    });
+    ExportKind::from_executable(executable)
 }
 
 impl Clone for NativeLibrary {
@@ -153,13 +427,14 @@ impl LuaUserData for NativeLibrary {
             Ok(this.get_symbol_ptr(&name).is_ok())
         });
 
-        // lib:listExports() -> {{name: string, ordinal: number?}}
+        // lib:listExports() -> {{name: string, ordinal: number?, kind: "function" | "data"}}
         methods.add_method("listExports", |lua, this, ()| {
-            let exports = this.list_exports()?;
+            let exports = this.list_exports();
             let result = lua.create_table()?;
             for (i, export) in exports.iter().enumerate() {
                 let entry = lua.create_table()?;
                 entry.set("name", export.name.clone())?;
+                entry.set("kind", export.kind.as_str())?;
                 if let Some(ord) = export.ordinal {
                     entry.set("ordinal", ord)?;
                 }
@@ -190,6 +465,52 @@ impl LuaUserData for NativeLibrary {
             },
         );
 
+        // lib:callVariadic(name, returnType, fixedArgTypes, varArgs, ...fixedArgs) -> result
+        // varArgs is an array of { type, value } pairs for the `...` part of the call
+        methods.add_method(
+            "callVariadic",
+            |lua,
+             this,
+             (name, ret_type, fixed_types, var_args, args): (
+                String,
+                CType,
+                LuaTable,
+                LuaTable,
+                LuaMultiValue,
+            )| {
+                let fn_ptr = this.get_symbol_ptr(&name)?;
+
+                let fixed_types: Vec<CType> = fixed_types
+                    .sequence_values::<CType>()
+                    .collect::<LuaResult<Vec<_>>>()?;
+
+                let var_args = var_args
+                    .sequence_values::<LuaValue>()
+                    .enumerate()
+                    .map(|(i, pair)| match pair? {
+                        LuaValue::Table(pair) => Ok((pair.get::<CType>(1)?, pair.get(2)?)),
+                        _ => Err(LuaError::external(format!(
+                            "Variadic argument {} must be a {{ type, value }} pair",
+                            i + 1
+                        ))),
+                    })
+                    .collect::<LuaResult<Vec<(CType, LuaValue)>>>()?;
+
+                dynamic_call_variadic(
+                    lua,
+                    fn_ptr,
+                    ret_type,
+                    &fixed_types,
+                    args.into_vec(),
+                    var_args,
+                )
+                .map_err(|e| {
+                    eprintln!("[FFI ERROR] Call to '{}' failed: {}", name, e);
+                    e
+                })
+            },
+        );
+
         // lib:callPtr(ptr, returnType, argTypes, ...args) -> result
         methods.add_method(
             "callPtr",
@@ -248,6 +569,11 @@ impl LuaUserData for NativeLibrary {
 
         // lib:close()
         methods.add_method("close", |_, _, ()| Ok(()));
+
+        // lib.name -> function declared through ffi.cdef, or nil
+        methods.add_meta_method(LuaMetaMethod::Index, |lua, this, name: String| {
+            crate::cdef::bind_declared(lua, &this.library, &name)
+        });
     }
 }
 
@@ -278,7 +604,7 @@ impl BoundFunction {
                 .map(|sym| *sym)
                 .map_err(|e| {
                     eprintln!("[FFI ERROR] Symbol '{}' not found: {}", name, e);
-                    LuaError::external(format!("Symbol '{name}' not found: {e}"))
+                    LuaError::from(FfiError::symbol_not_found(name, e))
                 })?
         };
 
@@ -299,3 +625,38 @@ impl LuaUserData for BoundFunction {
         });
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+
+    fn is_visible_globally(symbol: &str) -> bool {
+        let name = CString::new(symbol).unwrap();
+        !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+    }
+
+    #[test]
+    fn global_flag_exports_symbols() {
+        // Not linked into the test binary, so only visible once loaded globally
+        assert!(!is_visible_globally("BZ2_bzlibVersion"));
+
+        let local = LoadFlags {
+            global: false,
+            lazy: true,
+        };
+        let Ok(_lib) = NativeLibrary::open_with_flags("libbz2.so.1", local) else {
+            // libbz2 is not installed, nothing to test against
+            return;
+        };
+        assert!(!is_visible_globally("BZ2_bzlibVersion"));
+
+        let global = LoadFlags {
+            global: true,
+            lazy: false,
+        };
+        let _lib = NativeLibrary::open_with_flags("libbz2.so.1", global).unwrap();
+        assert!(is_visible_globally("BZ2_bzlibVersion"));
+    }
+}
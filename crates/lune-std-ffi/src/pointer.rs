@@ -8,7 +8,7 @@ use mlua::prelude::*;
 use std::ffi::c_void;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::types::CType;
+use crate::types::{CType, unsigned_from_lua, unsigned_into_lua};
 
 /// Unique ID generator for arena tracking
 static ARENA_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
@@ -321,6 +321,17 @@ impl LuaUserData for TypedPointer {
             }
         });
 
+        // Length: #ptr is the element count, when the pointer is bounded
+        methods.add_meta_method(LuaMetaMethod::Len, |_, this, ()| {
+            if this.element_count > 0 {
+                Ok(this.element_count)
+            } else {
+                Err(LuaError::external(
+                    "TypedPointer has no known element count, cast a bounded pointer to use #",
+                ))
+            }
+        });
+
         // Array assignment: ptr[i] = val writes at index
         methods.add_meta_method(
             LuaMetaMethod::NewIndex,
@@ -408,7 +419,7 @@ pub fn read_value_at(lua: &Lua, ptr: *mut u8, ctype: CType) -> LuaResult<LuaValu
         }
         CType::U64 => {
             let v = unsafe { *(ptr as *const u64) };
-            LuaValue::Number(v as f64)
+            unsigned_into_lua(lua, v)?
         }
         CType::ISize => {
             let v = unsafe { *(ptr as *const isize) };
@@ -416,7 +427,7 @@ pub fn read_value_at(lua: &Lua, ptr: *mut u8, ctype: CType) -> LuaResult<LuaValu
         }
         CType::USize => {
             let v = unsafe { *(ptr as *const usize) };
-            LuaValue::Integer(v as i64)
+            unsigned_into_lua(lua, v as u64)?
         }
         CType::F32 => {
             let v = unsafe { *(ptr as *const f32) };
@@ -483,15 +494,15 @@ pub fn write_value_at(lua: &Lua, ptr: *mut u8, ctype: CType, value: LuaValue) ->
             unsafe { *(ptr as *mut i64) = v };
         }
         CType::U64 => {
-            let v: f64 = FromLua::from_lua(value, lua)?;
-            unsafe { *(ptr as *mut u64) = v as u64 };
+            let v = unsigned_from_lua(&value)?;
+            unsafe { *(ptr as *mut u64) = v };
         }
         CType::ISize => {
             let v: i64 = FromLua::from_lua(value, lua)?;
             unsafe { *(ptr as *mut isize) = v as isize };
         }
         CType::USize => {
-            let v: i64 = FromLua::from_lua(value, lua)?;
+            let v = unsigned_from_lua(&value)?;
             unsafe { *(ptr as *mut usize) = v as usize };
         }
         CType::F32 => {
@@ -5,7 +5,12 @@
 //!
 //! # Safety
 //! - All allocations are invalidated after `reset()` is called
+//! - Allocations made after a `mark()` are invalidated by `reset_to()`
 //! - Pointers must not be used after the function call returns
+//!
+//! Calls may nest when a C callback re-enters Lua and makes another FFI
+//! call. Each call rewinds only to the mark taken when it started, so the
+//! outer call's strings stay intact until the outer call returns.
 
 use std::cell::RefCell;
 
@@ -98,10 +103,25 @@ impl ScratchArena {
     ///
     /// This is O(1) - just resets the offset to 0.
     #[inline]
+    #[allow(dead_code)]
     pub fn reset(&mut self) {
         self.offset = 0;
     }
 
+    /// Remember the current offset, to later release only what was allocated after it.
+    #[inline]
+    pub fn mark(&self) -> usize {
+        self.offset
+    }
+
+    /// Release every allocation made since `mark` was taken.
+    ///
+    /// Allocations made before the mark stay valid.
+    #[inline]
+    pub fn reset_to(&mut self, mark: usize) {
+        self.offset = mark.min(self.offset);
+    }
+
     /// Get the current allocation offset (for debugging).
     #[inline]
     #[allow(dead_code)]
@@ -151,6 +171,22 @@ mod tests {
         assert_eq!(arena.used(), 0);
     }
 
+    #[test]
+    fn test_reset_to_mark() {
+        let mut arena = ScratchArena::new(1024);
+
+        let outer = arena.alloc_cstring(b"outer").unwrap();
+        let mark = arena.mark();
+
+        arena.alloc_cstring(b"nested").unwrap();
+        arena.reset_to(mark);
+        arena.alloc_cstring(b"again!").unwrap();
+
+        let cstr = unsafe { std::ffi::CStr::from_ptr(outer) };
+        assert_eq!(cstr.to_str().unwrap(), "outer");
+        assert_eq!(arena.used(), mark + 7);
+    }
+
     #[test]
     fn test_overflow() {
         let mut arena = ScratchArena::new(10);
@@ -10,9 +10,11 @@ use libffi::middle::{Arg, Builder, Cif, CodePtr, Type as FfiType};
 use libloading::Library;
 use mlua::prelude::*;
 
+use crate::callback::FfiCallback;
+use crate::error::FfiError;
 use crate::pointer::RawPointer;
 use crate::scratch_arena::SCRATCH_ARENA;
-use crate::types::{Buffer, CType};
+use crate::types::{Buffer, CType, unsigned_from_lua, unsigned_into_lua};
 
 /// Convert CType to libffi Type
 #[inline]
@@ -35,6 +37,61 @@ fn ctype_to_ffi(ctype: CType) -> FfiType {
     }
 }
 
+/// Libffi type passed for an argument, out and inout arguments are passed as pointers
+#[inline]
+fn arg_to_ffi(ctype: CType, dir: ArgDirection) -> FfiType {
+    match dir {
+        ArgDirection::In => ctype_to_ffi(ctype),
+        ArgDirection::Out | ArgDirection::InOut => FfiType::pointer(),
+    }
+}
+
+/// How an argument is passed to the native function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgDirection {
+    /// Passed by value
+    In,
+    /// Pointer to scratch storage, written by the function and returned to Lua
+    Out,
+    /// Like `Out`, but the storage is initialized from the Lua argument first
+    InOut,
+}
+
+/// An argument entry in a function signature, either a type name
+/// or a `{ type = "...", dir = "in" | "out" | "inout" }` table.
+#[derive(Clone, Copy)]
+struct ArgSpec {
+    ctype: CType,
+    dir: ArgDirection,
+}
+
+impl FromLua for ArgSpec {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let LuaValue::Table(entry) = value else {
+            return Ok(Self {
+                ctype: CType::from_lua(value, lua)?,
+                dir: ArgDirection::In,
+            });
+        };
+
+        let ctype: CType = entry.get("type")?;
+        let dir = match entry.get::<Option<String>>("dir")?.as_deref() {
+            None | Some("in") => ArgDirection::In,
+            Some("out") => ArgDirection::Out,
+            Some("inout") => ArgDirection::InOut,
+            Some(other) => {
+                return Err(LuaError::external(format!(
+                    "Unknown argument direction '{other}', expected 'in', 'out' or 'inout'"
+                )));
+            }
+        };
+        if ctype == CType::Void {
+            return Err(LuaError::external("Cannot pass void as argument"));
+        }
+        Ok(Self { ctype, dir })
+    }
+}
+
 // ============================================================================
 // SmartBoundFunction - Pre-compiled callable
 // ============================================================================
@@ -53,6 +110,8 @@ pub struct SmartBoundFunction {
     ret_type: CType,
     /// Argument types
     arg_types: Vec<CType>,
+    /// Argument directions, parallel to `arg_types`
+    arg_dirs: Vec<ArgDirection>,
     /// Pre-compiled libffi CIF
     cif: Cif,
 }
@@ -64,76 +123,171 @@ unsafe impl Sync for SmartBoundFunction {}
 impl Clone for SmartBoundFunction {
     fn clone(&self) -> Self {
         // Rebuild CIF since it's not Clone
-        let ffi_args: Vec<FfiType> = self.arg_types.iter().map(|t| ctype_to_ffi(*t)).collect();
-        let ffi_ret = ctype_to_ffi(self.ret_type);
-        let cif = Builder::new().args(ffi_args).res(ffi_ret).into_cif();
+        let cif = build_cif(self.ret_type, &self.arg_types, &self.arg_dirs);
 
         Self {
             library: Arc::clone(&self.library),
             fn_ptr: self.fn_ptr,
             ret_type: self.ret_type,
             arg_types: self.arg_types.clone(),
+            arg_dirs: self.arg_dirs.clone(),
             cif,
         }
     }
 }
 
+fn build_cif(ret_type: CType, arg_types: &[CType], arg_dirs: &[ArgDirection]) -> Cif {
+    let ffi_args: Vec<FfiType> = arg_types
+        .iter()
+        .zip(arg_dirs)
+        .map(|(t, d)| arg_to_ffi(*t, *d))
+        .collect();
+    let ffi_ret = ctype_to_ffi(ret_type);
+    Builder::new().args(ffi_args).res(ffi_ret).into_cif()
+}
+
+/// Size of each scratch slot backing an out or inout argument, fits any scalar type
+const OUT_SLOT_SIZE: usize = 8;
+
 impl SmartBoundFunction {
     /// Create a new smart bound function.
-    pub fn new(
+    fn new(
         library: Arc<Library>,
         fn_ptr: *const c_void,
         ret_type: CType,
-        arg_types: Vec<CType>,
+        args: &[ArgSpec],
     ) -> LuaResult<Self> {
+        let arg_types: Vec<CType> = args.iter().map(|a| a.ctype).collect();
+        let arg_dirs: Vec<ArgDirection> = args.iter().map(|a| a.dir).collect();
+
         // Pre-compile the CIF
-        let ffi_args: Vec<FfiType> = arg_types.iter().map(|t| ctype_to_ffi(*t)).collect();
-        let ffi_ret = ctype_to_ffi(ret_type);
-        let cif = Builder::new().args(ffi_args).res(ffi_ret).into_cif();
+        let cif = build_cif(ret_type, &arg_types, &arg_dirs);
 
         Ok(Self {
             library,
             fn_ptr,
             ret_type,
             arg_types,
+            arg_dirs,
             cif,
         })
     }
 
+    /// Bind a function declared through `ffi.cdef`, whose arguments are all passed by value.
+    pub(crate) fn from_declaration(
+        library: Arc<Library>,
+        name: &str,
+        ret_type: CType,
+        arg_types: &[CType],
+    ) -> LuaResult<Self> {
+        let fn_ptr = lookup_function(&library, name)?;
+        let args: Vec<ArgSpec> = arg_types
+            .iter()
+            .map(|&ctype| ArgSpec {
+                ctype,
+                dir: ArgDirection::In,
+            })
+            .collect();
+        Self::new(library, fn_ptr, ret_type, &args)
+    }
+
+    /// Number of arguments passed from Lua, which excludes out arguments.
+    fn lua_arg_count(&self) -> usize {
+        self.arg_dirs
+            .iter()
+            .filter(|d| **d != ArgDirection::Out)
+            .count()
+    }
+
     /// Call the function with automatic marshalling.
-    fn call_with_args(&self, lua: &Lua, args: LuaMultiValue) -> LuaResult<LuaValue> {
+    ///
+    /// Returns the function's own result (nil for void functions), followed
+    /// by the values written to each out and inout argument in order.
+    fn call_with_args(&self, lua: &Lua, args: LuaMultiValue) -> LuaResult<LuaMultiValue> {
         let args_vec: Vec<LuaValue> = args.into_vec();
 
-        if args_vec.len() != self.arg_types.len() {
+        let expected = self.lua_arg_count();
+        if args_vec.len() != expected {
             return Err(LuaError::external(format!(
                 "Expected {} arguments, got {}",
-                self.arg_types.len(),
+                expected,
                 args_vec.len()
             )));
         }
 
+        // Scratch storage for out and inout arguments, one aligned slot each
+        let out_count = self
+            .arg_dirs
+            .iter()
+            .filter(|d| **d != ArgDirection::In)
+            .count();
+        let mut out_slots = (out_count > 0)
+            .then(|| Buffer::new_aligned(out_count * OUT_SLOT_SIZE, OUT_SLOT_SIZE))
+            .transpose()?;
+
         // Use scratch arena for string conversions
         SCRATCH_ARENA.with(|arena| {
-            let mut arena = arena.borrow_mut();
+            // The call may re-enter Lua through a callback and make nested FFI
+            // calls, so only borrow the arena while converting arguments and
+            // rewind to where this call started instead of resetting it fully
+            let mark = arena.borrow().mark();
 
             // Storage for argument values (keeps them alive during call)
             let mut storage = ArgStorage::new();
 
             // Convert each argument
-            for (value, ctype) in args_vec.into_iter().zip(&self.arg_types) {
-                storage.push(lua, value, *ctype, &mut arena)?;
+            let converted = {
+                let mut arena = arena.borrow_mut();
+                // Numbered from 1 like Lua arguments, so out arguments are skipped
+                let mut values = args_vec.into_iter().zip(1..);
+                let mut slot = 0;
+                self.arg_types
+                    .iter()
+                    .zip(&self.arg_dirs)
+                    .try_for_each(|(ctype, dir)| {
+                        let Some(slots) = out_slots.as_mut().filter(|_| *dir != ArgDirection::In)
+                        else {
+                            let (value, index) = values.next().unwrap_or((LuaValue::Nil, 0));
+                            return storage.push(lua, index, value, *ctype, &mut arena);
+                        };
+                        let offset = slot * OUT_SLOT_SIZE;
+                        slot += 1;
+                        if *dir == ArgDirection::InOut {
+                            let (value, index) = values.next().unwrap_or((LuaValue::Nil, 0));
+                            let got = value.type_name();
+                            slots
+                                .write(lua, offset, *ctype, value)
+                                .map_err(|err| argument_error(index, *ctype, got, err))?;
+                        }
+                        storage.push_ptr(unsafe { slots.as_ptr().add(offset) }.cast());
+                        Ok(())
+                    })
+            };
+
+            let result = converted.and_then(|()| {
+                // Build libffi args
+                let ffi_args: Vec<Arg> = storage.as_args();
+
+                // Perform the call
+                self.call_cif(lua, &ffi_args)
+            });
+
+            // Release this call's scratch allocations
+            arena.borrow_mut().reset_to(mark);
+
+            let mut results = LuaMultiValue::new();
+            results.push_back(result?);
+            if let Some(slots) = &out_slots {
+                let out_types = self
+                    .arg_types
+                    .iter()
+                    .zip(&self.arg_dirs)
+                    .filter(|(_, dir)| **dir != ArgDirection::In);
+                for (slot, (ctype, _)) in out_types.enumerate() {
+                    results.push_back(slots.read(lua, slot * OUT_SLOT_SIZE, *ctype)?);
+                }
             }
-
-            // Build libffi args
-            let ffi_args: Vec<Arg> = storage.as_args();
-
-            // Perform the call
-            let result = self.call_cif(lua, &ffi_args);
-
-            // Reset scratch arena after call
-            arena.reset();
-
-            result
+            Ok(results)
         })
     }
 
@@ -148,6 +302,7 @@ impl SmartBoundFunction {
                 LuaValue::Nil
             }
             CType::Bool => {
+                // Only the low byte of a bool return is defined, so read just that byte
                 let r: i8 = unsafe { self.cif.call(code_ptr, args) };
                 LuaValue::Boolean(r != 0)
             }
@@ -181,7 +336,7 @@ impl SmartBoundFunction {
             }
             CType::U64 => {
                 let r: u64 = unsafe { self.cif.call(code_ptr, args) };
-                LuaValue::Number(r as f64)
+                unsigned_into_lua(lua, r)?
             }
             CType::ISize => {
                 let r: isize = unsafe { self.cif.call(code_ptr, args) };
@@ -189,7 +344,7 @@ impl SmartBoundFunction {
             }
             CType::USize => {
                 let r: usize = unsafe { self.cif.call(code_ptr, args) };
-                LuaValue::Integer(r as i64)
+                unsigned_into_lua(lua, r as u64)?
             }
             CType::F32 => {
                 let r: f32 = unsafe { self.cif.call(code_ptr, args) };
@@ -258,6 +413,8 @@ struct ArgStorage {
     ptrs: Vec<*mut c_void>,
     // For owned CStrings (when not using scratch arena)
     cstrings: Vec<CString>,
+    // Callbacks passed as pointers, kept alive until the call returns
+    callbacks: Vec<LuaAnyUserData>,
     // Argument indices mapping to storage
     args: Vec<ArgRef>,
 }
@@ -294,11 +451,26 @@ impl ArgStorage {
             f64s: Vec::new(),
             ptrs: Vec::new(),
             cstrings: Vec::new(),
+            callbacks: Vec::new(),
             args: Vec::new(),
         }
     }
 
+    /// Convert and store the Lua argument at `index`, naming it in any conversion error.
     fn push(
+        &mut self,
+        lua: &Lua,
+        index: usize,
+        value: LuaValue,
+        ctype: CType,
+        scratch: &mut crate::scratch_arena::ScratchArena,
+    ) -> LuaResult<()> {
+        let got = value.type_name();
+        self.push_value(lua, value, ctype, scratch)
+            .map_err(|err| argument_error(index, ctype, got, err))
+    }
+
+    fn push_value(
         &mut self,
         lua: &Lua,
         value: LuaValue,
@@ -365,9 +537,9 @@ impl ArgStorage {
             }
 
             CType::U64 => {
-                let v: f64 = FromLua::from_lua(value, lua)?;
+                let v = unsigned_from_lua(&value)?;
                 let idx = self.u64s.len();
-                self.u64s.push(v as u64);
+                self.u64s.push(v);
                 ArgRef::U64(idx)
             }
 
@@ -379,9 +551,9 @@ impl ArgStorage {
             }
 
             CType::USize => {
-                let v: i64 = FromLua::from_lua(value, lua)?;
+                let v = unsigned_from_lua(&value)?;
                 let idx = self.u64s.len();
-                self.u64s.push(v as u64);
+                self.u64s.push(v);
                 ArgRef::U64(idx)
             }
 
@@ -408,8 +580,12 @@ impl ArgStorage {
                             raw.addr
                         } else if let Ok(buf) = ud.borrow::<Buffer>() {
                             buf.as_ptr().cast()
+                        } else if let Ok(callback) = ud.borrow::<FfiCallback>() {
+                            let ptr = callback.as_ptr();
+                            self.callbacks.push(ud.clone());
+                            ptr
                         } else {
-                            return Err(LuaError::external("Expected pointer, buffer, or nil"));
+                            return Err(type_mismatch("userdata", ctype));
                         }
                     }
                     LuaValue::Integer(i) => i as usize as *mut c_void,
@@ -422,11 +598,7 @@ impl ArgStorage {
                             LuaError::external("Scratch arena overflow for string argument")
                         })? as *mut c_void
                     }
-                    _ => {
-                        return Err(LuaError::external(
-                            "Expected pointer, buffer, string, or nil",
-                        ));
-                    }
+                    other => return Err(type_mismatch(other.type_name(), ctype)),
                 };
                 let idx = self.ptrs.len();
                 self.ptrs.push(ptr);
@@ -456,7 +628,7 @@ impl ArgStorage {
                         self.ptrs.push(ud.0);
                         ArgRef::Ptr(idx)
                     }
-                    _ => return Err(LuaError::external("Expected string, pointer, or nil")),
+                    other => return Err(type_mismatch(other.type_name(), ctype)),
                 }
             }
         };
@@ -465,6 +637,13 @@ impl ArgStorage {
         Ok(())
     }
 
+    /// Push a pointer argument that needs no conversion.
+    fn push_ptr(&mut self, ptr: *mut c_void) {
+        let idx = self.ptrs.len();
+        self.ptrs.push(ptr);
+        self.args.push(ArgRef::Ptr(idx));
+    }
+
     fn as_args(&self) -> Vec<Arg> {
         self.args
             .iter()
@@ -486,6 +665,55 @@ impl ArgStorage {
     }
 }
 
+/// Conversion error for a Lua value of type `from` that cannot be passed as `ctype`.
+fn type_mismatch(from: &'static str, ctype: CType) -> LuaError {
+    LuaError::FromLuaConversionError {
+        from,
+        to: ctype.name().to_owned(),
+        message: None,
+    }
+}
+
+/// Lua types accepted for an argument of type `ctype`, for error messages.
+fn expected_lua_types(ctype: CType) -> &'static str {
+    match ctype {
+        CType::Void => "nothing",
+        CType::Bool => "boolean",
+        CType::Pointer => "pointer, buffer, callback, string, or nil",
+        CType::CString => "string, pointer, or nil",
+        _ => "number",
+    }
+}
+
+/// Prefix a failed argument conversion with the argument index and its C type,
+/// as in `argument 2 (i32): expected number, got table`.
+fn argument_error(index: usize, ctype: CType, got: &'static str, err: LuaError) -> LuaError {
+    let reason = match err {
+        // Numbers that do not fit keep the reason given by the conversion
+        LuaError::FromLuaConversionError {
+            message: Some(message),
+            ..
+        } if matches!(got, "number" | "integer") => message,
+        LuaError::FromLuaConversionError { .. } => {
+            format!("expected {}, got {got}", expected_lua_types(ctype))
+        }
+        err => err.to_string(),
+    };
+    LuaError::external(format!("argument {index} ({}): {reason}", ctype.name()))
+}
+
+/// Look up the address of the function `name` exported by `library`.
+fn lookup_function(library: &Library, name: &str) -> LuaResult<*const c_void> {
+    let cname = CString::new(name).map_err(|_| LuaError::external("Invalid symbol name"))?;
+
+    unsafe {
+        library
+            .get::<*const c_void>(cname.as_bytes_with_nul())
+            .map(|sym| *sym)
+            .map_err(|e| LuaError::from(FfiError::symbol_not_found(name, e)))
+    }
+}
+
 // ============================================================================
 // SmartLibrary - Library with pre-bound interface
 // ============================================================================
@@ -504,8 +732,7 @@ impl ArgStorage {
 /// User32.MessageBoxA(0, "Hello!", "Title", User32.MB_OK)
 /// ```
 pub struct SmartLibrary {
-    /// Keep library loaded
-    #[allow(dead_code)]
+    /// Keep library loaded, and bind functions declared through `ffi.cdef`
     library: Arc<Library>,
     /// Library path
     path: String,
@@ -542,28 +769,18 @@ impl SmartLibrary {
                 LuaValue::Table(sig) => {
                     let ret_type: CType = sig.get("ret").unwrap_or(CType::Void);
 
-                    let arg_types: Vec<CType> = match sig.get::<LuaTable>("args") {
+                    let args: Vec<ArgSpec> = match sig.get::<LuaTable>("args") {
                         Ok(args_tbl) => args_tbl
-                            .sequence_values::<CType>()
+                            .sequence_values::<ArgSpec>()
                             .collect::<LuaResult<Vec<_>>>()?,
                         Err(_) => Vec::new(),
                     };
 
                     // Get symbol pointer
-                    let cname = CString::new(name.as_str())
-                        .map_err(|_| LuaError::external("Invalid symbol name"))?;
-
-                    let fn_ptr = unsafe {
-                        library
-                            .get::<*const c_void>(cname.as_bytes_with_nul())
-                            .map(|sym| *sym)
-                            .map_err(|e| {
-                                LuaError::external(format!("Symbol '{}' not found: {}", name, e))
-                            })?
-                    };
+                    let fn_ptr = lookup_function(&library, &name)?;
 
                     let bound =
-                        SmartBoundFunction::new(Arc::clone(&library), fn_ptr, ret_type, arg_types)?;
+                        SmartBoundFunction::new(Arc::clone(&library), fn_ptr, ret_type, &args)?;
 
                     functions.insert(name, bound);
                 }
@@ -624,8 +841,11 @@ impl LuaUserData for SmartLibrary {
                 return func.clone().into_lua(lua);
             }
 
-            // Not found
-            Ok(LuaValue::Nil)
+            // Fall back to functions declared through ffi.cdef
+            match crate::cdef::bind_declared(lua, &this.library, &key)? {
+                Some(func) => func.into_lua(lua),
+                None => Ok(LuaValue::Nil),
+            }
         });
 
         // close() method
@@ -0,0 +1,156 @@
+//! Struct classes: struct layouts with Lua methods attached.
+//!
+//! A class is created from a struct schema with an extra `methods` table,
+//! and calling it allocates a new instance whose fields live in native memory.
+
+use mlua::prelude::*;
+
+use crate::pointer::RawPointer;
+use crate::struct_mapper::{StructDefinition, StructView, keep_owner_alive};
+use crate::types::Buffer;
+
+/// Names handled by instances themselves, which fields and methods may not use
+const RESERVED_NAMES: [&str; 3] = ["ptr", "size", "addr"];
+
+/// Constructor for struct instances, created by `ffi.structClass`
+pub struct StructClass {
+    def: StructDefinition,
+    methods: LuaTable,
+}
+
+impl StructClass {
+    /// Parse a struct schema, along with the functions in its `methods` subtable
+    pub fn from_schema(lua: &Lua, schema: LuaTable) -> LuaResult<Self> {
+        let methods = match schema.get::<LuaValue>("methods")? {
+            LuaValue::Nil => lua.create_table()?,
+            LuaValue::Table(t) => t,
+            _ => return Err(LuaError::external("Struct class methods must be a table")),
+        };
+        let def = StructDefinition::from_schema(lua, schema)?;
+
+        for field in &def.fields {
+            if RESERVED_NAMES.contains(&field.name.as_str()) {
+                return Err(LuaError::external(format!(
+                    "Field name '{}' is reserved in struct classes",
+                    field.name
+                )));
+            }
+        }
+        for pair in methods.pairs::<String, LuaValue>() {
+            let (name, value) = pair?;
+            if !matches!(value, LuaValue::Function(_)) {
+                return Err(LuaError::external(format!(
+                    "Method '{}' must be a function",
+                    name
+                )));
+            }
+            if def.get_field(&name).is_some() || RESERVED_NAMES.contains(&name.as_str()) {
+                return Err(LuaError::external(format!(
+                    "Method '{}' conflicts with a field of the same name",
+                    name
+                )));
+            }
+        }
+
+        Ok(Self { def, methods })
+    }
+
+    /// Allocate a zeroed instance, setting any fields given in `init`
+    pub fn instantiate(&self, lua: &Lua, init: Option<LuaTable>) -> LuaResult<StructInstance> {
+        let buffer = Buffer::new_aligned(self.def.size, self.def.alignment)?;
+        let ptr = RawPointer {
+            addr: buffer.as_ptr().cast(),
+            arena_id: 0,
+            size_hint: self.def.size,
+        };
+        let instance = StructInstance {
+            view: StructView::new(&ptr, self.def.clone()),
+            methods: self.methods.clone(),
+            _buffer: buffer,
+        };
+
+        if let Some(init) = init {
+            for pair in init.pairs::<String, LuaValue>() {
+                let (name, value) = pair?;
+                instance.view.write_field(lua, &name, value)?;
+            }
+        }
+
+        Ok(instance)
+    }
+}
+
+impl LuaUserData for StructClass {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("size", |_, this| Ok(this.def.size));
+        fields.add_field_method_get("definition", |_, this| Ok(this.def.clone()));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Class(init?) -> StructInstance
+        methods.add_meta_method(LuaMetaMethod::Call, |lua, this, init: Option<LuaTable>| {
+            this.instantiate(lua, init)
+        });
+
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(format!(
+                "StructClass(size={}, fields={})",
+                this.def.size,
+                this.def.fields.len()
+            ))
+        });
+    }
+}
+
+/// An instance of a struct class, owning the memory its fields live in
+pub struct StructInstance {
+    pub(crate) view: StructView,
+    methods: LuaTable,
+    _buffer: Buffer,
+}
+
+impl LuaUserData for StructInstance {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Fields first, then methods: instance.health, instance:heal(10)
+        methods.add_meta_function(
+            LuaMetaMethod::Index,
+            |lua, (ud, key): (LuaAnyUserData, String)| {
+                let this = ud.borrow::<StructInstance>()?;
+                match key.as_str() {
+                    "ptr" => {
+                        return RawPointer {
+                            addr: this.view.ptr,
+                            arena_id: 0,
+                            size_hint: this.view.def.size,
+                        }
+                        .into_lua(lua);
+                    }
+                    "size" => return Ok(LuaValue::Integer(this.view.def.size as i64)),
+                    "addr" => return Ok(LuaValue::Integer(this.view.ptr as usize as i64)),
+                    _ => {}
+                }
+                if this.view.def.get_field(&key).is_some() {
+                    let value = this.view.read_field(lua, &key)?;
+                    keep_owner_alive(&value, &ud)?;
+                    return Ok(value);
+                }
+                match this.methods.raw_get::<LuaValue>(key.as_str())? {
+                    LuaValue::Nil => Err(LuaError::external(format!("Unknown field: {}", key))),
+                    method => Ok(method),
+                }
+            },
+        );
+
+        methods.add_meta_method(
+            LuaMetaMethod::NewIndex,
+            |lua, this, (key, value): (String, LuaValue)| this.view.write_field(lua, &key, value),
+        );
+
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(format!(
+                "StructInstance(0x{:x}, size={})",
+                this.view.ptr as usize, this.view.def.size
+            ))
+        });
+    }
+}
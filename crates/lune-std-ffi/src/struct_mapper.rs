@@ -1,14 +1,53 @@
 //! Struct layout mapper for C-ABI compliant memory access.
 //!
 //! Parses field definitions and calculates proper offsets with padding.
+//!
+//! Bitfields follow the System V (GCC / Clang) rules on every platform: a
+//! bitfield is packed into the storage unit of its declared type right after
+//! the previous one, and only moves on to the next unit when it would
+//! otherwise straddle a unit boundary. Bits are allocated from the least
+//! significant bit on little-endian hosts, and from the most significant bit
+//! on big-endian hosts. MSVC instead starts a new unit whenever the declared
+//! type changes, so structs mixing bitfield types may not match its layout.
 
 use mlua::prelude::*;
 use std::collections::HashMap;
 use std::ffi::c_void;
 
 use crate::pointer::RawPointer;
+use crate::struct_class::StructInstance;
 use crate::types::CType;
 
+/// Byte order of a struct field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Native,
+    Little,
+    Big,
+}
+
+impl Endian {
+    /// Parse an endianness tag ("le", "be", "native")
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "native" | "ne" => Some(Self::Native),
+            "le" | "little" => Some(Self::Little),
+            "be" | "big" => Some(Self::Big),
+            _ => None,
+        }
+    }
+
+    /// Whether values with this byte order must be swapped on this host
+    pub fn needs_swap(self) -> bool {
+        match self {
+            Self::Native => false,
+            Self::Little => cfg!(target_endian = "big"),
+            Self::Big => cfg!(target_endian = "little"),
+        }
+    }
+}
+
 /// A field in a struct definition
 #[derive(Debug, Clone)]
 pub struct StructField {
@@ -18,6 +57,78 @@ pub struct StructField {
     pub size: usize,
     /// For fixed arrays: [u8; 32] has array_len = 32
     pub array_len: Option<usize>,
+    /// Byte order used when reading and writing this field
+    pub endian: Endian,
+    /// For bitfields: where the bits sit within the storage unit at `offset`
+    pub bitfield: Option<Bitfield>,
+    /// For fields declared with `ffi.ptrTo`: the struct the pointer points at
+    pub pointee: Option<Pointee>,
+    /// For struct fields: the layout of the embedded struct, of each element for arrays
+    pub nested: Option<Box<StructDefinition>>,
+}
+
+impl StructField {
+    /// Alignment the field was placed with, that of its element type for arrays.
+    #[must_use]
+    pub fn alignment(&self) -> usize {
+        match &self.nested {
+            Some(def) => def.alignment,
+            None => self.ctype.alignment(),
+        }
+    }
+}
+
+/// A field as declared, before it is placed by [`StructDefinition::from_fields`]
+#[derive(Debug, Clone)]
+pub struct FieldSpec {
+    pub name: String,
+    pub ctype: CType,
+    pub array_len: Option<usize>,
+    pub endian: Endian,
+    /// Width in bits, for bitfields
+    pub bits: Option<u32>,
+    pub pointee: Option<Pointee>,
+    /// For struct fields, embedded by value
+    pub nested: Option<StructDefinition>,
+}
+
+/// Target of a pointer-to-struct field, created via `ffi.ptrTo`
+#[derive(Debug, Clone)]
+pub enum Pointee {
+    /// A separately defined struct
+    Struct(Box<StructDefinition>),
+    /// The struct that contains the field, for linked lists and trees
+    SelfRef,
+}
+
+impl LuaUserData for Pointee {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(match this {
+                Self::Struct(def) => format!("ptrTo(StructDefinition(size={}))", def.size),
+                Self::SelfRef => "ptrTo(self)".to_owned(),
+            })
+        });
+    }
+}
+
+/// Position of a bitfield within its storage unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bitfield {
+    /// Bits between the least significant bit of the unit and the field
+    pub shift: u32,
+    /// Width of the field in bits
+    pub width: u32,
+}
+
+impl Bitfield {
+    fn mask(self) -> u64 {
+        if self.width >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << self.width) - 1
+        }
+    }
 }
 
 /// A compiled struct definition with layout info
@@ -28,41 +139,151 @@ pub struct StructDefinition {
     pub field_map: HashMap<String, usize>,
     pub size: usize,
     pub alignment: usize,
+    /// Whether this is the layout of a union, with every field at offset 0
+    pub is_union: bool,
 }
 
 impl StructDefinition {
+    /// Bytes of padding after each field, before the next one starts.
+    ///
+    /// Bitfields sharing a storage unit have none between them, and the
+    /// last field is followed by the trailing padding of the struct. Union
+    /// members are each followed by the bytes up to the end of the union.
+    pub fn padding(&self) -> Vec<usize> {
+        self.fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let next = match self.fields.get(i + 1) {
+                    Some(f) if !self.is_union => f.offset,
+                    _ => self.size,
+                };
+                next.saturating_sub(field.offset + field.size)
+            })
+            .collect()
+    }
+
+    /// Bytes of padding after the end of the last field, up to the struct size.
+    pub fn trailing_padding(&self) -> usize {
+        let end = self
+            .fields
+            .iter()
+            .map(|f| f.offset + f.size)
+            .max()
+            .unwrap_or(0);
+        self.size.saturating_sub(end)
+    }
+
     /// Parse a schema table into a struct definition
     ///
     /// Schema format: { {"name", "type"}, {"name2", "type2"}, ... }
     /// Or with arrays: { {"name", "u8", 32}, ... } for fixed arrays
+    /// Or with byte order: { {"port", "u16", "be"}, {"ports", "u16", 4, "be"}, ... }
+    /// Or as bitfields: { {"flags", "u32", {bits = 3}}, ... }
+    /// Or as nested structs: { {"origin", PointDef}, {"corners", PointDef, 4}, ... }
     pub fn from_schema(_lua: &Lua, schema: LuaTable) -> LuaResult<Self> {
+        Self::from_fields(None, parse_schema(schema)?)
+    }
+
+    /// Parse a schema table into a union definition, same format as [`Self::from_schema`]
+    pub fn union_from_schema(_lua: &Lua, schema: LuaTable) -> LuaResult<Self> {
+        Self::union_from_fields(None, parse_schema(schema)?)
+    }
+
+    /// Lay out fields in declaration order, with C-ABI padding and alignment
+    pub fn from_fields(name: Option<String>, specs: Vec<FieldSpec>) -> LuaResult<Self> {
+        Self::layout(name, specs, false)
+    }
+
+    /// Lay out fields on top of each other, as the members of a union
+    pub fn union_from_fields(name: Option<String>, specs: Vec<FieldSpec>) -> LuaResult<Self> {
+        Self::layout(name, specs, true)
+    }
+
+    /// Accept either a struct or a union definition from Lua
+    pub fn from_userdata(ud: &LuaAnyUserData) -> LuaResult<Self> {
+        if let Ok(def) = ud.borrow::<StructDefinition>() {
+            Ok(def.clone())
+        } else if let Ok(def) = ud.borrow::<UnionDefinition>() {
+            Ok(def.0.clone())
+        } else {
+            Err(LuaError::external(
+                "Expected a StructDefinition or UnionDefinition",
+            ))
+        }
+    }
+
+    fn layout(name: Option<String>, specs: Vec<FieldSpec>, is_union: bool) -> LuaResult<Self> {
         let mut fields = Vec::new();
         let mut field_map = HashMap::new();
-        let mut offset = 0usize;
+        // Tracked in bits so that consecutive bitfields can share a byte
+        let mut bit_pos = 0usize;
+        let mut end_bits = 0usize;
         let mut max_align = 1usize;
 
-        for pair in schema.sequence_values::<LuaTable>() {
-            let field_def = pair?;
+        for spec in specs {
+            // Union members all start at the beginning
+            if is_union {
+                bit_pos = 0;
+            }
 
-            // Get field name
-            let name: String = field_def.get(1)?;
+            let FieldSpec {
+                name,
+                ctype,
+                array_len,
+                endian,
+                bits,
+                pointee,
+                nested,
+            } = spec;
 
-            // Get field type
-            let type_val: LuaValue = field_def.get(2)?;
-            let ctype = match type_val {
-                LuaValue::String(s) => {
-                    let type_str = s.to_str()?;
-                    CType::from_str(&type_str)
-                        .ok_or_else(|| LuaError::external(format!("Unknown type: {}", type_str)))?
-                }
-                _ => return Err(LuaError::external("Field type must be a string")),
-            };
+            if nested.is_some() && (bits.is_some() || endian != Endian::Native) {
+                return Err(LuaError::external(format!(
+                    "Struct field '{}' cannot be a bitfield or have an explicit endianness",
+                    name
+                )));
+            }
+
+            if endian != Endian::Native
+                && matches!(ctype, CType::Pointer | CType::CString | CType::Void)
+            {
+                return Err(LuaError::external(format!(
+                    "Field '{}' of type {:?} cannot have an explicit endianness",
+                    name, ctype
+                )));
+            }
+
+            if pointee.is_some() && array_len.is_some() {
+                return Err(LuaError::external(format!(
+                    "Pointer field '{}' cannot be an array",
+                    name
+                )));
+            }
 
-            // Check for array length (optional 3rd element)
-            let array_len: Option<usize> = field_def.get(3).ok();
+            let (field_size, field_align) = match &nested {
+                Some(def) => (def.size, def.alignment),
+                None => (ctype.size(), ctype.alignment()),
+            };
 
-            let field_size = ctype.size();
-            let field_align = ctype.alignment();
+            if let Some(width) = bits {
+                let bitfield =
+                    place_bitfield(&name, ctype, width, array_len, endian, &mut bit_pos)?;
+                field_map.insert(name.clone(), fields.len());
+                fields.push(StructField {
+                    name,
+                    ctype,
+                    offset: bitfield.0,
+                    size: field_size,
+                    array_len: None,
+                    endian,
+                    bitfield: Some(bitfield.1),
+                    pointee: None,
+                    nested: None,
+                });
+                end_bits = end_bits.max(bit_pos);
+                max_align = max_align.max(field_align);
+                continue;
+            }
 
             // Calculate actual size (considering arrays)
             let actual_size = if let Some(len) = array_len {
@@ -71,7 +292,8 @@ impl StructDefinition {
                 field_size
             };
 
-            // Align offset
+            // Align offset, after any partially used byte from bitfields
+            let mut offset = bit_pos.div_ceil(8);
             let padding = (field_align - (offset % field_align)) % field_align;
             offset += padding;
 
@@ -83,22 +305,29 @@ impl StructDefinition {
                 offset,
                 size: actual_size,
                 array_len,
+                endian,
+                bitfield: None,
+                pointee,
+                nested: nested.map(Box::new),
             });
 
-            offset += actual_size;
+            bit_pos = (offset + actual_size) * 8;
+            end_bits = end_bits.max(bit_pos);
             max_align = max_align.max(field_align);
         }
 
-        // Final struct size with trailing padding
+        // Final size with trailing padding, after the largest member for unions
+        let offset = end_bits.div_ceil(8);
         let trailing_padding = (max_align - (offset % max_align)) % max_align;
         let total_size = offset + trailing_padding;
 
         Ok(Self {
-            name: None,
+            name,
             fields,
             field_map,
             size: total_size,
             alignment: max_align,
+            is_union,
         })
     }
 
@@ -107,67 +336,453 @@ impl StructDefinition {
         self.field_map.get(name).map(|&i| &self.fields[i])
     }
 
+    /// Resolve a possibly dotted field path (`"a.b.c"`) to its absolute
+    /// byte offset and the innermost field
+    pub fn resolve_path(&self, path: &str) -> LuaResult<(usize, &StructField)> {
+        let mut segments = path.split('.');
+        let mut name = segments.next().unwrap_or_default();
+        let mut def = self;
+        let mut offset = 0;
+
+        loop {
+            let field = def
+                .get_field(name)
+                .ok_or_else(|| LuaError::external(format!("Unknown field: {}", name)))?;
+            offset += field.offset;
+
+            let Some(next) = segments.next() else {
+                return Ok((offset, field));
+            };
+            match &field.nested {
+                Some(nested) if field.array_len.is_none() => {
+                    def = nested;
+                    name = next;
+                }
+                Some(_) => {
+                    return Err(LuaError::external(format!(
+                        "Cannot access '{}' in field '{}', it is an array of structs",
+                        next, name
+                    )));
+                }
+                None => {
+                    return Err(LuaError::external(format!(
+                        "Cannot access '{}' in field '{}', it is not a struct",
+                        next, name
+                    )));
+                }
+            }
+        }
+    }
+
     /// Get field by index
     pub fn get_field_by_index(&self, index: usize) -> Option<&StructField> {
         self.fields.get(index)
     }
 }
 
+/// Parse the field entries of a struct or union schema table
+fn parse_schema(schema: LuaTable) -> LuaResult<Vec<FieldSpec>> {
+    let mut specs = Vec::new();
+
+    for pair in schema.sequence_values::<LuaTable>() {
+        let field_def = pair?;
+
+        // Get field name
+        let name: String = field_def.get(1)?;
+
+        // Get field type
+        let type_val: LuaValue = field_def.get(2)?;
+        let mut pointee = None;
+        let mut nested = None;
+        let ctype = match type_val {
+            LuaValue::String(s) => {
+                let type_str = s.to_str()?;
+                CType::from_str(&type_str)
+                    .ok_or_else(|| LuaError::external(format!("Unknown type: {}", type_str)))?
+            }
+            LuaValue::UserData(ud) if ud.is::<Pointee>() => {
+                pointee = Some(ud.borrow::<Pointee>()?.clone());
+                CType::Pointer
+            }
+            LuaValue::UserData(ud) if ud.is::<StructDefinition>() || ud.is::<UnionDefinition>() => {
+                nested = Some(StructDefinition::from_userdata(&ud)?);
+                CType::Void
+            }
+            _ => {
+                return Err(LuaError::external(
+                    "Field type must be a string, a StructDefinition, a UnionDefinition or ffi.ptrTo(...)",
+                ));
+            }
+        };
+
+        // Check for array length and/or endianness (optional 3rd and 4th elements)
+        let mut array_len: Option<usize> = None;
+        let mut endian = Endian::Native;
+        let mut bits: Option<u32> = None;
+        for extra in [field_def.get::<LuaValue>(3)?, field_def.get::<LuaValue>(4)?] {
+            match extra {
+                LuaValue::Nil => {}
+                LuaValue::Integer(n) if n >= 0 && array_len.is_none() => {
+                    array_len = Some(n as usize);
+                }
+                LuaValue::Number(n) if n >= 0.0 && n.fract() == 0.0 && array_len.is_none() => {
+                    array_len = Some(n as usize);
+                }
+                LuaValue::Table(t) if bits.is_none() => {
+                    bits = Some(t.get::<u32>("bits").map_err(|_| {
+                        LuaError::external(format!(
+                            "Invalid bitfield for '{}', expected {{ bits = number }}",
+                            name
+                        ))
+                    })?);
+                }
+                LuaValue::String(s) => {
+                    let tag = s.to_str()?;
+                    endian = Endian::from_str(&tag).ok_or_else(|| {
+                        LuaError::external(format!(
+                            "Invalid endianness '{}' for field '{}', expected 'le', 'be' or 'native'",
+                            tag, name
+                        ))
+                    })?;
+                }
+                _ => {
+                    return Err(LuaError::external(format!(
+                        "Invalid field definition for '{}'",
+                        name
+                    )));
+                }
+            }
+        }
+
+        specs.push(FieldSpec {
+            name,
+            ctype,
+            array_len,
+            endian,
+            bits,
+            pointee,
+            nested,
+        });
+    }
+
+    Ok(specs)
+}
+
+/// Place a bitfield at `bit_pos`, returning the byte offset of its storage
+/// unit and its position within it, and advancing `bit_pos` past it.
+fn place_bitfield(
+    name: &str,
+    ctype: CType,
+    width: u32,
+    array_len: Option<usize>,
+    endian: Endian,
+    bit_pos: &mut usize,
+) -> LuaResult<(usize, Bitfield)> {
+    let is_integer = matches!(
+        ctype,
+        CType::Bool
+            | CType::I8
+            | CType::U8
+            | CType::I16
+            | CType::U16
+            | CType::I32
+            | CType::U32
+            | CType::I64
+            | CType::U64
+            | CType::ISize
+            | CType::USize
+    );
+    if !is_integer {
+        return Err(LuaError::external(format!(
+            "Bitfield '{}' must have an integer or bool type, got {:?}",
+            name, ctype
+        )));
+    }
+    if array_len.is_some() || endian != Endian::Native {
+        return Err(LuaError::external(format!(
+            "Bitfield '{}' cannot be an array or have an explicit endianness",
+            name
+        )));
+    }
+
+    let unit_bits = ctype.size() * 8;
+    if width == 0 || width as usize > unit_bits {
+        return Err(LuaError::external(format!(
+            "Bitfield '{}' must be between 1 and {} bits wide, got {}",
+            name, unit_bits, width
+        )));
+    }
+
+    // Move to the next storage unit rather than straddle a unit boundary
+    if *bit_pos % unit_bits + width as usize > unit_bits {
+        *bit_pos = bit_pos.next_multiple_of(unit_bits);
+    }
+
+    let offset = *bit_pos / unit_bits * ctype.size();
+    let bit_offset = (*bit_pos % unit_bits) as u32;
+    let shift = if cfg!(target_endian = "big") {
+        unit_bits as u32 - bit_offset - width
+    } else {
+        bit_offset
+    };
+    *bit_pos += width as usize;
+
+    Ok((offset, Bitfield { shift, width }))
+}
+
+fn is_signed(ctype: CType) -> bool {
+    matches!(
+        ctype,
+        CType::I8 | CType::I16 | CType::I32 | CType::I64 | CType::ISize
+    )
+}
+
+/// Read a whole bitfield storage unit, which need not be aligned
+fn read_unit(ptr: *const u8, size: usize) -> u64 {
+    unsafe {
+        match size {
+            1 => u64::from(ptr.read()),
+            2 => u64::from(ptr.cast::<u16>().read_unaligned()),
+            4 => u64::from(ptr.cast::<u32>().read_unaligned()),
+            _ => ptr.cast::<u64>().read_unaligned(),
+        }
+    }
+}
+
+/// Write a whole bitfield storage unit, which need not be aligned
+fn write_unit(ptr: *mut u8, size: usize, unit: u64) {
+    unsafe {
+        match size {
+            1 => ptr.write(unit as u8),
+            2 => ptr.cast::<u16>().write_unaligned(unit as u16),
+            4 => ptr.cast::<u32>().write_unaligned(unit as u32),
+            _ => ptr.cast::<u64>().write_unaligned(unit),
+        }
+    }
+}
+
+fn read_bitfield(ptr: *const u8, ctype: CType, bitfield: Bitfield) -> LuaValue {
+    let raw = (read_unit(ptr, ctype.size()) >> bitfield.shift) & bitfield.mask();
+    if ctype == CType::Bool {
+        LuaValue::Boolean(raw != 0)
+    } else if is_signed(ctype) {
+        // Sign-extend from the top bit of the field
+        let unused = 64 - bitfield.width;
+        LuaValue::Integer(((raw << unused) as i64) >> unused)
+    } else {
+        LuaValue::Integer(raw as i64)
+    }
+}
+
+fn write_bitfield(
+    ptr: *mut u8,
+    field: &StructField,
+    bitfield: Bitfield,
+    value: &LuaValue,
+) -> LuaResult<()> {
+    let raw = match value {
+        LuaValue::Boolean(b) if field.ctype == CType::Bool => u64::from(*b),
+        LuaValue::Integer(_) | LuaValue::Number(_) => {
+            let n = match value {
+                LuaValue::Integer(i) => *i,
+                LuaValue::Number(n) if n.fract() == 0.0 => *n as i64,
+                _ => {
+                    return Err(LuaError::external(format!(
+                        "Bitfield '{}' expects an integer",
+                        field.name
+                    )));
+                }
+            };
+            let width = bitfield.width;
+            let (min, max) = if is_signed(field.ctype) {
+                let half = 1i128 << (width - 1);
+                (-half, half - 1)
+            } else {
+                (0, (1i128 << width) - 1)
+            };
+            if i128::from(n) < min || i128::from(n) > max {
+                return Err(LuaError::external(format!(
+                    "Value {} does not fit in {}-bit field '{}'",
+                    n, width, field.name
+                )));
+            }
+            n as u64 & bitfield.mask()
+        }
+        _ => {
+            return Err(LuaError::external(format!(
+                "Bitfield '{}' expects {}, got {}",
+                field.name,
+                if field.ctype == CType::Bool {
+                    "a boolean"
+                } else {
+                    "an integer"
+                },
+                value.type_name()
+            )));
+        }
+    };
+
+    let size = field.ctype.size();
+    let mask = bitfield.mask() << bitfield.shift;
+    let unit = read_unit(ptr, size);
+    write_unit(ptr, size, (unit & !mask) | (raw << bitfield.shift));
+    Ok(())
+}
+
+// ============================================================================
+// UnionDefinition - A struct layout whose fields all share offset 0
+// ============================================================================
+
+/// A compiled union definition, laid out by [`StructDefinition::union_from_fields`]
+#[derive(Debug, Clone)]
+pub struct UnionDefinition(pub StructDefinition);
+
+impl AsRef<StructDefinition> for StructDefinition {
+    fn as_ref(&self) -> &StructDefinition {
+        self
+    }
+}
+
+impl AsRef<StructDefinition> for UnionDefinition {
+    fn as_ref(&self) -> &StructDefinition {
+        &self.0
+    }
+}
+
 impl LuaUserData for StructDefinition {
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
-        fields.add_field_method_get("size", |_, this| Ok(this.size));
-        fields.add_field_method_get("alignment", |_, this| Ok(this.alignment));
-        fields.add_field_method_get("fieldCount", |_, this| Ok(this.fields.len()));
+        add_definition_fields(fields);
     }
 
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        // Get offset of a field
-        methods.add_method("offsetOf", |_, this, name: String| {
-            this.get_field(&name)
-                .map(|f| f.offset)
-                .ok_or_else(|| LuaError::external(format!("Unknown field: {}", name)))
-        });
-
-        // Get size of a field
-        methods.add_method("sizeOf", |_, this, name: String| {
-            this.get_field(&name)
-                .map(|f| f.size)
-                .ok_or_else(|| LuaError::external(format!("Unknown field: {}", name)))
-        });
+        add_definition_methods(methods);
+    }
+}
 
-        // Get all field names
-        methods.add_method("fields", |lua, this, ()| {
-            let names: Vec<String> = this.fields.iter().map(|f| f.name.clone()).collect();
-            lua.create_sequence_from(names)
-        });
+impl LuaUserData for UnionDefinition {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        add_definition_fields(fields);
+    }
 
-        // ToString
-        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
-            let fields_str: Vec<String> = this
-                .fields
-                .iter()
-                .map(|f| {
-                    if let Some(len) = f.array_len {
-                        format!("  {} {:?}[{}] @ {}", f.name, f.ctype, len, f.offset)
-                    } else {
-                        format!("  {} {:?} @ {}", f.name, f.ctype, f.offset)
-                    }
-                })
-                .collect();
-            Ok(format!(
-                "StructDefinition(size={}, align={}) {{\n{}\n}}",
-                this.size,
-                this.alignment,
-                fields_str.join("\n")
-            ))
-        });
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        add_definition_methods(methods);
     }
 }
 
+fn add_definition_fields<T, F>(fields: &mut F)
+where
+    T: AsRef<StructDefinition>,
+    F: LuaUserDataFields<T>,
+{
+    fields.add_field_method_get("size", |_, this| Ok(this.as_ref().size));
+    fields.add_field_method_get("alignment", |_, this| Ok(this.as_ref().alignment));
+    fields.add_field_method_get("fieldCount", |_, this| Ok(this.as_ref().fields.len()));
+}
+
+fn add_definition_methods<T, M>(methods: &mut M)
+where
+    T: AsRef<StructDefinition>,
+    M: LuaUserDataMethods<T>,
+{
+    // Get offset of a field
+    methods.add_method("offsetOf", |_, this, name: String| {
+        this.as_ref().resolve_path(&name).map(|(offset, _)| offset)
+    });
+
+    // Get size of a field
+    methods.add_method("sizeOf", |_, this, name: String| {
+        this.as_ref().resolve_path(&name).map(|(_, f)| f.size)
+    });
+
+    // layout() -> ({ { name, offset, size, paddingAfter } }, trailingPadding)
+    methods.add_method("layout", |lua, this, ()| {
+        let this = this.as_ref();
+        let entries = lua.create_table_with_capacity(this.fields.len(), 0)?;
+        for (i, (field, padding)) in this.fields.iter().zip(this.padding()).enumerate() {
+            let entry = lua.create_table_with_capacity(0, 4)?;
+            entry.set("name", field.name.as_str())?;
+            entry.set("offset", field.offset)?;
+            entry.set("size", field.size)?;
+            entry.set("paddingAfter", padding)?;
+            entries.set(i + 1, entry)?;
+        }
+        Ok((entries, this.trailing_padding()))
+    });
+
+    // Get alignment of a field
+    methods.add_method("alignOf", |_, this, name: String| {
+        this.as_ref()
+            .resolve_path(&name)
+            .map(|(_, f)| f.alignment())
+    });
+
+    // Get all field names
+    methods.add_method("fields", |lua, this, ()| {
+        let names: Vec<String> = this
+            .as_ref()
+            .fields
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        lua.create_sequence_from(names)
+    });
+
+    // ToString
+    methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+        let this = this.as_ref();
+        let fields_str: Vec<String> = this
+            .fields
+            .iter()
+            .map(|f| {
+                let endian = match f.endian {
+                    Endian::Native => "",
+                    Endian::Little => " le",
+                    Endian::Big => " be",
+                };
+                let len = f
+                    .array_len
+                    .map(|len| format!("[{}]", len))
+                    .unwrap_or_default();
+                if let Some(nested) = &f.nested {
+                    let kind = if nested.is_union { "union" } else { "struct" };
+                    format!(
+                        "  {} {}(size={}){} @ {}",
+                        f.name, kind, nested.size, len, f.offset
+                    )
+                } else if let Some(bitfield) = f.bitfield {
+                    format!(
+                        "  {} {:?} : {} @ {}.{}",
+                        f.name, f.ctype, bitfield.width, f.offset, bitfield.shift
+                    )
+                } else if f.array_len.is_some() {
+                    format!("  {} {:?}{}{} @ {}", f.name, f.ctype, len, endian, f.offset)
+                } else {
+                    format!("  {} {:?}{} @ {}", f.name, f.ctype, endian, f.offset)
+                }
+            })
+            .collect();
+        Ok(format!(
+            "{}(size={}, align={}) {{\n{}\n}}",
+            if this.is_union {
+                "UnionDefinition"
+            } else {
+                "StructDefinition"
+            },
+            this.size,
+            this.alignment,
+            fields_str.join("\n")
+        ))
+    });
+}
+
 // ============================================================================
 // StructView - Runtime access to struct fields via pointer
 // ============================================================================
 
+/// Scratch space for byte swapping, aligned so any field type can be read from it
+#[repr(align(8))]
+struct SwapSlot([u8; 8]);
+
 /// A view into a struct at a memory location
 pub struct StructView {
     pub ptr: *mut c_void,
@@ -185,6 +800,15 @@ impl StructView {
         }
     }
 
+    /// Hand the view to Lua, as a `UnionView` if it has a union layout
+    pub fn into_lua_view(self, lua: &Lua) -> LuaResult<LuaAnyUserData> {
+        if self.def.is_union {
+            lua.create_userdata(UnionView(self))
+        } else {
+            lua.create_userdata(self)
+        }
+    }
+
     /// Read a field by name
     pub fn read_field(&self, lua: &Lua, name: &str) -> LuaResult<LuaValue> {
         let field = self
@@ -193,6 +817,43 @@ impl StructView {
             .ok_or_else(|| LuaError::external(format!("Unknown field: {}", name)))?;
 
         let ptr = unsafe { self.ptr.cast::<u8>().add(field.offset) };
+        if let Some(nested) = &field.nested {
+            // Views into this struct's memory, one per element for arrays
+            let view = |i: usize| StructView {
+                ptr: unsafe { ptr.add(i * nested.size) }.cast(),
+                def: (**nested).clone(),
+                arena_id: self.arena_id,
+            };
+            return match field.array_len {
+                None => view(0).into_lua_view(lua).map(LuaValue::UserData),
+                Some(len) => {
+                    let views = lua.create_table_with_capacity(len, 0)?;
+                    for i in 0..len {
+                        views.raw_push(view(i).into_lua_view(lua)?)?;
+                    }
+                    Ok(LuaValue::Table(views))
+                }
+            };
+        }
+        if let Some(bitfield) = field.bitfield {
+            return Ok(read_bitfield(ptr, field.ctype, bitfield));
+        }
+        if let Some(pointee) = &field.pointee {
+            let def = match pointee {
+                Pointee::Struct(def) => (**def).clone(),
+                Pointee::SelfRef => self.def.clone(),
+            };
+            let addr = unsafe { ptr.cast::<*mut c_void>().read_unaligned() };
+            return StructPointer { addr, def }.into_lua(lua);
+        }
+        if field.endian.needs_swap() {
+            // Swap into a scratch slot, then decode as a native value
+            let size = field.ctype.size();
+            let mut tmp = SwapSlot([0; 8]);
+            unsafe { std::ptr::copy_nonoverlapping(ptr, tmp.0.as_mut_ptr(), size) };
+            tmp.0[..size].reverse();
+            return crate::pointer::read_value_at(lua, tmp.0.as_mut_ptr(), field.ctype);
+        }
         crate::pointer::read_value_at(lua, ptr, field.ctype)
     }
 
@@ -204,83 +865,336 @@ impl StructView {
             .ok_or_else(|| LuaError::external(format!("Unknown field: {}", name)))?;
 
         let ptr = unsafe { self.ptr.cast::<u8>().add(field.offset) };
+        if let Some(nested) = &field.nested {
+            let Some(len) = field.array_len else {
+                return self.write_nested(lua, ptr, nested, name, value);
+            };
+            let LuaValue::Table(elements) = value else {
+                return Err(LuaError::external(format!(
+                    "Struct array field '{}' expects a table of structs, got {}",
+                    name,
+                    value.type_name()
+                )));
+            };
+            if elements.raw_len() > len {
+                return Err(LuaError::external(format!(
+                    "Struct array field '{}' holds {} structs, got {}",
+                    name,
+                    len,
+                    elements.raw_len()
+                )));
+            }
+            for (i, element) in elements.sequence_values::<LuaValue>().enumerate() {
+                let element_ptr = unsafe { ptr.add(i * nested.size) };
+                self.write_nested(lua, element_ptr, nested, name, element?)?;
+            }
+            return Ok(());
+        }
+        if let Some(bitfield) = field.bitfield {
+            return write_bitfield(ptr, field, bitfield, &value);
+        }
+        if field.pointee.is_some() {
+            let addr = struct_pointer_target(&value).ok_or_else(|| {
+                LuaError::external(format!(
+                    "Pointer field '{}' expects a StructView, pointer or nil, got {}",
+                    name,
+                    value.type_name()
+                ))
+            })?;
+            unsafe { ptr.cast::<*mut c_void>().write_unaligned(addr) };
+            return Ok(());
+        }
+        if field.endian.needs_swap() {
+            // Encode as a native value in a scratch slot, then swap into place
+            let size = field.ctype.size();
+            let mut tmp = SwapSlot([0; 8]);
+            crate::pointer::write_value_at(lua, tmp.0.as_mut_ptr(), field.ctype, value)?;
+            tmp.0[..size].reverse();
+            unsafe { std::ptr::copy_nonoverlapping(tmp.0.as_ptr(), ptr, size) };
+            return Ok(());
+        }
         crate::pointer::write_value_at(lua, ptr, field.ctype, value)
     }
 
+    /// Write a struct embedded at `ptr`, either copied from another struct
+    /// of the same size or field by field from a table of values
+    fn write_nested(
+        &self,
+        lua: &Lua,
+        ptr: *mut u8,
+        def: &StructDefinition,
+        name: &str,
+        value: LuaValue,
+    ) -> LuaResult<()> {
+        match value {
+            LuaValue::Table(values) => {
+                let view = StructView {
+                    ptr: ptr.cast(),
+                    def: def.clone(),
+                    arena_id: self.arena_id,
+                };
+                for pair in values.pairs::<String, LuaValue>() {
+                    let (key, value) = pair?;
+                    view.write_field(lua, &key, value)?;
+                }
+                Ok(())
+            }
+            LuaValue::UserData(ud) => {
+                let (src, size) = if let Ok(view) = ud.borrow::<StructView>() {
+                    (view.ptr, view.def.size)
+                } else if let Ok(view) = ud.borrow::<UnionView>() {
+                    (view.0.ptr, view.0.def.size)
+                } else if let Ok(instance) = ud.borrow::<StructInstance>() {
+                    (instance.view.ptr, instance.view.def.size)
+                } else {
+                    return Err(LuaError::external(format!(
+                        "Struct field '{}' expects a StructView or a table of field values",
+                        name
+                    )));
+                };
+                if size != def.size {
+                    return Err(LuaError::external(format!(
+                        "Struct field '{}' expects a struct of {} bytes, got {}",
+                        name, def.size, size
+                    )));
+                }
+                // The source may be this very struct, or overlap it
+                unsafe { std::ptr::copy(src.cast::<u8>(), ptr, size) };
+                Ok(())
+            }
+            other => Err(LuaError::external(format!(
+                "Struct field '{}' expects a StructView or a table of field values, got {}",
+                name,
+                other.type_name()
+            ))),
+        }
+    }
+
     /// Get pointer to a field (for arrays or nested structs)
     pub fn field_ptr(&self, name: &str) -> LuaResult<RawPointer> {
         let field = self
             .def
             .get_field(name)
             .ok_or_else(|| LuaError::external(format!("Unknown field: {}", name)))?;
+        if field.bitfield.is_some() {
+            return Err(LuaError::external(format!(
+                "Cannot take a pointer to bitfield '{}'",
+                name
+            )));
+        }
 
         let ptr = unsafe { self.ptr.cast::<u8>().add(field.offset) };
         Ok(RawPointer::managed(ptr.cast(), self.arena_id, field.size))
     }
 }
 
+/// A view into a union at a memory location, with every field at offset 0
+pub struct UnionView(pub StructView);
+
+impl AsRef<StructView> for StructView {
+    fn as_ref(&self) -> &StructView {
+        self
+    }
+}
+
+impl AsMut<StructView> for StructView {
+    fn as_mut(&mut self) -> &mut StructView {
+        self
+    }
+}
+
+impl AsRef<StructView> for UnionView {
+    fn as_ref(&self) -> &StructView {
+        &self.0
+    }
+}
+
+impl AsMut<StructView> for UnionView {
+    fn as_mut(&mut self) -> &mut StructView {
+        &mut self.0
+    }
+}
+
 impl LuaUserData for StructView {
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
-        fields.add_field_method_get("size", |_, this| Ok(this.def.size));
-        fields.add_field_method_get("addr", |_, this| Ok(this.ptr as usize));
+        add_view_fields(fields);
     }
 
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        // Field access via indexing: view.health, view.position
-        methods.add_meta_method(LuaMetaMethod::Index, |lua, this, key: String| {
+        add_view_methods(methods);
+    }
+}
+
+impl LuaUserData for UnionView {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        add_view_fields(fields);
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        add_view_methods(methods);
+    }
+}
+
+fn add_view_fields<T, F>(fields: &mut F)
+where
+    T: AsRef<StructView>,
+    F: LuaUserDataFields<T>,
+{
+    fields.add_field_method_get("size", |_, this| Ok(this.as_ref().def.size));
+    fields.add_field_method_get("addr", |_, this| Ok(this.as_ref().ptr as usize));
+}
+
+fn add_view_methods<T, M>(methods: &mut M)
+where
+    T: AsRef<StructView> + AsMut<StructView> + 'static,
+    M: LuaUserDataMethods<T>,
+{
+    // Field access via indexing: view.health, view.position
+    methods.add_meta_function(
+        LuaMetaMethod::Index,
+        |lua, (ud, key): (LuaAnyUserData, String)| {
+            let this = ud.borrow::<T>()?;
+            let this = (*this).as_ref();
             // Check for built-in properties first
             match key.as_str() {
                 "size" => return Ok(LuaValue::Integer(this.def.size as i64)),
                 "addr" => return Ok(LuaValue::Integer(this.ptr as usize as i64)),
                 _ => {}
             }
-            this.read_field(lua, &key)
-        });
+            let value = this.read_field(lua, &key)?;
+            keep_owner_alive(&value, &ud)?;
+            Ok(value)
+        },
+    );
 
-        // Field assignment: view.health = 100
-        methods.add_meta_method(
-            LuaMetaMethod::NewIndex,
-            |lua, this, (key, value): (String, LuaValue)| this.write_field(lua, &key, value),
-        );
-
-        // Get pointer to a field
-        methods.add_method("fieldPtr", |_, this, name: String| this.field_ptr(&name));
-
-        // pointTo(ptr) - Update the pointer this view points to (zero-GC iteration)
-        methods.add_method_mut("pointTo", |_, this, ptr: LuaValue| {
-            match ptr {
-                LuaValue::UserData(ud) => {
-                    if let Ok(raw) = ud.borrow::<RawPointer>() {
-                        this.ptr = raw.addr;
-                        this.arena_id = raw.arena_id;
-                    } else {
-                        return Err(LuaError::external(
-                            "Expected RawPointer, number, or address",
-                        ));
-                    }
-                }
-                LuaValue::Integer(addr) => {
-                    this.ptr = addr as usize as *mut c_void;
-                    this.arena_id = 0; // Unmanaged
-                }
-                LuaValue::Number(addr) => {
-                    this.ptr = addr as usize as *mut c_void;
-                    this.arena_id = 0; // Unmanaged
-                }
-                LuaValue::LightUserData(lud) => {
-                    this.ptr = lud.0;
-                    this.arena_id = 0; // Unmanaged
+    // Field assignment: view.health = 100
+    methods.add_meta_method(
+        LuaMetaMethod::NewIndex,
+        |lua, this, (key, value): (String, LuaValue)| this.as_ref().write_field(lua, &key, value),
+    );
+
+    // Get pointer to a field
+    methods.add_method("fieldPtr", |_, this, name: String| {
+        this.as_ref().field_ptr(&name)
+    });
+
+    // pointTo(ptr) - Update the pointer this view points to (zero-GC iteration)
+    methods.add_method_mut("pointTo", |_, this, ptr: LuaValue| {
+        let this = this.as_mut();
+        match ptr {
+            LuaValue::UserData(ud) => {
+                if let Ok(raw) = ud.borrow::<RawPointer>() {
+                    this.ptr = raw.addr;
+                    this.arena_id = raw.arena_id;
+                } else {
+                    return Err(LuaError::external(
+                        "Expected RawPointer, number, or address",
+                    ));
                 }
-                _ => return Err(LuaError::external("Expected pointer, number, or address")),
             }
-            Ok(())
+            LuaValue::Integer(addr) => {
+                this.ptr = addr as usize as *mut c_void;
+                this.arena_id = 0; // Unmanaged
+            }
+            LuaValue::Number(addr) => {
+                this.ptr = addr as usize as *mut c_void;
+                this.arena_id = 0; // Unmanaged
+            }
+            LuaValue::LightUserData(lud) => {
+                this.ptr = lud.0;
+                this.arena_id = 0; // Unmanaged
+            }
+            _ => return Err(LuaError::external("Expected pointer, number, or address")),
+        }
+        Ok(())
+    });
+
+    // ToString
+    methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+        let this = this.as_ref();
+        Ok(format!(
+            "{}(0x{:x}, size={})",
+            if this.def.is_union {
+                "UnionView"
+            } else {
+                "StructView"
+            },
+            this.ptr as usize,
+            this.def.size
+        ))
+    });
+}
+
+/// Keep `owner` alive for as long as the nested struct views in `value`,
+/// which point into its memory, are still around
+pub(crate) fn keep_owner_alive(value: &LuaValue, owner: &LuaAnyUserData) -> LuaResult<()> {
+    match value {
+        LuaValue::UserData(ud) if ud.is::<StructView>() || ud.is::<UnionView>() => {
+            ud.set_user_value(owner.clone())
+        }
+        LuaValue::Table(views) => views
+            .sequence_values::<LuaAnyUserData>()
+            .try_for_each(|view| view?.set_user_value(owner.clone())),
+        _ => Ok(()),
+    }
+}
+
+/// Address to store in a pointer-to-struct field
+fn struct_pointer_target(value: &LuaValue) -> Option<*mut c_void> {
+    match value {
+        LuaValue::Nil => Some(std::ptr::null_mut()),
+        LuaValue::LightUserData(lud) => Some(lud.0),
+        LuaValue::UserData(ud) => {
+            if let Ok(view) = ud.borrow::<StructView>() {
+                Some(view.ptr)
+            } else if let Ok(view) = ud.borrow::<UnionView>() {
+                Some(view.0.ptr)
+            } else if let Ok(pointer) = ud.borrow::<StructPointer>() {
+                Some(pointer.addr)
+            } else if let Ok(raw) = ud.borrow::<RawPointer>() {
+                Some(raw.addr)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// ============================================================================
+// StructPointer - Pointer field that can be dereferenced into a StructView
+// ============================================================================
+
+/// The value of a pointer-to-struct field, which knows the layout it points at
+pub struct StructPointer {
+    pub addr: *mut c_void,
+    pub def: StructDefinition,
+}
+
+impl LuaUserData for StructPointer {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("addr", |_, this| Ok(this.addr as usize));
+        fields.add_field_method_get("isNull", |_, this| Ok(this.addr.is_null()));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // deref() - View the pointed-at struct, erroring on null
+        methods.add_method("deref", |lua, this, ()| {
+            if this.addr.is_null() {
+                return Err(LuaError::external(
+                    "Cannot dereference a null struct pointer",
+                ));
+            }
+            // The pointee may live anywhere, so the view is unmanaged
+            let raw = RawPointer::managed(this.addr, 0, this.def.size);
+            StructView::new(&raw, this.def.clone()).into_lua_view(lua)
         });
 
         // ToString
         methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
             Ok(format!(
-                "StructView(0x{:x}, size={})",
-                this.ptr as usize, this.def.size
+                "StructPointer(0x{:x}, size={})",
+                this.addr as usize, this.def.size
             ))
         });
     }
@@ -0,0 +1,118 @@
+//! Best-effort address-to-symbol resolution for FFI debugging.
+//!
+//! Maps a code pointer back to the module it lives in, and to the
+//! nearest exported symbol when the platform can tell us one.
+
+use std::fmt;
+use std::path::Path;
+
+/// Location of an address inside a loaded module.
+#[derive(Debug, Clone)]
+pub struct SymbolInfo {
+    /// Path of the module containing the address
+    pub module: String,
+    /// Offset of the address from the module base
+    pub offset: usize,
+    /// Nearest symbol at or below the address, and the offset from it
+    pub symbol: Option<(String, usize)>,
+}
+
+impl fmt::Display for SymbolInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let module = Path::new(&self.module)
+            .file_name()
+            .map_or_else(|| self.module.clone(), |n| n.to_string_lossy().into_owned());
+        write!(f, "{}+0x{:x}", module, self.offset)?;
+        match &self.symbol {
+            Some((name, 0)) => write!(f, " ({name})"),
+            Some((name, offset)) => write!(f, " ({name}+0x{offset:x})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Resolve `addr` to its module and nearest symbol, if possible.
+pub fn symbolicate(addr: usize) -> Option<SymbolInfo> {
+    if addr == 0 {
+        return None;
+    }
+    resolve(addr)
+}
+
+#[cfg(unix)]
+fn resolve(addr: usize) -> Option<SymbolInfo> {
+    use std::ffi::{CStr, c_void};
+
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    if unsafe { libc::dladdr(addr as *const c_void, &mut info) } == 0 || info.dli_fname.is_null() {
+        return None;
+    }
+
+    let module = unsafe { CStr::from_ptr(info.dli_fname) }
+        .to_string_lossy()
+        .into_owned();
+    let offset = addr.wrapping_sub(info.dli_fbase as usize);
+    let symbol = if info.dli_sname.is_null() || info.dli_saddr.is_null() {
+        None
+    } else {
+        let name = unsafe { CStr::from_ptr(info.dli_sname) }
+            .to_string_lossy()
+            .into_owned();
+        Some((name, addr.wrapping_sub(info.dli_saddr as usize)))
+    };
+
+    Some(SymbolInfo {
+        module,
+        offset,
+        symbol,
+    })
+}
+
+#[cfg(windows)]
+fn resolve(addr: usize) -> Option<SymbolInfo> {
+    use windows_sys::Win32::System::LibraryLoader::{
+        GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+        GetModuleFileNameW, GetModuleHandleExW,
+    };
+
+    let mut handle = std::ptr::null_mut();
+    let flags =
+        GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT;
+    if unsafe { GetModuleHandleExW(flags, addr as *const u16, &mut handle) } == 0 {
+        return None;
+    }
+
+    let mut buf = [0u16; 1024];
+    let len = unsafe { GetModuleFileNameW(handle, buf.as_mut_ptr(), buf.len() as u32) } as usize;
+    if len == 0 {
+        return None;
+    }
+
+    let module = String::from_utf16_lossy(&buf[..len]);
+    let offset = addr.wrapping_sub(handle as usize);
+    let symbol = nearest_pe_export(&module, offset);
+
+    Some(SymbolInfo {
+        module,
+        offset,
+        symbol,
+    })
+}
+
+/// Find the PE export with the greatest RVA at or below `rva`.
+#[cfg(windows)]
+fn nearest_pe_export(path: &str, rva: usize) -> Option<(String, usize)> {
+    let bytes = std::fs::read(path).ok()?;
+    let pe = goblin::pe::PE::parse(&bytes).ok()?;
+    pe.exports
+        .iter()
+        .filter_map(|export| Some((export.name?, export.rva)))
+        .filter(|(_, export_rva)| *export_rva <= rva)
+        .max_by_key(|(_, export_rva)| *export_rva)
+        .map(|(name, export_rva)| (name.to_string(), rva - export_rva))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn resolve(_addr: usize) -> Option<SymbolInfo> {
+    None
+}
@@ -5,6 +5,8 @@ use std::alloc::{Layout, alloc, dealloc};
 use std::ffi::c_void;
 use std::ptr;
 
+use crate::pointer::{RawPointer, TypedPointer};
+
 /// Represents a C type for FFI calls
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CType {
@@ -74,12 +76,14 @@ impl CType {
 }
 
 impl FromLua for CType {
-    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
         match value {
             LuaValue::String(s) => {
                 let borrowed = s.to_str()?;
                 let s: &str = &borrowed;
+                // Types declared through ffi.cdef may be used by name too
                 Self::from_str(s)
+                    .or_else(|| crate::cdef::lookup_scalar(lua, s))
                     .ok_or_else(|| LuaError::external(format!("Unknown C type: '{s}'")))
             }
             _ => Err(LuaError::external("Expected string for CType")),
@@ -87,9 +91,11 @@ impl FromLua for CType {
     }
 }
 
-impl IntoLua for CType {
-    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
-        let name = match self {
+impl CType {
+    /// Canonical name of the type, as accepted by [`CType::from_str`].
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
             Self::Void => "void",
             Self::Bool => "bool",
             Self::I8 => "i8",
@@ -106,8 +112,115 @@ impl IntoLua for CType {
             Self::F64 => "f64",
             Self::Pointer => "pointer",
             Self::CString => "string",
-        };
-        Ok(LuaValue::String(lua.create_string(name)?))
+        }
+    }
+}
+
+impl IntoLua for CType {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        Ok(LuaValue::String(lua.create_string(self.name())?))
+    }
+}
+
+/// Convert an unsigned 64-bit value read from native memory into a Lua value.
+///
+/// Values that fit in an `i64` become integers, like every other integer
+/// type. Larger values cannot be represented exactly and are returned as
+/// their decimal string instead, which [`unsigned_from_lua`] accepts back.
+pub fn unsigned_into_lua(lua: &Lua, v: u64) -> LuaResult<LuaValue> {
+    match i64::try_from(v) {
+        Ok(v) => Ok(LuaValue::Integer(v)),
+        Err(_) => Ok(LuaValue::String(lua.create_string(v.to_string())?)),
+    }
+}
+
+/// Convert a Lua value into an unsigned 64-bit value for native memory.
+///
+/// Accepts numbers (negative numbers wrap, as with C casts) and the decimal
+/// strings produced by [`unsigned_into_lua`].
+pub fn unsigned_from_lua(value: &LuaValue) -> LuaResult<u64> {
+    match value {
+        LuaValue::Integer(i) => Ok(*i as u64),
+        LuaValue::Number(n) if *n < 0.0 => Ok(*n as i64 as u64),
+        LuaValue::Number(n) => Ok(*n as u64),
+        LuaValue::String(s) => {
+            let s = s.to_str()?;
+            s.trim()
+                .parse::<u64>()
+                .map_err(|_| LuaError::external(format!("Invalid unsigned integer: '{}'", &*s)))
+        }
+        _ => Err(LuaError::FromLuaConversionError {
+            from: value.type_name(),
+            to: String::from("u64"),
+            message: None,
+        }),
+    }
+}
+
+/// Default alignment for buffers allocated without an explicit alignment
+pub const DEFAULT_BUFFER_ALIGN: usize = 8;
+
+/// Allocation options accepted by `ffi.buffer`: either an alignment or `{ align?, zero? }`
+#[derive(Debug, Clone, Copy)]
+pub struct BufferOptions {
+    pub align: usize,
+    pub zero: bool,
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        Self {
+            align: DEFAULT_BUFFER_ALIGN,
+            zero: true,
+        }
+    }
+}
+
+impl FromLua for BufferOptions {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Integer(_) | LuaValue::Number(_) => Ok(Self {
+                align: usize::from_lua(value, lua)?,
+                ..Self::default()
+            }),
+            LuaValue::Table(t) => {
+                let defaults = Self::default();
+                Ok(Self {
+                    align: t.get::<Option<usize>>("align")?.unwrap_or(defaults.align),
+                    zero: t.get::<Option<bool>>("zero")?.unwrap_or(defaults.zero),
+                })
+            }
+            _ => Err(LuaError::external(
+                "Expected alignment number or options table for buffer",
+            )),
+        }
+    }
+}
+
+/// Options accepted by `ffi.bufferFromPtr`: `{ copy? }`
+#[derive(Debug, Clone, Copy)]
+pub struct BufferFromPtrOptions {
+    pub copy: bool,
+}
+
+impl Default for BufferFromPtrOptions {
+    fn default() -> Self {
+        Self { copy: true }
+    }
+}
+
+impl FromLua for BufferFromPtrOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Table(t) => Ok(Self {
+                copy: t.get::<Option<bool>>("copy")?.unwrap_or(true),
+            }),
+            _ => Err(LuaError::external(
+                "Expected options table for bufferFromPtr",
+            )),
+        }
     }
 }
 
@@ -115,6 +228,7 @@ impl IntoLua for CType {
 pub struct Buffer {
     ptr: *mut u8,
     size: usize,
+    align: usize,
     owned: bool,
 }
 
@@ -122,14 +236,42 @@ impl Buffer {
     /// Allocate a new buffer of the given size
     #[must_use]
     pub fn new(size: usize) -> Self {
-        let layout = Layout::from_size_align(size.max(1), 8).unwrap();
+        Self::new_aligned(size, DEFAULT_BUFFER_ALIGN).expect("default buffer layout is valid")
+    }
+
+    /// Allocate a new zeroed buffer with an explicit power-of-two alignment
+    pub fn new_aligned(size: usize, align: usize) -> LuaResult<Self> {
+        Self::allocate(size, align, true)
+    }
+
+    /// Allocate a new buffer, optionally skipping zero-initialization.
+    ///
+    /// When `zeroed` is false, the contents are unspecified until written.
+    pub fn allocate(size: usize, align: usize, zeroed: bool) -> LuaResult<Self> {
+        if !align.is_power_of_two() {
+            return Err(LuaError::external(format!(
+                "Buffer alignment must be a power of two, got {}",
+                align
+            )));
+        }
+        let layout = Layout::from_size_align(size.max(1), align)
+            .map_err(|e| LuaError::external(format!("Invalid buffer layout: {}", e)))?;
         let ptr = unsafe { alloc(layout) };
-        unsafe { ptr::write_bytes(ptr, 0, size) };
-        Self {
+        if ptr.is_null() {
+            return Err(LuaError::external(format!(
+                "Failed to allocate {} bytes aligned to {}",
+                size, align
+            )));
+        }
+        if zeroed {
+            unsafe { ptr::write_bytes(ptr, 0, size) };
+        }
+        Ok(Self {
             ptr,
             size,
+            align,
             owned: true,
-        }
+        })
     }
 
     /// Create a buffer from an existing pointer (not owned)
@@ -137,16 +279,64 @@ impl Buffer {
         Self {
             ptr,
             size,
+            align: 1,
             owned: false,
         }
     }
 
+    /// Allocate a new buffer holding a copy of `size` bytes at `ptr`.
+    ///
+    /// The copy stays valid after the memory at `ptr` is freed.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads of `size` bytes.
+    pub unsafe fn copy_from_ptr(ptr: *const u8, size: usize) -> LuaResult<Self> {
+        let buffer = Self::allocate(size, DEFAULT_BUFFER_ALIGN, false)?;
+        unsafe { ptr::copy_nonoverlapping(ptr, buffer.ptr, size) };
+        Ok(buffer)
+    }
+
     /// Get a pointer to the buffer
     #[must_use]
     pub fn as_ptr(&self) -> *mut u8 {
         self.ptr
     }
 
+    /// Get the size of the buffer in bytes
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// View the buffer as an array of `ctype` elements, without copying.
+    ///
+    /// The view is bounded to the whole elements that fit in the buffer.
+    pub fn as_array(&self, ctype: CType) -> LuaResult<TypedPointer> {
+        let stride = ctype.size();
+        if stride == 0 {
+            return Err(LuaError::external(format!(
+                "Cannot view a buffer as an array of {:?}",
+                ctype
+            )));
+        }
+        if self.size < stride {
+            return Err(LuaError::external(format!(
+                "Buffer of {} bytes is too small for a single {:?}",
+                self.size, ctype
+            )));
+        }
+        if !(self.ptr as usize).is_multiple_of(ctype.alignment()) {
+            return Err(LuaError::external(format!(
+                "Buffer at 0x{:x} is not aligned for {:?}",
+                self.ptr as usize, ctype
+            )));
+        }
+
+        let raw = RawPointer::managed(self.ptr.cast(), 0, self.size - self.size % stride);
+        Ok(TypedPointer::new(&raw, ctype))
+    }
+
     /// Read a value of the given type at offset
     pub fn read(&self, lua: &Lua, offset: usize, ctype: CType) -> LuaResult<LuaValue> {
         if offset + ctype.size() > self.size {
@@ -188,7 +378,7 @@ impl Buffer {
             }
             CType::U64 => {
                 let v = unsafe { *(ptr as *const u64) };
-                (v as f64).into_lua(lua)?
+                unsigned_into_lua(lua, v)?
             }
             CType::ISize => {
                 let v = unsafe { *(ptr as *const isize) };
@@ -196,7 +386,7 @@ impl Buffer {
             }
             CType::USize => {
                 let v = unsafe { *(ptr as *const usize) };
-                (v as i64).into_lua(lua)?
+                unsigned_into_lua(lua, v as u64)?
             }
             CType::F32 => {
                 let v = unsafe { *(ptr as *const f32) };
@@ -275,15 +465,15 @@ impl Buffer {
                 unsafe { *ptr.cast::<i64>() = v };
             }
             CType::U64 => {
-                let v: f64 = FromLua::from_lua(value, lua)?;
-                unsafe { *ptr.cast::<u64>() = v as u64 };
+                let v = unsigned_from_lua(&value)?;
+                unsafe { *ptr.cast::<u64>() = v };
             }
             CType::ISize => {
                 let v: i64 = FromLua::from_lua(value, lua)?;
                 unsafe { *ptr.cast::<isize>() = v as isize };
             }
             CType::USize => {
-                let v: i64 = FromLua::from_lua(value, lua)?;
+                let v = unsigned_from_lua(&value)?;
                 unsafe { *ptr.cast::<usize>() = v as usize };
             }
             CType::F32 => {
@@ -338,7 +528,7 @@ impl Buffer {
 impl Drop for Buffer {
     fn drop(&mut self) {
         if self.owned && !self.ptr.is_null() {
-            let layout = Layout::from_size_align(self.size.max(1), 8).unwrap();
+            let layout = Layout::from_size_align(self.size.max(1), self.align).unwrap();
             unsafe { dealloc(self.ptr, layout) };
         }
     }
@@ -350,6 +540,9 @@ impl LuaUserData for Buffer {
         fields.add_field_method_get("ptr", |_, this| {
             Ok(LuaLightUserData(this.ptr.cast::<c_void>()))
         });
+        fields.add_field_method_get("addr", |_, this| Ok(this.ptr as usize));
+        fields.add_field_method_get("align", |_, this| Ok(this.align));
+        fields.add_field_method_get("owned", |_, this| Ok(this.owned));
     }
 
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
@@ -410,6 +603,14 @@ impl LuaUserData for Buffer {
             },
         );
 
+        // asArray(ctype) -> TypedPointer over the buffer's memory, kept alive by the view
+        methods.add_function("asArray", |lua, (ud, ctype): (LuaAnyUserData, CType)| {
+            let view = ud.borrow::<Buffer>()?.as_array(ctype)?;
+            let view = lua.create_userdata(view)?;
+            view.set_user_value(ud)?;
+            Ok(view)
+        });
+
         methods.add_method("slice", |_, this, (offset, size): (usize, usize)| {
             if offset + size > this.size {
                 return Err(LuaError::external("Slice out of bounds"));
@@ -0,0 +1,272 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::SocketAddr,
+    time::Duration,
+};
+
+use async_io::{Async, Timer};
+use async_net::TcpStream;
+use futures::stream::FuturesUnordered;
+use futures_lite::prelude::*;
+use socket2::{Domain, Protocol, Socket, Type};
+
+/**
+    How long to wait on a connection attempt before also
+    starting one to the next address, as recommended by RFC 8305.
+*/
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/**
+    Connects to a host and port, trying every address it resolves to.
+
+    See [`connect_addrs`] for details on how the addresses are raced,
+    and how the `local` address to connect from is used.
+*/
+pub async fn connect_host(host: &str, port: u16, local: Option<SocketAddr>) -> Result<TcpStream> {
+    let addrs = async_net::resolve((host, port)).await?;
+    connect_addrs(addrs, local)
+        .await
+        .map_err(|e| Error::new(e.kind(), format!("Failed to connect to {host}:{port}: {e}")))
+}
+
+/**
+    Connects to the first of the given addresses that accepts, in a
+    "happy eyeballs" fashion.
+
+    Address families are interleaved, and a new attempt is started whenever
+    the previous one fails, or has not completed within [`ATTEMPT_DELAY`].
+    Attempts still in flight are dropped once one succeeds.
+
+    If every attempt fails, the error lists the failure for each address.
+
+    When a `local` address is given, every attempt is made from it, and
+    only addresses of the same family as the local address are tried.
+*/
+pub async fn connect_addrs(addrs: Vec<SocketAddr>, local: Option<SocketAddr>) -> Result<TcpStream> {
+    let addrs = match local {
+        Some(local) => {
+            let matching: Vec<_> = addrs
+                .into_iter()
+                .filter(|addr| addr.is_ipv4() == local.is_ipv4())
+                .collect();
+            if matching.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::AddrNotAvailable,
+                    format!("no addresses of the same family as the bind address {local}"),
+                ));
+            }
+            matching
+        }
+        None => addrs,
+    };
+
+    let mut pending = interleave_families(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut errors = Vec::new();
+
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => attempts.push(attempt(addr, local)),
+                None => break,
+            }
+        }
+
+        let has_pending = pending.len() > 0;
+        let finished = async { attempts.next().await }
+            .or(async {
+                if has_pending {
+                    Timer::after(ATTEMPT_DELAY).await;
+                    None
+                } else {
+                    std::future::pending().await
+                }
+            })
+            .await;
+
+        match finished {
+            Some((_, Ok(stream))) => return Ok(stream),
+            Some((addr, Err(e))) => errors.push((addr, e)),
+            // Attempt delay elapsed, race the next address alongside
+            None => {}
+        }
+
+        // Failures move on right away instead of waiting out the delay
+        if let Some(addr) = pending.next() {
+            attempts.push(attempt(addr, local));
+        }
+    }
+
+    // Keep the kind of the failure, so that a timeout is not reported as a refusal
+    Err(match errors.len() {
+        0 => Error::new(ErrorKind::NotFound, "no addresses to connect to"),
+        1 => errors.remove(0).1,
+        _ => {
+            let kind = errors
+                .last()
+                .map_or(ErrorKind::ConnectionRefused, |(_, e)| e.kind());
+            let failures = errors
+                .iter()
+                .map(|(addr, e)| format!("{addr}: {e}"))
+                .collect::<Vec<_>>();
+            Error::new(
+                kind,
+                format!("all addresses failed ({})", failures.join(", ")),
+            )
+        }
+    })
+}
+
+async fn attempt(addr: SocketAddr, local: Option<SocketAddr>) -> (SocketAddr, Result<TcpStream>) {
+    let result = match local {
+        Some(local) => connect_from(local, addr).await,
+        None => TcpStream::connect(addr).await,
+    };
+    (addr, result)
+}
+
+/**
+    Connects to `addr` from a socket bound to `local` beforehand,
+    which picks the source address and interface of the connection.
+*/
+async fn connect_from(local: SocketAddr, addr: SocketAddr) -> Result<TcpStream> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket
+        .bind(&local.into())
+        .map_err(|e| Error::new(e.kind(), format!("failed to bind to {local}: {e}")))?;
+    socket.set_nonblocking(true)?;
+
+    match socket.connect(&addr.into()) {
+        Ok(()) => {}
+        Err(e) if is_in_progress(&e) => {}
+        Err(e) => return Err(e),
+    }
+
+    // Same as what async-io does for its own connects, the socket
+    // becomes writable once the connection has either succeeded or failed
+    let stream = Async::new(std::net::TcpStream::from(socket))?;
+    stream.writable().await?;
+    if let Some(e) = stream.get_ref().take_error()? {
+        return Err(e);
+    }
+    stream.get_ref().peer_addr()?;
+
+    Ok(TcpStream::from(stream))
+}
+
+fn is_in_progress(e: &Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EINPROGRESS) {
+        return true;
+    }
+    e.kind() == ErrorKind::WouldBlock
+}
+
+/**
+    Reorders addresses so that families alternate, starting
+    with the family of the first (most preferred) address.
+*/
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let prefer_v6 = first.is_ipv6();
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == prefer_v6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Instant};
+
+    use super::*;
+
+    #[test]
+    fn interleaves_address_families() {
+        let v4: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let v6: SocketAddr = "[::1]:1".parse().unwrap();
+        assert_eq!(
+            interleave_families(vec![v6, v6, v6, v4]),
+            vec![v6, v4, v6, v6]
+        );
+        assert_eq!(interleave_families(vec![v4, v4, v6]), vec![v4, v6, v4]);
+    }
+
+    /**
+        Creates a listener that never accepts and whose queue is full,
+        so that further connection attempts to it hang.
+    */
+    fn blackhole() -> (socket2::Socket, Vec<std::net::TcpStream>, SocketAddr) {
+        let socket =
+            socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        socket
+            .bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into())
+            .unwrap();
+        socket.listen(0).unwrap();
+        let addr = socket.local_addr().unwrap().as_socket().unwrap();
+
+        let mut queued = Vec::new();
+        while queued.len() < 16 {
+            match std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+                Ok(stream) => queued.push(stream),
+                Err(_) => break,
+            }
+        }
+        (socket, queued, addr)
+    }
+
+    #[test]
+    fn falls_back_past_blackholed_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let good = listener.local_addr().unwrap();
+        let (_socket, _queued, blackhole) = blackhole();
+
+        let start = Instant::now();
+        let stream = async_io::block_on(connect_addrs(vec![blackhole, good], None)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), good);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn combines_errors_when_all_fail() {
+        let closed = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let other = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let err = async_io::block_on(connect_addrs(vec![closed, other], None)).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains(&closed.to_string()), "{message}");
+        assert!(message.contains(&other.to_string()), "{message}");
+    }
+
+    #[test]
+    fn keeps_error_kind_when_all_fail() {
+        // Binding to an address this host does not have fails before connecting
+        let unassigned: SocketAddr = "192.0.2.1:0".parse().unwrap();
+        let target: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+        let err = async_io::block_on(connect_addrs(vec![target], Some(unassigned))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrNotAvailable);
+
+        let err =
+            async_io::block_on(connect_addrs(vec![target, target], Some(unassigned))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrNotAvailable);
+    }
+}
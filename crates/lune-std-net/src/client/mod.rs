@@ -1,5 +1,6 @@
 use hyper::{Method, Response as HyperResponse, Uri, body::Incoming, header::LOCATION};
 
+use lune_utils::NetworkError;
 use mlua::prelude::*;
 use url::Url;
 
@@ -12,6 +13,7 @@ use crate::{
     shared::{request::Request, tcp::Tcp, websocket::Websocket},
 };
 
+pub mod connect;
 pub mod rustls;
 pub mod stream;
 pub mod tcp;
@@ -36,11 +38,11 @@ pub async fn connect_ws(url: Url) -> LuaResult<Websocket<WsStream>> {
     Connects using plain TCP using the given host, port, and config.
 */
 pub async fn connect_tcp(host: String, port: u16, config: TcpConfig) -> LuaResult<Tcp> {
-    let tls = config.tls.unwrap_or_default();
+    let tls = config.tls_config();
 
-    let stream = MaybeTlsStream::connect(&host, port, tls)
+    let stream = MaybeTlsStream::connect(&host, port, tls.as_ref(), config.bind_addr)
         .await
-        .into_lua_err()?;
+        .map_err(|e| NetworkError::from_connect(&host, port, e))?;
 
     if let Some(ttl) = config.ttl {
         stream.set_ttl(ttl).into_lua_err()?;
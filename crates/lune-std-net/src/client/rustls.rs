@@ -3,7 +3,12 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
 };
 
-use rustls::{ClientConfig, crypto::ring};
+use rustls::{
+    ClientConfig, DigitallySignedStruct, Error, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{WebPkiSupportedAlgorithms, ring, verify_tls12_signature, verify_tls13_signature},
+};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
 
 static PROVIDER_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
@@ -24,3 +29,65 @@ pub static CLIENT_CONFIG: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
         .with_no_client_auth()
         .into()
 });
+
+/**
+    Client config that accepts any server certificate, for
+    servers using self-signed or otherwise untrusted certificates.
+
+    The connection is still encrypted, but not authenticated.
+*/
+pub static INSECURE_CLIENT_CONFIG: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
+    initialize_provider();
+    let algorithms = ring::default_provider().signature_verification_algorithms;
+    rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification { algorithms }))
+        .with_no_client_auth()
+        .into()
+});
+
+/**
+    Certificate verifier that trusts every certificate and server name.
+
+    Handshake signatures are still checked, so that the peer
+    must at least hold the key for the certificate it presents.
+*/
+#[derive(Debug)]
+struct SkipServerVerification {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
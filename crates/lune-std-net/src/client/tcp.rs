@@ -1,9 +1,42 @@
+use std::net::{IpAddr, SocketAddr};
+
 use mlua::prelude::*;
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone)]
 pub struct TcpConfig {
     pub tls: Option<bool>,
     pub ttl: Option<u32>,
+    pub bind_addr: Option<SocketAddr>,
+    pub sni: Option<String>,
+    pub insecure_skip_verify: bool,
+}
+
+impl TcpConfig {
+    /**
+        Returns the TLS settings for the connection, if it should use TLS.
+    */
+    pub fn tls_config(&self) -> Option<TlsConfig> {
+        self.tls.unwrap_or_default().then(|| TlsConfig {
+            sni: self.sni.clone(),
+            insecure_skip_verify: self.insecure_skip_verify,
+        })
+    }
+}
+
+/**
+    Settings for the TLS handshake of a client connection.
+*/
+#[derive(Debug, Default, Clone)]
+pub struct TlsConfig {
+    /**
+        The server name to send and to verify the certificate
+        against, instead of the host that is connected to.
+    */
+    pub sni: Option<String>,
+    /**
+        Accept any certificate the server presents.
+    */
+    pub insecure_skip_verify: bool,
 }
 
 impl FromLua for TcpConfig {
@@ -13,7 +46,7 @@ impl FromLua for TcpConfig {
         } else if let LuaValue::Boolean(tls) = value {
             Ok(TcpConfig {
                 tls: Some(tls),
-                ttl: None,
+                ..TcpConfig::default()
             })
         } else if let LuaValue::Table(tab) = value {
             let mut this = TcpConfig::default();
@@ -24,6 +57,15 @@ impl FromLua for TcpConfig {
             if let Some(ttl) = tab.get::<Option<_>>("ttl")? {
                 this.ttl = Some(ttl);
             }
+            if let Some(addr) = tab.get::<Option<String>>("bindAddr")? {
+                this.bind_addr = Some(parse_bind_addr(&addr)?);
+            }
+            if let Some(sni) = tab.get::<Option<String>>("sni")? {
+                this.sni = Some(sni);
+            }
+            if let Some(insecure) = tab.get::<Option<bool>>("insecureSkipVerify")? {
+                this.insecure_skip_verify = insecure;
+            }
 
             Ok(this)
         } else {
@@ -35,3 +77,18 @@ impl FromLua for TcpConfig {
         }
     }
 }
+
+/**
+    Parses a local address to connect from, either a bare IP
+    address, which lets the system pick the port, or `ip:port`.
+*/
+fn parse_bind_addr(addr: &str) -> LuaResult<SocketAddr> {
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 0));
+    }
+    addr.parse().map_err(|_| {
+        LuaError::runtime(format!(
+            "Invalid bindAddr '{addr}', expected an IP address or 'ip:port'"
+        ))
+    })
+}
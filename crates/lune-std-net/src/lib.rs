@@ -1,6 +1,6 @@
 #![allow(clippy::cargo_common_metadata)]
 
-use lune_utils::TableBuilder;
+use lune_utils::{TableBuilder, structured_errors::with_structured_errors};
 use mlua::prelude::*;
 
 pub(crate) mod body;
@@ -14,7 +14,13 @@ use crate::shared::{hyper::HyperExecutor, tcp::Tcp};
 use self::{
     client::{stream::WsStream, tcp::TcpConfig},
     server::config::ServeConfig,
-    shared::{request::Request, response::Response, websocket::Websocket},
+    shared::{
+        bind::{self, BindOptions},
+        error::structured_error,
+        request::Request,
+        response::Response,
+        websocket::Websocket,
+    },
 };
 
 pub use self::client::fetch;
@@ -45,24 +51,58 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         .build_readonly()?;
 
     let submodule_tcp = TableBuilder::new(lua.clone())?
-        .with_async_function("connect", net_tcp_connect)?
-        .with_async_function("listen", net_tcp_listen)?
+        .with_value(
+            "connect",
+            with_structured_errors(
+                &lua,
+                "net.tcp.connect",
+                lua.create_async_function(net_tcp_connect)?,
+                structured_error,
+            )?,
+        )?
+        .with_value(
+            "listen",
+            with_structured_errors(
+                &lua,
+                "net.tcp.listen",
+                lua.create_async_function(net_tcp_listen)?,
+                structured_error,
+            )?,
+        )?
         .build_readonly()?;
 
     let submodule_udp = TableBuilder::new(lua.clone())?
-        .with_async_function("bind", net_udp_bind)?
+        .with_value(
+            "bind",
+            with_structured_errors(
+                &lua,
+                "net.udp.bind",
+                lua.create_async_function(net_udp_bind)?,
+                structured_error,
+            )?,
+        )?
         .build_readonly()?;
 
     let submodule_ws = TableBuilder::new(lua.clone())?
         .with_async_function("connect", net_ws_connect)?
         .build_readonly()?;
 
+    let tls_connect = with_structured_errors(
+        &lua,
+        "net.tlsConnect",
+        lua.create_async_function(net_tls_connect)?,
+        structured_error,
+    )?;
+
     TableBuilder::new(lua)?
         .with_async_function("request", net_http_request)?
         .with_async_function("socket", net_ws_connect)?
         .with_async_function("serve", net_http_serve)?
+        .with_value("tlsConnect", tls_connect)?
         .with_function("urlEncode", net_url_encode)?
         .with_function("urlDecode", net_url_decode)?
+        .with_function("findFreePort", net_find_free_port)?
+        .with_function("isPortAvailable", net_is_port_available)?
         .with_value("http", submodule_http)?
         .with_value("tcp", submodule_tcp)?
         .with_value("udp", submodule_udp)?
@@ -84,12 +124,26 @@ async fn net_tcp_connect(_: Lua, (host, port, config): (String, u16, TcpConfig))
     self::client::connect_tcp(host, port, config).await
 }
 
-async fn net_tcp_listen(_: Lua, addr: String) -> LuaResult<shared::tcp_server::TcpServer> {
-    shared::tcp_server::TcpServer::listen(&addr).await
+async fn net_tls_connect(
+    _: Lua,
+    (host, port, mut config): (String, u16, TcpConfig),
+) -> LuaResult<Tcp> {
+    config.tls = Some(true);
+    self::client::connect_tcp(host, port, config).await
+}
+
+async fn net_tcp_listen(
+    _: Lua,
+    (addr, options): (String, BindOptions),
+) -> LuaResult<shared::tcp_server::TcpServer> {
+    shared::tcp_server::TcpServer::listen(&addr, options).await
 }
 
-async fn net_udp_bind(_: Lua, addr: String) -> LuaResult<shared::udp::UdpSocket> {
-    shared::udp::UdpSocket::bind(&addr)
+async fn net_udp_bind(
+    _: Lua,
+    (addr, options): (String, BindOptions),
+) -> LuaResult<shared::udp::UdpSocket> {
+    shared::udp::UdpSocket::bind(&addr, options)
 }
 
 async fn net_ws_connect(_: Lua, url: String) -> LuaResult<Websocket<WsStream>> {
@@ -97,6 +151,16 @@ async fn net_ws_connect(_: Lua, url: String) -> LuaResult<Websocket<WsStream>> {
     self::client::connect_ws(url).await
 }
 
+fn net_find_free_port(_: &Lua, host: Option<String>) -> LuaResult<u16> {
+    let host = host.unwrap_or_else(|| bind::DEFAULT_PORT_HOST.to_string());
+    bind::find_free_port(&host)
+}
+
+fn net_is_port_available(_: &Lua, (port, host): (u16, Option<String>)) -> LuaResult<bool> {
+    let host = host.unwrap_or_else(|| bind::DEFAULT_PORT_HOST.to_string());
+    bind::is_port_available(&host, port)
+}
+
 fn net_url_encode(
     lua: &Lua,
     (lua_string, as_binary): (LuaString, Option<bool>),
@@ -0,0 +1,158 @@
+//! Address family selection for listening and bound sockets.
+
+use lune_utils::NetworkError;
+use mlua::prelude::*;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Host checked by `net.findFreePort` and `net.isPortAvailable` when none is given.
+pub const DEFAULT_PORT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// Which address family a socket should be bound with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// IPv4 only.
+    V4,
+    /// IPv6 only, with `IPV6_V6ONLY` set.
+    V6,
+    /// IPv6 socket that also accepts IPv4-mapped traffic.
+    Dual,
+}
+
+impl AddressFamily {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "v4" => Some(Self::V4),
+            "v6" => Some(Self::V6),
+            "dual" => Some(Self::Dual),
+            _ => None,
+        }
+    }
+
+    fn matches(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::V4 => addr.is_ipv4(),
+            Self::V6 | Self::Dual => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Options shared by `net.tcp.listen` and `net.udp.bind`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BindOptions {
+    pub family: Option<AddressFamily>,
+}
+
+impl FromLua for BindOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Nil = value {
+            Ok(BindOptions::default())
+        } else if let LuaValue::Table(tab) = value {
+            let mut this = BindOptions::default();
+
+            if let Some(family) = tab.get::<Option<String>>("family")? {
+                let Some(family) = AddressFamily::from_str(&family) else {
+                    return Err(LuaError::runtime(format!(
+                        "Invalid address family '{family}', expected 'v4', 'v6' or 'dual'"
+                    )));
+                };
+                this.family = Some(family);
+            }
+
+            Ok(this)
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("BindOptions"),
+                message: None,
+            })
+        }
+    }
+}
+
+/// Resolve `addr` and create a socket bound to the first address of the requested family.
+fn bind_socket(
+    addr: &str,
+    family: AddressFamily,
+    ty: Type,
+    protocol: Protocol,
+) -> LuaResult<Socket> {
+    let resolved = addr
+        .to_socket_addrs()
+        .into_lua_err()?
+        .find(|a| family.matches(a))
+        .ok_or_else(|| {
+            let name = if family == AddressFamily::V4 {
+                "IPv4"
+            } else {
+                "IPv6"
+            };
+            LuaError::runtime(format!("No {name} address found for '{addr}'"))
+        })?;
+
+    let socket = Socket::new(Domain::for_address(resolved), ty, Some(protocol)).into_lua_err()?;
+    if family != AddressFamily::V4 {
+        socket
+            .set_only_v6(family == AddressFamily::V6)
+            .into_lua_err()?;
+    }
+    // Matches what std does for listeners on unix platforms
+    #[cfg(not(windows))]
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true).into_lua_err()?;
+    }
+    socket
+        .bind(&resolved.into())
+        .map_err(|e| NetworkError::from_bind(addr, e))?;
+    socket.set_nonblocking(true).into_lua_err()?;
+
+    Ok(socket)
+}
+
+/// Create a listening TCP socket for the given address family.
+pub fn tcp_listener(addr: &str, family: AddressFamily) -> LuaResult<std::net::TcpListener> {
+    let socket = bind_socket(addr, family, Type::STREAM, Protocol::TCP)?;
+    socket
+        .listen(LISTEN_BACKLOG)
+        .map_err(|e| NetworkError::from_bind(addr, e))?;
+    Ok(socket.into())
+}
+
+/// Create a bound UDP socket for the given address family.
+pub fn udp_socket(addr: &str, family: AddressFamily) -> LuaResult<std::net::UdpSocket> {
+    let socket = bind_socket(addr, family, Type::DGRAM, Protocol::UDP)?;
+    Ok(socket.into())
+}
+
+/// Resolve `host` to the `ip:port` string and family that [`tcp_listener`] would bind.
+fn resolve_host(host: &str, port: u16) -> LuaResult<(String, AddressFamily)> {
+    let resolved = (host, port)
+        .to_socket_addrs()
+        .into_lua_err()?
+        .next()
+        .ok_or_else(|| LuaError::runtime(format!("No address found for '{host}'")))?;
+    let family = if resolved.is_ipv4() {
+        AddressFamily::V4
+    } else {
+        AddressFamily::V6
+    };
+    Ok((resolved.to_string(), family))
+}
+
+/// Find a TCP port on `host` that nothing is listening on, by letting the system pick one.
+///
+/// The listener is closed again before returning, so the port is only
+/// free until something else happens to bind it.
+pub fn find_free_port(host: &str) -> LuaResult<u16> {
+    let (addr, family) = resolve_host(host, 0)?;
+    let listener = tcp_listener(&addr, family)?;
+    Ok(listener.local_addr().into_lua_err()?.port())
+}
+
+/// Check whether a TCP listener could currently be bound to `port` on `host`.
+pub fn is_port_available(host: &str, port: u16) -> LuaResult<bool> {
+    let (addr, family) = resolve_host(host, port)?;
+    Ok(tcp_listener(&addr, family).is_ok())
+}
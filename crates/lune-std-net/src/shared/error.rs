@@ -0,0 +1,46 @@
+//! Structured errors for socket operations.
+//!
+//! Connecting, binding, reading and writing raise [`NetworkError`]s to Lua
+//! as `{ kind, ioKind, message, transient }` tables, so that scripts can
+//! tell a refused connection apart from an invalid address.
+
+use lune_utils::NetworkError;
+use mlua::prelude::*;
+
+/// Convert into the `{ kind, ioKind, message, transient }` table raised to Lua.
+fn into_lua_table(err: &NetworkError, lua: &Lua) -> LuaResult<LuaTable> {
+    let message = err.to_string();
+    let table = lua.create_table()?;
+    table.set("kind", err.kind())?;
+    table.set("ioKind", err.io_kind().map(|kind| format!("{kind:?}")))?;
+    table.set("message", message.as_str())?;
+    table.set("transient", err.is_transient())?;
+
+    let meta = lua.create_table()?;
+    meta.set(
+        "__tostring",
+        lua.create_function(move |_, _: LuaValue| Ok(message.clone()))?,
+    )?;
+    table.set_metatable(Some(meta))?;
+
+    Ok(table)
+}
+
+/// Find a [`NetworkError`] inside a (possibly wrapped) Lua error.
+fn find_network_error(err: &LuaError) -> Option<&NetworkError> {
+    match err {
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => {
+            find_network_error(cause)
+        }
+        _ => err.downcast_ref::<NetworkError>(),
+    }
+}
+
+/// Convert a [`NetworkError`] raised by a wrapped function into its table.
+///
+/// Passed to [`lune_utils::structured_errors::with_structured_errors`].
+pub fn structured_error(lua: &Lua, err: &LuaError) -> LuaResult<Option<LuaTable>> {
+    find_network_error(err)
+        .map(|net_err| into_lua_table(net_err, lua))
+        .transpose()
+}
@@ -1,10 +1,14 @@
+pub mod bind;
+pub mod error;
 pub mod futures;
 pub mod headers;
 pub mod hyper;
 pub mod lua;
 pub mod request;
 pub mod response;
+pub mod stats;
 pub mod tcp;
 pub mod tcp_server;
 pub mod udp;
+pub mod vectored;
 pub mod websocket;
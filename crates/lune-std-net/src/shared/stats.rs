@@ -0,0 +1,49 @@
+//! Byte counters shared between clones of a connection or socket.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use mlua::prelude::*;
+
+/// Total bytes read and written over a connection.
+///
+/// Clones share the same counters, so reads and writes made from
+/// different coroutines through different handles all add up.
+#[derive(Debug, Default, Clone)]
+pub struct ByteCounters {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl ByteCounters {
+    pub fn record_read(&self, len: usize) {
+        self.inner.read.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, len: usize) {
+        self.inner.written.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.read.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.written.load(Ordering::Relaxed)
+    }
+
+    /// Snapshot the counters as a `{ bytesRead, bytesWritten }` table.
+    pub fn to_lua_table(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let table = lua.create_table()?;
+        table.set("bytesRead", self.bytes_read())?;
+        table.set("bytesWritten", self.bytes_written())?;
+        Ok(table)
+    }
+}
@@ -7,10 +7,15 @@ use futures::{
     prelude::*,
 };
 
+use lune_utils::{NetworkError, structured_errors::StructuredAsyncMethod};
 use mlua::prelude::*;
 
 use crate::client::stream::MaybeTlsStream;
 
+use super::error::structured_error;
+use super::stats::ByteCounters;
+use super::vectored::{parts_from_lua, write_all_vectored};
+
 const DEFAULT_BUFFER_SIZE: usize = 1024;
 
 #[derive(Debug, Clone)]
@@ -19,6 +24,7 @@ pub struct Tcp {
     remote_addr: Arc<Option<SocketAddr>>,
     read_half: Arc<AsyncMutex<ReadHalf<MaybeTlsStream>>>,
     write_half: Arc<AsyncMutex<WriteHalf<MaybeTlsStream>>>,
+    counters: ByteCounters,
 }
 
 impl Tcp {
@@ -27,6 +33,7 @@ impl Tcp {
 
         let mut handle = self.read_half.lock().await;
         let read = handle.read(&mut buf).await?;
+        self.counters.record_read(read);
 
         buf.truncate(read);
 
@@ -36,10 +43,19 @@ impl Tcp {
     async fn write(&self, data: Vec<u8>) -> Result<(), Error> {
         let mut handle = self.write_half.lock().await;
         handle.write_all(&data).await?;
+        self.counters.record_write(data.len());
 
         Ok(())
     }
 
+    async fn writev(&self, parts: Vec<Vec<u8>>) -> Result<usize, Error> {
+        let mut handle = self.write_half.lock().await;
+        let written = write_all_vectored(&mut *handle, &parts).await?;
+        self.counters.record_write(written);
+
+        Ok(written)
+    }
+
     async fn close(&self) -> Result<(), Error> {
         let mut handle = self.write_half.lock().await;
 
@@ -66,6 +82,7 @@ where
             remote_addr: Arc::new(remote_addr),
             read_half: Arc::new(AsyncMutex::new(read)),
             write_half: Arc::new(AsyncMutex::new(write)),
+            counters: ByteCounters::default(),
         }
     }
 }
@@ -84,25 +101,54 @@ impl LuaUserData for Tcp {
         fields.add_field_method_get("remotePort", |_, this| {
             Ok(this.remote_addr.map(|address| address.port()))
         });
+
+        // Reads and writes raise NetworkErrors as { kind, ioKind, message, transient } tables
+        fields.add_field(
+            "read",
+            StructuredAsyncMethod::new(
+                "Tcp.read",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, size: Option<usize>| {
+                    let this = this.clone();
+                    let size = size.unwrap_or(DEFAULT_BUFFER_SIZE);
+                    async move {
+                        let bytes = this.read(size).await.map_err(NetworkError::from_receive)?;
+                        lua.create_string(bytes)
+                    }
+                },
+            ),
+        );
+        fields.add_field(
+            "write",
+            StructuredAsyncMethod::new(
+                "Tcp.write",
+                structured_error,
+                |_, this: LuaUserDataRef<Self>, data: BString| {
+                    let this = this.clone();
+                    let data = data.to_vec();
+                    async move { Ok(this.write(data).await.map_err(NetworkError::from_send)?) }
+                },
+            ),
+        );
+        fields.add_field(
+            "writev",
+            StructuredAsyncMethod::new(
+                "Tcp.writev",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, parts: LuaTable| {
+                    let this = this.clone();
+                    let parts = parts_from_lua(&lua, &parts);
+                    async move { Ok(this.writev(parts?).await.map_err(NetworkError::from_send)?) }
+                },
+            ),
+        );
     }
 
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_method("read", |lua, this, size: Option<usize>| {
-            let this = this.clone();
-            let size = size.unwrap_or(DEFAULT_BUFFER_SIZE);
-            async move {
-                let bytes = this.read(size).await.into_lua_err()?;
-                lua.create_string(bytes)
-            }
-        });
-        methods.add_async_method("write", |_, this, data: BString| {
-            let this = this.clone();
-            let data = data.to_vec();
-            async move { this.write(data).await.into_lua_err() }
-        });
         methods.add_async_method("close", |_, this, (): ()| {
             let this = this.clone();
             async move { this.close().await.into_lua_err() }
         });
+        methods.add_method("stats", |lua, this, (): ()| this.counters.to_lua_table(lua));
     }
 }
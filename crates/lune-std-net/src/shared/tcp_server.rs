@@ -2,44 +2,247 @@
 //!
 //! Provides async TCP listener with accept loop.
 
+use async_io::Timer;
 use async_net::{TcpListener as AsyncTcpListener, TcpStream};
+use bstr::ByteSlice;
+use lune_utils::{NetworkError, structured_errors::StructuredAsyncMethod};
 use mlua::prelude::*;
-use mlua_luau_scheduler::LuaSpawnExt;
+use mlua_luau_scheduler::{LuaSchedulerExt, LuaSpawnExt};
+use socket2::SockRef;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::bind::{self, BindOptions};
+use super::error::structured_error;
+use super::stats::ByteCounters;
+use super::vectored::{parts_from_lua, write_all_vectored};
+
+const READ_TO_END_CHUNK_SIZE: usize = 8192;
+const READ_UNTIL_CHUNK_SIZE: usize = 4096;
+
+/// Stream of an accepted connection, along with bytes read
+/// past a delimiter that the next read should return first.
+struct BufferedStream {
+    stream: TcpStream,
+    pending: Vec<u8>,
+}
+
+impl BufferedStream {
+    /// Read a chunk from the socket into `pending`, returning how many bytes arrived.
+    async fn fill(
+        &mut self,
+        counters: &ByteCounters,
+        timeout: Option<Duration>,
+    ) -> LuaResult<usize> {
+        use futures_lite::AsyncReadExt;
+        let start = self.pending.len();
+        self.pending.resize(start + READ_UNTIL_CHUNK_SIZE, 0);
+        let result = timed_read(self.stream.read(&mut self.pending[start..]), timeout).await;
+        // Drop the unused space again, also when the read failed
+        self.pending
+            .truncate(start + result.as_ref().map_or(0, |len| *len));
+        let len = result?;
+        counters.record_read(len);
+        Ok(len)
+    }
+}
+
+/// Wait for a read from the socket, erroring if no data arrives within `timeout`.
+///
+/// Reads from the socket may be dropped without losing data, so
+/// the connection can still be read from after timing out.
+async fn timed_read(
+    read: impl Future<Output = std::io::Result<usize>>,
+    timeout: Option<Duration>,
+) -> Result<usize, NetworkError> {
+    let read = async { read.await.map_err(NetworkError::from_receive) };
+    let Some(timeout) = timeout else {
+        return read.await;
+    };
+    futures_lite::future::or(read, async {
+        Timer::after(timeout).await;
+        Err(NetworkError::Timeout {
+            duration_ms: u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX),
+        })
+    })
+    .await
+}
 
 /// Accepted TCP connection (simpler than client Tcp).
 pub struct TcpConnection {
-    stream: Arc<async_lock::Mutex<TcpStream>>,
+    stream: Arc<async_lock::Mutex<BufferedStream>>,
+    /// Handle to the same socket, for changing options while a read holds the lock
+    socket: TcpStream,
+    /// How long reads wait for data, in milliseconds, or `0` to wait forever
+    read_timeout_ms: Arc<AtomicU64>,
     remote_addr: String,
+    counters: ByteCounters,
 }
 
 impl TcpConnection {
     fn new(stream: TcpStream, addr: String) -> Self {
         Self {
-            stream: Arc::new(async_lock::Mutex::new(stream)),
+            socket: stream.clone(),
+            stream: Arc::new(async_lock::Mutex::new(BufferedStream {
+                stream,
+                pending: Vec::new(),
+            })),
+            read_timeout_ms: Arc::default(),
             remote_addr: addr,
+            counters: ByteCounters::default(),
+        }
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        match self.read_timeout_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
         }
     }
 
+    /// Make reads error if no data arrives within `timeout`, or wait forever when `None`.
+    ///
+    /// Only reads started afterwards are affected.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        let ms = timeout.map_or(0, |t| u64::try_from(t.as_millis()).unwrap_or(u64::MAX));
+        self.read_timeout_ms.store(ms, Ordering::Relaxed);
+    }
+
+    /// Send small writes right away instead of batching them (disable Nagle's algorithm).
+    pub fn set_nodelay(&self, nodelay: bool) -> LuaResult<()> {
+        self.socket.set_nodelay(nodelay).into_lua_err()
+    }
+
+    /// Periodically probe idle connections, so that dead peers are eventually detected.
+    pub fn set_keepalive(&self, keepalive: bool) -> LuaResult<()> {
+        SockRef::from(&self.socket)
+            .set_keepalive(keepalive)
+            .into_lua_err()
+    }
+
     pub async fn read(&self, size: usize) -> LuaResult<Vec<u8>> {
         use futures_lite::AsyncReadExt;
-        let mut buf = vec![0u8; size];
         let mut stream = self.stream.lock().await;
-        let len = stream.read(&mut buf).await.into_lua_err()?;
+        if !stream.pending.is_empty() {
+            let len = size.min(stream.pending.len());
+            return Ok(stream.pending.drain(..len).collect());
+        }
+        let mut buf = vec![0u8; size];
+        let len = timed_read(stream.stream.read(&mut buf), self.read_timeout()).await?;
+        self.counters.record_read(len);
         buf.truncate(len);
         Ok(buf)
     }
 
+    /// Read until the peer closes the connection, erroring if more than `max_bytes` arrive.
+    pub async fn read_to_end(&self, max_bytes: Option<usize>) -> LuaResult<Vec<u8>> {
+        use futures_lite::AsyncReadExt;
+        let mut chunk = vec![0u8; READ_TO_END_CHUNK_SIZE];
+        let mut stream = self.stream.lock().await;
+        let mut data = std::mem::take(&mut stream.pending);
+        loop {
+            if let Some(max) = max_bytes
+                && data.len() > max
+            {
+                stream.pending = data;
+                return Err(LuaError::runtime(format!(
+                    "readToEnd exceeded the maximum of {max} bytes"
+                )));
+            }
+            let len = match timed_read(stream.stream.read(&mut chunk), self.read_timeout()).await {
+                Ok(len) => len,
+                Err(err) => {
+                    // Keep what already arrived for the next read, instead of losing it
+                    stream.pending = data;
+                    return Err(err.into());
+                }
+            };
+            self.counters.record_read(len);
+            if len == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    /// Read up to, and excluding, the next `delimiter`, keeping any bytes
+    /// received after it for the next read.
+    ///
+    /// Once the peer closes the connection, whatever is left is returned
+    /// without a delimiter, and `None` once nothing is left.
+    pub async fn read_until(
+        &self,
+        delimiter: &[u8],
+        max_bytes: Option<usize>,
+    ) -> LuaResult<Option<Vec<u8>>> {
+        if delimiter.is_empty() {
+            return Err(LuaError::runtime("readUntil delimiter must not be empty"));
+        }
+        let mut stream = self.stream.lock().await;
+        // Only search the bytes that could complete a delimiter, not the whole buffer every time
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = stream.pending[searched..].find(delimiter) {
+                let end = searched + pos;
+                let data = stream.pending[..end].to_vec();
+                stream.pending.drain(..end + delimiter.len());
+                return Ok(Some(data));
+            }
+            if let Some(max) = max_bytes
+                && stream.pending.len() > max
+            {
+                return Err(LuaError::runtime(format!(
+                    "readUntil exceeded the maximum of {max} bytes without finding the delimiter"
+                )));
+            }
+            searched = stream.pending.len().saturating_sub(delimiter.len() - 1);
+            if stream.fill(&self.counters, self.read_timeout()).await? == 0 {
+                if stream.pending.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(std::mem::take(&mut stream.pending)));
+            }
+        }
+    }
+
+    /// Read the next line, without its `\n` or `\r\n` ending.
+    pub async fn read_line(&self, max_bytes: Option<usize>) -> LuaResult<Option<Vec<u8>>> {
+        let mut line = self.read_until(b"\n", max_bytes).await?;
+        if let Some(line) = &mut line
+            && line.last() == Some(&b'\r')
+        {
+            line.pop();
+        }
+        Ok(line)
+    }
+
     pub async fn write(&self, data: &[u8]) -> LuaResult<usize> {
         use futures_lite::AsyncWriteExt;
         let mut stream = self.stream.lock().await;
-        stream.write(data).await.into_lua_err()
+        let len = stream
+            .stream
+            .write(data)
+            .await
+            .map_err(NetworkError::from_send)?;
+        self.counters.record_write(len);
+        Ok(len)
+    }
+
+    /// Write several buffers in order, returning the total number of bytes written.
+    pub async fn writev(&self, parts: &[Vec<u8>]) -> LuaResult<usize> {
+        let mut stream = self.stream.lock().await;
+        let len = write_all_vectored(&mut stream.stream, parts)
+            .await
+            .map_err(NetworkError::from_send)?;
+        self.counters.record_write(len);
+        Ok(len)
     }
 
     pub async fn close(&self) -> LuaResult<()> {
         use futures_lite::AsyncWriteExt;
         let mut stream = self.stream.lock().await;
-        stream.close().await.into_lua_err()
+        stream.stream.close().await.into_lua_err()
     }
 }
 
@@ -47,7 +250,10 @@ impl Clone for TcpConnection {
     fn clone(&self) -> Self {
         Self {
             stream: Arc::clone(&self.stream),
+            socket: self.socket.clone(),
+            read_timeout_ms: Arc::clone(&self.read_timeout_ms),
             remote_addr: self.remote_addr.clone(),
+            counters: self.counters.clone(),
         }
     }
 }
@@ -55,20 +261,148 @@ impl Clone for TcpConnection {
 impl LuaUserData for TcpConnection {
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
         fields.add_field_method_get("address", |_, this| Ok(this.remote_addr.clone()));
+
+        // Reads and writes raise NetworkErrors as { kind, ioKind, message, transient } tables
+        fields.add_field(
+            "read",
+            StructuredAsyncMethod::new(
+                "TcpConnection.read",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, size: Option<usize>| async move {
+                    let data = this.read(size.unwrap_or(4096)).await?;
+                    lua.create_string(&data)
+                },
+            ),
+        );
+
+        fields.add_field(
+            "readToEnd",
+            StructuredAsyncMethod::new(
+                "TcpConnection.readToEnd",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, max_bytes: Option<usize>| async move {
+                    let data = this.read_to_end(max_bytes).await?;
+                    lua.create_string(&data)
+                },
+            ),
+        );
+
+        fields.add_field(
+            "readUntil",
+            StructuredAsyncMethod::new(
+                "TcpConnection.readUntil",
+                structured_error,
+                |lua,
+                 this: LuaUserDataRef<Self>,
+                 (delimiter, max_bytes): (LuaString, Option<usize>)| async move {
+                    let data = this.read_until(&delimiter.as_bytes(), max_bytes).await?;
+                    data.map(|data| lua.create_string(&data)).transpose()
+                },
+            ),
+        );
+
+        fields.add_field(
+            "readLine",
+            StructuredAsyncMethod::new(
+                "TcpConnection.readLine",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, max_bytes: Option<usize>| async move {
+                    let data = this.read_line(max_bytes).await?;
+                    data.map(|data| lua.create_string(&data)).transpose()
+                },
+            ),
+        );
+
+        fields.add_field(
+            "write",
+            StructuredAsyncMethod::new(
+                "TcpConnection.write",
+                structured_error,
+                |_, this: LuaUserDataRef<Self>, data: LuaString| async move {
+                    let bytes = data.as_bytes().to_vec();
+                    this.write(&bytes).await
+                },
+            ),
+        );
+
+        fields.add_field(
+            "writev",
+            StructuredAsyncMethod::new(
+                "TcpConnection.writev",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, parts: LuaTable| async move {
+                    let parts = parts_from_lua(&lua, &parts)?;
+                    this.writev(&parts).await
+                },
+            ),
+        );
     }
 
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_method("read", |lua, this, size: Option<usize>| async move {
-            let data = this.read(size.unwrap_or(4096)).await?;
-            lua.create_string(&data)
+        methods.add_async_method("close", |_, this, ()| async move { this.close().await });
+
+        methods.add_method("stats", |lua, this, ()| this.counters.to_lua_table(lua));
+
+        methods.add_method("setNoDelay", |_, this, nodelay: bool| {
+            this.set_nodelay(nodelay)
         });
 
-        methods.add_async_method("write", |_, this, data: LuaString| async move {
-            let bytes = data.as_bytes().to_vec();
-            this.write(&bytes).await
+        methods.add_method("setKeepAlive", |_, this, keepalive: bool| {
+            this.set_keepalive(keepalive)
         });
 
-        methods.add_async_method("close", |_, this, ()| async move { this.close().await });
+        // setReadTimeout(ms: number?) - nil or 0 waits forever
+        methods.add_method("setReadTimeout", |_, this, ms: Option<f64>| {
+            let timeout = match ms {
+                None => None,
+                Some(ms) if ms.is_finite() && ms >= 0.0 => {
+                    Some(Duration::from_secs_f64(ms / 1000.0)).filter(|t| !t.is_zero())
+                }
+                Some(ms) => {
+                    return Err(LuaError::runtime(format!(
+                        "Read timeout must be a positive number of milliseconds, got {ms}"
+                    )));
+                }
+            };
+            this.set_read_timeout(timeout);
+            Ok(())
+        });
+    }
+}
+
+/// Options for `TcpServer:serve`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpServeOptions {
+    /// How many handlers may run at once before accepting pauses, or `None` for no limit.
+    pub max_concurrent: Option<usize>,
+}
+
+impl FromLua for TcpServeOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Nil = value {
+            Ok(TcpServeOptions::default())
+        } else if let LuaValue::Table(tab) = value {
+            let mut this = TcpServeOptions::default();
+
+            if let Some(max) = tab.get::<Option<LuaNumber>>("maxConcurrent")? {
+                if max.fract() != 0.0 || max < 1.0 {
+                    return Err(LuaError::runtime(format!(
+                        "Invalid maxConcurrent {max}, expected a positive integer"
+                    )));
+                }
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let max = max.min(usize::MAX as f64) as usize;
+                this.max_concurrent = Some(max);
+            }
+
+            Ok(this)
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("TcpServeOptions"),
+                message: None,
+            })
+        }
     }
 }
 
@@ -80,8 +414,15 @@ pub struct TcpServer {
 
 impl TcpServer {
     /// Bind to a local address and start listening.
-    pub async fn listen(addr: &str) -> LuaResult<Self> {
-        let listener = AsyncTcpListener::bind(addr).await.into_lua_err()?;
+    pub async fn listen(addr: &str, options: BindOptions) -> LuaResult<Self> {
+        let listener = match options.family {
+            Some(family) => {
+                AsyncTcpListener::try_from(bind::tcp_listener(addr, family)?).into_lua_err()?
+            }
+            None => AsyncTcpListener::bind(addr)
+                .await
+                .map_err(|e| NetworkError::from_bind(addr, e))?,
+        };
 
         let local_addr = listener
             .local_addr()
@@ -100,6 +441,18 @@ impl TcpServer {
     }
 }
 
+/// Run a `serve` handler for an accepted connection, waiting until it returns.
+///
+/// Errors thrown by the handler itself are already reported by the scheduler.
+async fn run_handler(lua: &Lua, handler: LuaFunction, conn: TcpConnection) -> LuaResult<()> {
+    let thread_id = lua.push_thread_back(handler, conn)?;
+    lua.track_thread(thread_id);
+    lua.wait_for_thread(thread_id).await;
+    // Take the result, so that it isn't kept around
+    let _ = lua.get_thread_result(thread_id);
+    Ok(())
+}
+
 impl Clone for TcpServer {
     fn clone(&self) -> Self {
         Self {
@@ -118,28 +471,46 @@ impl LuaUserData for TcpServer {
         // accept() -> TcpConnection
         methods.add_async_method("accept", |_, this, ()| async move { this.accept().await });
 
-        // serve(handler: (socket) -> ()) - Run accept loop with callback
-        methods.add_method("serve", |lua, this, handler: LuaFunction| {
-            let server = this.clone();
+        // serve(handler: (socket) -> (), options?) - Run accept loop with callback,
+        // each handler runs in its own thread and may yield while handling its connection
+        methods.add_method(
+            "serve",
+            |lua, this, (handler, options): (LuaFunction, TcpServeOptions)| {
+                let server = this.clone();
+                let limit = options
+                    .max_concurrent
+                    .map(|max| Arc::new(async_lock::Semaphore::new(max)));
+                let inner_lua = lua.clone();
 
-            lua.spawn_local(async move {
-                loop {
-                    match server.accept().await {
-                        Ok(conn) => {
-                            if let Err(e) = handler.call::<()>((conn,)) {
-                                eprintln!("\x1b[33m[WARN]\x1b[0m TCP handler error: {e}");
+                lua.spawn_local(async move {
+                    loop {
+                        // Wait for a running handler to finish before accepting more
+                        let permit = match &limit {
+                            Some(limit) => Some(limit.acquire_arc().await),
+                            None => None,
+                        };
+                        match server.accept().await {
+                            Ok(conn) => {
+                                let lua = inner_lua.clone();
+                                let handler = handler.clone();
+                                inner_lua.spawn_local(async move {
+                                    if let Err(e) = run_handler(&lua, handler, conn).await {
+                                        eprintln!("\x1b[33m[WARN]\x1b[0m TCP handler error: {e}");
+                                    }
+                                    drop(permit);
+                                });
+                            }
+                            Err(e) => {
+                                eprintln!("\x1b[31m[ERROR]\x1b[0m TCP accept error: {e}");
+                                break;
                             }
-                        }
-                        Err(e) => {
-                            eprintln!("\x1b[31m[ERROR]\x1b[0m TCP accept error: {e}");
-                            break;
                         }
                     }
-                }
-            });
+                });
 
-            Ok(())
-        });
+                Ok(())
+            },
+        );
 
         methods.add_method("close", |_, _, ()| Ok(()));
     }
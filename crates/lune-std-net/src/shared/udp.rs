@@ -3,20 +3,29 @@
 //! Provides async UDP bind, send, and receive operations.
 
 use async_io::Async;
+use lune_utils::{NetworkError, structured_errors::StructuredAsyncMethod};
 use mlua::prelude::*;
-use std::net::UdpSocket as StdUdpSocket;
+use std::net::{Ipv4Addr, UdpSocket as StdUdpSocket};
 use std::sync::Arc;
 
+use super::bind::{self, BindOptions};
+use super::error::structured_error;
+use super::stats::ByteCounters;
+
 /// Async UDP socket wrapper for Lua userdata.
 pub struct UdpSocket {
     inner: Arc<Async<StdUdpSocket>>,
     bound_addr: String,
+    counters: ByteCounters,
 }
 
 impl UdpSocket {
     /// Bind to a local address.
-    pub fn bind(addr: &str) -> LuaResult<Self> {
-        let socket = StdUdpSocket::bind(addr).into_lua_err()?;
+    pub fn bind(addr: &str, options: BindOptions) -> LuaResult<Self> {
+        let socket = match options.family {
+            Some(family) => bind::udp_socket(addr, family)?,
+            None => StdUdpSocket::bind(addr).map_err(|e| NetworkError::from_bind(addr, e))?,
+        };
         socket.set_nonblocking(true).into_lua_err()?;
 
         let bound_addr = socket
@@ -28,16 +37,20 @@ impl UdpSocket {
         Ok(Self {
             inner: Arc::new(async_socket),
             bound_addr,
+            counters: ByteCounters::default(),
         })
     }
 
     /// Send data to a target address.
     pub async fn send_to(&self, data: &[u8], target: &str) -> LuaResult<usize> {
         let target: std::net::SocketAddr = target.parse().into_lua_err()?;
-        self.inner
+        let len = self
+            .inner
             .write_with(|sock| sock.send_to(data, target))
             .await
-            .into_lua_err()
+            .map_err(NetworkError::from_send)?;
+        self.counters.record_write(len);
+        Ok(len)
     }
 
     /// Receive data with sender address.
@@ -47,7 +60,8 @@ impl UdpSocket {
             .inner
             .read_with(|sock| sock.recv_from(&mut buf))
             .await
-            .into_lua_err()?;
+            .map_err(NetworkError::from_receive)?;
+        self.counters.record_read(len);
 
         buf.truncate(len);
         Ok((buf, addr.to_string()))
@@ -60,10 +74,13 @@ impl UdpSocket {
 
     /// Send on connected socket.
     pub async fn send(&self, data: &[u8]) -> LuaResult<usize> {
-        self.inner
+        let len = self
+            .inner
             .write_with(|sock| sock.send(data))
             .await
-            .into_lua_err()
+            .map_err(NetworkError::from_send)?;
+        self.counters.record_write(len);
+        Ok(len)
     }
 
     /// Receive on connected socket.
@@ -73,11 +90,63 @@ impl UdpSocket {
             .inner
             .read_with(|sock| sock.recv(&mut buf))
             .await
-            .into_lua_err()?;
+            .map_err(NetworkError::from_receive)?;
+        self.counters.record_read(len);
 
         buf.truncate(len);
         Ok(buf)
     }
+
+    /// Join an IPv4 multicast group on the given local interface.
+    pub fn join_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> LuaResult<()> {
+        self.inner
+            .get_ref()
+            .join_multicast_v4(&group, &interface)
+            .into_lua_err()
+    }
+
+    /// Leave an IPv4 multicast group previously joined on the given local interface.
+    pub fn leave_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> LuaResult<()> {
+        self.inner
+            .get_ref()
+            .leave_multicast_v4(&group, &interface)
+            .into_lua_err()
+    }
+
+    /// Set how many hops outgoing IPv4 multicast packets may take.
+    pub fn set_multicast_ttl_v4(&self, ttl: u32) -> LuaResult<()> {
+        self.inner
+            .get_ref()
+            .set_multicast_ttl_v4(ttl)
+            .into_lua_err()
+    }
+
+    /// Allow or disallow sending to broadcast addresses.
+    pub fn set_broadcast(&self, broadcast: bool) -> LuaResult<()> {
+        self.inner.get_ref().set_broadcast(broadcast).into_lua_err()
+    }
+}
+
+/// Parse the group and optional interface address given to the multicast methods.
+fn parse_multicast_args(group: &str, interface: Option<&str>) -> LuaResult<(Ipv4Addr, Ipv4Addr)> {
+    let parse = |addr: &str, what: &str| {
+        addr.parse::<Ipv4Addr>().map_err(|_| {
+            LuaError::runtime(format!(
+                "Invalid multicast {what} address '{addr}', expected an IPv4 address"
+            ))
+        })
+    };
+    let group = parse(group, "group")?;
+    if !group.is_multicast() {
+        return Err(LuaError::runtime(format!(
+            "Address '{group}' is not an IPv4 multicast address (224.0.0.0/4)"
+        )));
+    }
+    let interface = match interface {
+        Some(interface) => parse(interface, "interface")?,
+        None => Ipv4Addr::UNSPECIFIED,
+    };
+    Ok((group, interface))
 }
 
 impl Clone for UdpSocket {
@@ -85,6 +154,7 @@ impl Clone for UdpSocket {
         Self {
             inner: Arc::clone(&self.inner),
             bound_addr: self.bound_addr.clone(),
+            counters: self.counters.clone(),
         }
     }
 }
@@ -92,43 +162,98 @@ impl Clone for UdpSocket {
 impl LuaUserData for UdpSocket {
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
         fields.add_field_method_get("address", |_, this| Ok(this.bound_addr.clone()));
-    }
 
-    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Sends and receives raise NetworkErrors as { kind, ioKind, message, transient } tables
+
         // sendTo(data: buffer, address: string) -> number
-        methods.add_async_method(
+        fields.add_field(
             "sendTo",
-            |_, this, (data, target): (LuaString, String)| async move {
-                let bytes = data.as_bytes().to_vec();
-                this.send_to(&bytes, &target).await
-            },
+            StructuredAsyncMethod::new(
+                "UdpSocket.sendTo",
+                structured_error,
+                |_, this: LuaUserDataRef<Self>, (data, target): (LuaString, String)| async move {
+                    let bytes = data.as_bytes().to_vec();
+                    this.send_to(&bytes, &target).await
+                },
+            ),
         );
 
         // recvFrom(maxSize?: number) -> { data: buffer, address: string }
-        methods.add_async_method(
+        fields.add_field(
             "recvFrom",
-            |lua, this, max_size: Option<usize>| async move {
-                let (data, addr) = this.recv_from(max_size.unwrap_or(65535)).await?;
-                let result = lua.create_table()?;
-                result.set("data", lua.create_string(&data)?)?;
-                result.set("address", addr)?;
-                Ok(result)
-            },
+            StructuredAsyncMethod::new(
+                "UdpSocket.recvFrom",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, max_size: Option<usize>| async move {
+                    let (data, addr) = this.recv_from(max_size.unwrap_or(65535)).await?;
+                    let result = lua.create_table()?;
+                    result.set("data", lua.create_string(&data)?)?;
+                    result.set("address", addr)?;
+                    Ok(result)
+                },
+            ),
+        );
+
+        // send(data: buffer) -> number
+        fields.add_field(
+            "send",
+            StructuredAsyncMethod::new(
+                "UdpSocket.send",
+                structured_error,
+                |_, this: LuaUserDataRef<Self>, data: LuaString| async move {
+                    let bytes = data.as_bytes().to_vec();
+                    this.send(&bytes).await
+                },
+            ),
+        );
+
+        // recv(maxSize?: number) -> buffer
+        fields.add_field(
+            "recv",
+            StructuredAsyncMethod::new(
+                "UdpSocket.recv",
+                structured_error,
+                |lua, this: LuaUserDataRef<Self>, max_size: Option<usize>| async move {
+                    let data = this.recv(max_size.unwrap_or(65535)).await?;
+                    lua.create_string(&data)
+                },
+            ),
         );
+    }
 
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         // connect(address: string) -> ()
         methods.add_method("connect", |_, this, addr: String| this.connect(&addr));
 
-        // send(data: buffer) -> number
-        methods.add_async_method("send", |_, this, data: LuaString| async move {
-            let bytes = data.as_bytes().to_vec();
-            this.send(&bytes).await
+        // stats() -> { bytesRead: number, bytesWritten: number }
+        methods.add_method("stats", |lua, this, ()| this.counters.to_lua_table(lua));
+
+        // joinMulticastV4(group: string, interface?: string) -> ()
+        methods.add_method(
+            "joinMulticastV4",
+            |_, this, (group, interface): (String, Option<String>)| {
+                let (group, interface) = parse_multicast_args(&group, interface.as_deref())?;
+                this.join_multicast_v4(group, interface)
+            },
+        );
+
+        // leaveMulticastV4(group: string, interface?: string) -> ()
+        methods.add_method(
+            "leaveMulticastV4",
+            |_, this, (group, interface): (String, Option<String>)| {
+                let (group, interface) = parse_multicast_args(&group, interface.as_deref())?;
+                this.leave_multicast_v4(group, interface)
+            },
+        );
+
+        // setMulticastTtl(ttl: number) -> ()
+        methods.add_method("setMulticastTtl", |_, this, ttl: u32| {
+            this.set_multicast_ttl_v4(ttl)
         });
 
-        // recv(maxSize?: number) -> buffer
-        methods.add_async_method("recv", |lua, this, max_size: Option<usize>| async move {
-            let data = this.recv(max_size.unwrap_or(65535)).await?;
-            lua.create_string(&data)
+        // setBroadcast(enabled: boolean) -> ()
+        methods.add_method("setBroadcast", |_, this, enabled: bool| {
+            this.set_broadcast(enabled)
         });
 
         // close() - not really needed as drop handles it, but for explicitness
@@ -0,0 +1,46 @@
+//! Vectored ("scatter-gather") writes of several buffers at once.
+
+use std::io::{Error, ErrorKind, IoSlice, Result};
+
+use bstr::BString;
+use futures::io::{AsyncWrite, AsyncWriteExt};
+use mlua::prelude::*;
+
+/// Collect the strings and buffers in a Lua array, in order.
+pub fn parts_from_lua(lua: &Lua, parts: &LuaTable) -> LuaResult<Vec<Vec<u8>>> {
+    parts
+        .sequence_values::<LuaValue>()
+        .map(|part| Ok(BString::from_lua(part?, lua)?.into()))
+        .collect()
+}
+
+/**
+    Writes all of the given parts, in order, using as few vectored
+    writes as the writer allows. Returns the total number of bytes written.
+*/
+pub async fn write_all_vectored<W>(writer: &mut W, parts: &[Vec<u8>]) -> Result<usize>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut slices = parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| IoSlice::new(part))
+        .collect::<Vec<_>>();
+    let mut remaining = &mut slices[..];
+
+    let mut total = 0;
+    while !remaining.is_empty() {
+        let written = writer.write_vectored(remaining).await?;
+        if written == 0 {
+            return Err(Error::new(
+                ErrorKind::WriteZero,
+                "failed to write whole buffer",
+            ));
+        }
+        total += written;
+        IoSlice::advance_slices(&mut remaining, written);
+    }
+
+    Ok(total)
+}
@@ -10,6 +10,7 @@ use super::options::ProcessSpawnOptionsStdioKind;
 mod tee_writer;
 mod wait_for_child;
 
+pub(crate) use self::wait_for_child::read_with_stdio_kind;
 use self::wait_for_child::wait_for_child;
 
 pub async fn exec(
@@ -17,7 +17,7 @@ pub(super) struct WaitForChildResult {
     pub stderr: Vec<u8>,
 }
 
-async fn read_with_stdio_kind<R>(
+pub(crate) async fn read_with_stdio_kind<R>(
     read_from: Option<R>,
     kind: ProcessSpawnOptionsStdioKind,
 ) -> LuaResult<Vec<u8>>
@@ -18,8 +18,10 @@ use lune_utils::{
 mod create;
 mod exec;
 mod options;
+mod spawn;
 
 use self::options::ProcessSpawnOptions;
+use self::spawn::{SpawnOptions, SpawnedChild};
 
 const TYPEDEFS: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/types.d.luau"));
 
@@ -85,6 +87,7 @@ pub fn module(lua: Lua) -> LuaResult<LuaTable> {
         .with_value("exit", process_exit)?
         .with_async_function("exec", process_exec)?
         .with_function("create", process_create)?
+        .with_async_function("spawn", process_spawn)?
         .build_readonly()
 }
 
@@ -125,3 +128,39 @@ fn process_create(
 
     create::Child::new(lua, child).into_lua(lua)
 }
+
+async fn process_spawn(
+    lua: Lua,
+    (program, args, options): (String, ProcessArgs, SpawnOptions),
+) -> LuaResult<SpawnedChild> {
+    let SpawnOptions { inner, stream } = options;
+    let mut stdio = inner.stdio.clone();
+    let stdin = stdio.stdin.take();
+
+    let stdin_stdio = if stdin.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    let (stdout_stdio, stderr_stdio) = if stream {
+        (Stdio::piped(), Stdio::piped())
+    } else {
+        (stdio.stdout.as_stdio(), stdio.stderr.as_stdio())
+    };
+
+    let child = inner
+        .into_command(program, args)
+        .stdin(stdin_stdio)
+        .stdout(stdout_stdio)
+        .stderr(stderr_stdio)
+        .spawn()?;
+
+    Ok(SpawnedChild::new(
+        &lua,
+        child,
+        stdin,
+        stream,
+        stdio.stdout,
+        stdio.stderr,
+    ))
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use async_channel::{Receiver, Sender, unbounded};
+use async_lock::OnceCell;
+use async_process::Child as AsyncChild;
+use futures_lite::prelude::*;
+use futures_util::{FutureExt, join, select};
+
+use mlua::prelude::*;
+use mlua_luau_scheduler::LuaSpawnExt;
+
+use crate::create::ChildReader;
+use crate::exec::read_with_stdio_kind;
+use crate::options::{ProcessSpawnOptions, ProcessSpawnOptionsStdioKind};
+
+/**
+    Options for `process.spawn`, which are the same as for `process.exec`
+    with the addition of `stream`, to read output while the process runs.
+*/
+#[derive(Debug, Clone, Default)]
+pub(super) struct SpawnOptions {
+    pub inner: ProcessSpawnOptions,
+    pub stream: bool,
+}
+
+impl FromLua for SpawnOptions {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let stream = match &value {
+            LuaValue::Table(t) => match t.get("stream")? {
+                LuaValue::Nil => false,
+                LuaValue::Boolean(b) => b,
+                value => {
+                    return Err(LuaError::RuntimeError(format!(
+                        "Invalid type for option 'stream' - expected boolean, got '{}'",
+                        value.type_name()
+                    )));
+                }
+            },
+            _ => false,
+        };
+        Ok(Self {
+            inner: ProcessSpawnOptions::from_lua(value, lua)?,
+            stream,
+        })
+    }
+}
+
+/**
+    Final status and any captured output of a spawned process.
+*/
+#[derive(Debug, Clone)]
+struct SpawnOutcome {
+    code: i32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+/**
+    A child process spawned by `process.spawn`.
+
+    Output is either captured in the background and available once the
+    process exits, or, when streaming, read through `stdout` and `stderr`.
+*/
+#[derive(Debug, Clone)]
+pub struct SpawnedChild {
+    streams: Option<(ChildReader, ChildReader)>,
+    kill_tx: Sender<()>,
+    outcome: Arc<OnceCell<Result<SpawnOutcome, String>>>,
+}
+
+impl SpawnedChild {
+    pub fn new(
+        lua: &Lua,
+        mut child: AsyncChild,
+        stdin: Option<Vec<u8>>,
+        stream: bool,
+        stdout_kind: ProcessSpawnOptionsStdioKind,
+        stderr_kind: ProcessSpawnOptionsStdioKind,
+    ) -> Self {
+        let streams = stream.then(|| {
+            (
+                ChildReader::from(child.stdout.take()),
+                ChildReader::from(child.stderr.take()),
+            )
+        });
+
+        // Streamed output is read through the readers instead
+        let (stdout_kind, stderr_kind) = if stream {
+            (
+                ProcessSpawnOptionsStdioKind::None,
+                ProcessSpawnOptionsStdioKind::None,
+            )
+        } else {
+            (stdout_kind, stderr_kind)
+        };
+
+        let (kill_tx, kill_rx) = unbounded();
+        let outcome = Arc::new(OnceCell::new());
+        lua.spawn_local(run_child(
+            child,
+            stdin,
+            stdout_kind,
+            stderr_kind,
+            kill_rx,
+            Arc::clone(&outcome),
+        ));
+
+        Self {
+            streams,
+            kill_tx,
+            outcome,
+        }
+    }
+
+    fn captured(&self, lua: &Lua, pick: fn(&SpawnOutcome) -> &[u8]) -> LuaResult<LuaValue> {
+        match self.outcome.get() {
+            Some(Ok(outcome)) => Ok(LuaValue::String(lua.create_string(pick(outcome))?)),
+            _ => Ok(LuaValue::Nil),
+        }
+    }
+}
+
+impl LuaUserData for SpawnedChild {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("stdout", |lua, this| match &this.streams {
+            Some((stdout, _)) => stdout.clone().into_lua(lua),
+            None => this.captured(lua, |o| &o.stdout),
+        });
+        fields.add_field_method_get("stderr", |lua, this| match &this.streams {
+            Some((_, stderr)) => stderr.clone().into_lua(lua),
+            None => this.captured(lua, |o| &o.stderr),
+        });
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("kill", |_, this, (): ()| {
+            let _ = this.kill_tx.try_send(());
+            Ok(())
+        });
+        methods.add_async_method("wait", |_, this, (): ()| {
+            let outcome = Arc::clone(&this.outcome);
+            async move {
+                match outcome.wait().await {
+                    Ok(outcome) => Ok(outcome.code),
+                    Err(e) => Err(LuaError::runtime(e.clone())),
+                }
+            }
+        });
+    }
+}
+
+async fn run_child(
+    mut child: AsyncChild,
+    stdin: Option<Vec<u8>>,
+    stdout_kind: ProcessSpawnOptionsStdioKind,
+    stderr_kind: ProcessSpawnOptionsStdioKind,
+    kill_rx: Receiver<()>,
+    outcome: Arc<OnceCell<Result<SpawnOutcome, String>>>,
+) {
+    // Write all of stdin up front and close it, same as `process.exec`
+    let child_stdin = child.stdin.take();
+    let write_stdin = async {
+        if let (Some(stdin), Some(mut child_stdin)) = (stdin, child_stdin) {
+            child_stdin.write_all(&stdin).await?;
+            child_stdin.close().await?;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let status = async {
+        select! {
+            s = child.status().fuse() => s.ok(),
+            killed = kill_rx.recv().fuse() => {
+                // The channel also closes when the handle is garbage collected,
+                // which must not kill a process that was left to run on its own
+                if killed.is_ok() {
+                    let _ = child.kill(); // Will only error if already exited
+                }
+                child.status().await.ok()
+            }
+        }
+    };
+
+    let (written, status, stdout, stderr) = join!(
+        write_stdin,
+        status,
+        read_with_stdio_kind(stdout, stdout_kind),
+        read_with_stdio_kind(stderr, stderr_kind),
+    );
+
+    let result = match (written, stdout, stderr) {
+        (Err(e), _, _) => Err(e.to_string()),
+        (_, Err(e), _) | (_, _, Err(e)) => Err(e.to_string()),
+        (Ok(()), Ok(stdout), Ok(stderr)) => Ok(SpawnOutcome {
+            // Killed processes have no exit code, match `ChildProcess:status`
+            code: status.and_then(|s| s.code()).unwrap_or(9),
+            stdout,
+            stderr,
+        }),
+    };
+
+    // Only errors if already set, which never happens
+    let _ = outcome.set(result).await;
+}
@@ -1,92 +1,922 @@
 //! SQL Connection wrapper for SQLite.
 
+use lune_utils::structured_errors::StructuredMethod;
 use mlua::prelude::*;
-use parking_lot::Mutex;
-use rusqlite::Connection;
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use rusqlite::{
+    Connection, Statement,
+    backup::{Backup, StepResult},
+    params_from_iter,
+    types::Value as SqlValue,
+};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
+use crate::datetime;
+use crate::error::structured_error;
+use crate::functions;
+use crate::hooks::{SlowQueryHook, SqlHooks};
+use crate::options::{SqlOpenOptions, SqlQueryOptions};
+use crate::schema;
+use crate::snapshot::SqlSnapshot;
 use crate::statement::SqlStatement;
-use crate::value::lua_to_sql;
+use crate::value::{SqlParams, lua_to_sql};
+
+/// Counter used to give each `sql.memory()` database a unique shared-cache name.
+static MEMORY_DATABASE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Delay before the first busy retry, grows linearly with each further attempt.
+const BUSY_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Handle shared by clones of a connection and the statements prepared from it.
+///
+/// Empty once the connection has been closed.
+pub(crate) type SharedConnection = Arc<Mutex<Option<Connection>>>;
 
 /// SQLite database connection.
+///
+/// Clones share the same underlying handle, and with it the same transaction
+/// scope. Use [`SqlConnection::duplicate`] for an independent handle.
 pub struct SqlConnection {
-    conn: Arc<Mutex<Connection>>,
+    conn: SharedConnection,
     path: String,
+    /// Path and options the handle was opened with, used to open duplicates
+    open_path: Arc<str>,
+    options: SqlOpenOptions,
+    hooks: SqlHooks,
+    attached: Arc<Mutex<HashSet<String>>>,
 }
 
 impl SqlConnection {
-    /// Open a database file.
-    pub fn open(path: &str) -> LuaResult<Self> {
-        let conn = Connection::open(path).into_lua_err()?;
+    fn with_handle(path: &str, open_path: &str, options: SqlOpenOptions) -> LuaResult<Self> {
+        let conn =
+            Connection::open_with_flags(open_path, options.flags(open_path)).into_lua_err()?;
+        options.configure(&conn)?;
         Ok(Self {
-            conn: Arc::new(Mutex::new(conn)),
+            conn: Arc::new(Mutex::new(Some(conn))),
             path: path.to_owned(),
+            open_path: Arc::from(open_path),
+            options,
+            hooks: SqlHooks::default(),
+            attached: Arc::default(),
         })
     }
 
-    /// Open an in-memory database.
+    /// Open a database file, or a `file:` URI when requested by the options,
+    /// then apply the pragmas the options ask for.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the database cannot be opened, if WAL mode is requested for
+    /// an in-memory database, or if a pragma cannot be applied.
+    pub fn open(path: &str, options: &SqlOpenOptions) -> LuaResult<Self> {
+        if options.wal == Some(true) && options.is_memory(path) {
+            return Err(LuaError::external(
+                "WAL mode needs a database file, it cannot be used for in-memory databases",
+            ));
+        }
+        Self::with_handle(path, path, *options)
+    }
+
+    /// Open a private in-memory database, which no other handle can reach.
     pub fn memory() -> LuaResult<Self> {
-        let conn = Connection::open_in_memory().into_lua_err()?;
-        Ok(Self {
-            conn: Arc::new(Mutex::new(conn)),
-            path: ":memory:".to_owned(),
-        })
+        Self::with_handle(":memory:", ":memory:", SqlOpenOptions::default())
     }
 
-    /// Execute a query with parameters. Returns rows for SELECT, affected count for others.
-    pub fn query(&self, lua: &Lua, sql: &str, params: Vec<LuaValue>) -> LuaResult<LuaValue> {
-        let conn = self.conn.lock();
+    /// Open an in-memory database that can be duplicated.
+    ///
+    /// The database is named and uses a shared cache so that duplicates
+    /// reach it, but no other connection can reach it by accident.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the database cannot be opened.
+    pub fn shared_memory() -> LuaResult<Self> {
+        let id = MEMORY_DATABASE_ID.fetch_add(1, Ordering::Relaxed);
+        let uri = format!(
+            "file:lune-memory-{}-{id}?mode=memory&cache=shared",
+            std::process::id()
+        );
+        let options = SqlOpenOptions {
+            uri: Some(true),
+            ..SqlOpenOptions::default()
+        };
+        Self::with_handle(":memory:", &uri, options)
+    }
+
+    /// Open a new, independent handle to the same database.
+    ///
+    /// Unlike a clone, the duplicate has its own transaction scope, hooks
+    /// and attached databases, and does not serialize on this handle.
+    ///
+    /// # Errors
+    ///
+    /// Errors for private in-memory databases, which cannot be shared,
+    /// or if the database can no longer be opened.
+    pub fn duplicate(&self) -> LuaResult<Self> {
+        if self.open_path.is_empty() || &*self.open_path == ":memory:" {
+            return Err(LuaError::external(
+                "Cannot duplicate a private in-memory database, use sql.sharedMemory() instead",
+            ));
+        }
+        Self::with_handle(&self.path, &self.open_path, self.options)
+    }
+
+    /// Run `f`, retrying with a short backoff while the database is busy or locked.
+    ///
+    /// `f` must take the connection lock itself, so that it is released between attempts.
+    fn with_busy_retry<T>(&self, f: impl FnMut() -> LuaResult<T>) -> LuaResult<T> {
+        with_busy_retry(self.options.retry_busy, f)
+    }
+
+    /// Execute a query with parameters. Returns rows for statements that yield
+    /// columns, and the affected count for others.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the statement fails to prepare or run, if a parameter cannot
+    /// be converted, or if a column in `options.dates` cannot be read as a date.
+    pub fn query(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<LuaValue> {
+        let start = Instant::now();
+        let result = self.with_busy_retry(|| self.query_inner(lua, sql, params, options))?;
+        self.hooks.report_query(sql, start.elapsed())?;
+        Ok(result)
+    }
+
+    fn query_inner(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<LuaValue> {
+        let conn = lock_connection(&self.conn)?;
         let mut stmt = conn.prepare(sql).into_lua_err()?;
 
-        let param_values: Vec<_> = params
-            .into_iter()
-            .map(|v| lua_to_sql(&v))
-            .collect::<LuaResult<_>>()?;
+        let param_values = params.to_sql(lua, &stmt)?;
 
         let param_refs: Vec<&dyn rusqlite::ToSql> = param_values
             .iter()
             .map(|v| v as &dyn rusqlite::ToSql)
             .collect();
 
-        // Check if it's a SELECT query
-        if sql.trim().to_uppercase().starts_with("SELECT") {
-            let column_names: Vec<String> = stmt
-                .column_names()
-                .iter()
-                .map(|s| (*s).to_owned())
-                .collect();
-
-            let mut rows = stmt.query(param_refs.as_slice()).into_lua_err()?;
+        // Statements that yield columns, such as SELECT, WITH, PRAGMA or anything
+        // with RETURNING, return their rows, while plain writes return a count
+        if stmt.column_count() > 0 {
+            let rows = collect_rows(lua, &mut stmt, &param_refs, options, None)?;
+            Ok(LuaValue::Table(rows))
+        } else {
+            let affected = stmt.execute(param_refs.as_slice()).into_lua_err()?;
+            if !options.with_row_id {
+                return Ok(LuaValue::Integer(affected as i64));
+            }
             let result = lua.create_table()?;
-            let mut idx = 1;
+            result.set("affected", affected)?;
+            result.set("lastRowId", conn.last_insert_rowid())?;
+            Ok(LuaValue::Table(result))
+        }
+    }
 
-            while let Some(row) = rows.next().into_lua_err()? {
-                let row_table = lua.create_table()?;
-                for (i, name) in column_names.iter().enumerate() {
-                    let value = crate::value::sql_to_lua(lua, row, i)?;
-                    row_table.set(name.as_str(), value)?;
-                }
-                result.set(idx, row_table)?;
-                idx += 1;
-            }
+    /// Execute a query and return its first row, or `nil` if it yields none.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the query fails, or if `options.strict` is set and the
+    /// query yields more than one row.
+    pub fn query_one(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<Option<LuaTable>> {
+        let start = Instant::now();
+        let result = self.with_busy_retry(|| self.query_one_inner(lua, sql, params, options))?;
+        self.hooks.report_query(sql, start.elapsed())?;
+        Ok(result)
+    }
 
-            Ok(LuaValue::Table(result))
-        } else {
-            let affected = stmt.execute(param_refs.as_slice()).into_lua_err()?;
-            Ok(LuaValue::Integer(affected as i64))
+    fn query_one_inner(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<Option<LuaTable>> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn.prepare(sql).into_lua_err()?;
+
+        let param_values = params.to_sql(lua, &stmt)?;
+
+        let param_refs: Vec<&dyn rusqlite::ToSql> = param_values
+            .iter()
+            .map(|v| v as &dyn rusqlite::ToSql)
+            .collect();
+
+        // Strict lookups read one row past the first, to tell if there is more than one
+        let max_rows = if options.strict { 2 } else { 1 };
+        let rows = collect_rows(lua, &mut stmt, &param_refs, options, Some(max_rows))?;
+        if rows.raw_len() > 1 {
+            return Err(LuaError::external(
+                "Expected at most one row from queryOne, but the query returned more",
+            ));
         }
+        rows.get(1)
+    }
+
+    /// Execute a query and return its rows along with the result columns, in order.
+    ///
+    /// Each column is a `{ name, declType }` table, where `declType` is the type
+    /// the column was declared with, or `nil` for expressions.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the query fails.
+    pub fn query_with_meta(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<LuaTable> {
+        let start = Instant::now();
+        let result =
+            self.with_busy_retry(|| self.query_with_meta_inner(lua, sql, params, options))?;
+        self.hooks.report_query(sql, start.elapsed())?;
+        Ok(result)
+    }
+
+    fn query_with_meta_inner(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<LuaTable> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn.prepare(sql).into_lua_err()?;
+
+        let columns = lua.create_table()?;
+        for column in stmt.columns() {
+            let meta = lua.create_table()?;
+            meta.set("name", column.name())?;
+            meta.set("declType", column.decl_type())?;
+            columns.push(meta)?;
+        }
+
+        let param_values = params.to_sql(lua, &stmt)?;
+
+        let param_refs: Vec<&dyn rusqlite::ToSql> = param_values
+            .iter()
+            .map(|v| v as &dyn rusqlite::ToSql)
+            .collect();
+
+        let rows = collect_rows(lua, &mut stmt, &param_refs, options, None)?;
+        let result = lua.create_table()?;
+        result.set("columns", columns)?;
+        result.set("rows", rows)?;
+        Ok(result)
+    }
+
+    /// Execute a statement and always return the rows it yields,
+    /// such as those produced by `INSERT/UPDATE/DELETE ... RETURNING`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the statement fails to prepare or run, or if a parameter
+    /// cannot be converted.
+    pub fn execute_returning(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<LuaTable> {
+        let start = Instant::now();
+        let result =
+            self.with_busy_retry(|| self.execute_returning_inner(lua, sql, params, options))?;
+        self.hooks.report_query(sql, start.elapsed())?;
+        Ok(result)
+    }
+
+    fn execute_returning_inner(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &SqlParams,
+        options: &SqlQueryOptions,
+    ) -> LuaResult<LuaTable> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn.prepare(sql).into_lua_err()?;
+
+        let param_values = params.to_sql(lua, &stmt)?;
+
+        let param_refs: Vec<&dyn rusqlite::ToSql> = param_values
+            .iter()
+            .map(|v| v as &dyn rusqlite::ToSql)
+            .collect();
+
+        collect_rows(lua, &mut stmt, &param_refs, options, None)
+    }
+
+    /// Execute one statement once for each parameter row, inside one transaction,
+    /// and return the total number of rows affected.
+    ///
+    /// The statement is prepared once and the connection locked once for
+    /// every row. Rows may bind their parameters by position or by name.
+    ///
+    /// # Errors
+    ///
+    /// Errors if a row cannot be converted, if a transaction is already in
+    /// progress, or if any row fails, in which case none are applied.
+    pub fn query_many(&self, lua: &Lua, sql: &str, rows: &LuaTable) -> LuaResult<usize> {
+        let rows = rows
+            .sequence_values::<LuaValue>()
+            .enumerate()
+            .map(|(i, row)| {
+                SqlParams::from_lua(row?, lua)
+                    .with_context(|_| format!("Row {} has invalid parameters", i + 1))
+            })
+            .collect::<LuaResult<Vec<_>>>()?;
+        let start = Instant::now();
+        let affected = self.with_busy_retry(|| self.query_many_inner(lua, sql, &rows))?;
+        self.hooks.report_query(sql, start.elapsed())?;
+        Ok(affected)
+    }
+
+    fn query_many_inner(&self, lua: &Lua, sql: &str, rows: &[SqlParams]) -> LuaResult<usize> {
+        let mut conn = lock_connection(&self.conn)?;
+        if !conn.is_autocommit() {
+            return Err(LuaError::external(
+                "Cannot run queryMany, a transaction is already in progress",
+            ));
+        }
+
+        // Rolled back when dropped without being committed
+        let tx = conn.transaction().into_lua_err()?;
+        let mut total = 0;
+        {
+            let mut stmt = tx.prepare_cached(sql).into_lua_err()?;
+            for (i, params) in rows.iter().enumerate() {
+                let affected = params
+                    .to_sql(lua, &stmt)
+                    .and_then(|values| stmt.execute(params_from_iter(values)).into_lua_err())
+                    .with_context(|_| format!("Row {} of queryMany failed", i + 1))?;
+                total += affected;
+            }
+        }
+        tx.commit().into_lua_err()?;
+        Ok(total)
+    }
+
+    /// Rowid of the most recent successful insert on this handle, or 0 if there was none.
+    ///
+    /// Clones share the handle, so an insert through any of them counts.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the connection is closed or busy.
+    pub fn last_insert_rowid(&self) -> LuaResult<i64> {
+        Ok(lock_connection(&self.conn)?.last_insert_rowid())
     }
 
     /// Execute multiple statements (for schema creation).
     pub fn exec(&self, sql: &str) -> LuaResult<()> {
-        let conn = self.conn.lock();
-        conn.execute_batch(sql).into_lua_err()
+        self.with_busy_retry(|| {
+            let conn = lock_connection(&self.conn)?;
+            conn.execute_batch(sql).into_lua_err()
+        })
+    }
+
+    /// Run several parameterized statements in order, inside one transaction.
+    ///
+    /// Each entry of `statements` is a `{ sql, params }` table, with either
+    /// named or positional fields. Returns the rows affected by each statement.
+    ///
+    /// # Errors
+    ///
+    /// Errors if an entry is malformed, if a transaction is already in
+    /// progress, or if any statement fails, in which case none are applied.
+    pub fn execute_script(&self, lua: &Lua, statements: &LuaTable) -> LuaResult<Vec<usize>> {
+        let script = statements
+            .sequence_values::<LuaValue>()
+            .enumerate()
+            .map(|(i, entry)| script_entry(lua, i + 1, entry?))
+            .collect::<LuaResult<Vec<_>>>()?;
+        self.with_busy_retry(|| self.execute_script_inner(&script))
+    }
+
+    fn execute_script_inner(&self, script: &[(String, Vec<SqlValue>)]) -> LuaResult<Vec<usize>> {
+        let mut conn = lock_connection(&self.conn)?;
+        if !conn.is_autocommit() {
+            return Err(LuaError::external(
+                "Cannot run a script, a transaction is already in progress",
+            ));
+        }
+
+        // Rolled back when dropped without being committed
+        let tx = conn.transaction().into_lua_err()?;
+        let mut counts = Vec::with_capacity(script.len());
+        for (i, (sql, params)) in script.iter().enumerate() {
+            let affected = tx
+                .prepare_cached(sql)
+                .and_then(|mut stmt| stmt.execute(params_from_iter(params)))
+                .into_lua_err()
+                .with_context(|_| format!("Statement {} of the script failed", i + 1))?;
+            counts.push(affected);
+        }
+        tx.commit().into_lua_err()?;
+        Ok(counts)
+    }
+
+    /// Attach another database file under the given schema name.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the schema name is invalid or already attached,
+    /// or if the file cannot be attached.
+    pub fn attach(&self, path: &str, schema: &str) -> LuaResult<()> {
+        validate_schema_name(schema)?;
+        let mut attached = self.attached.lock();
+        if attached.contains(schema) {
+            return Err(LuaError::external(format!(
+                "Database '{schema}' is already attached"
+            )));
+        }
+        let conn = lock_connection(&self.conn)?;
+        conn.execute(&format!("ATTACH DATABASE ?1 AS \"{schema}\""), [path])
+            .into_lua_err()?;
+        attached.insert(schema.to_owned());
+        Ok(())
+    }
+
+    /// Detach a database previously attached with [`SqlConnection::attach`].
+    ///
+    /// # Errors
+    ///
+    /// Errors if the schema name is invalid or not attached, or if it
+    /// cannot be detached, such as while a transaction is using it.
+    pub fn detach(&self, schema: &str) -> LuaResult<()> {
+        validate_schema_name(schema)?;
+        let mut attached = self.attached.lock();
+        if !attached.contains(schema) {
+            return Err(LuaError::external(format!(
+                "Database '{schema}' is not attached"
+            )));
+        }
+        let conn = lock_connection(&self.conn)?;
+        conn.execute_batch(&format!("DETACH DATABASE \"{schema}\""))
+            .into_lua_err()?;
+        attached.remove(schema);
+        Ok(())
+    }
+
+    /// Names of all user tables in the main database.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the connection is closed or busy, or if reading the schema fails.
+    pub fn tables(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let conn = lock_connection(&self.conn)?;
+        schema::tables(lua, &conn)
+    }
+
+    /// Column descriptors for a table, from `PRAGMA table_info`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the table does not exist, or if reading the schema fails.
+    pub fn columns(&self, lua: &Lua, table: &str) -> LuaResult<LuaTable> {
+        let conn = lock_connection(&self.conn)?;
+        schema::columns(lua, &conn, table)
+    }
+
+    /// Index descriptors for a table, from `PRAGMA index_list`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the table does not exist, or if reading the schema fails.
+    pub fn indexes(&self, lua: &Lua, table: &str) -> LuaResult<LuaTable> {
+        let conn = lock_connection(&self.conn)?;
+        schema::indexes(lua, &conn, table)
+    }
+
+    /// Register a Lua function callable from SQL as `name`, see [`functions::register`].
+    ///
+    /// Registering the same name and argument count again replaces the function.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the argument count is out of range, or if the connection is closed or busy.
+    pub fn register_function(
+        &self,
+        lua: &Lua,
+        name: &str,
+        n_args: i32,
+        function: LuaFunction,
+    ) -> LuaResult<()> {
+        let conn = lock_connection(&self.conn)?;
+        functions::register(lua, &conn, name, n_args, function)
+    }
+
+    /// Run `f` inside a transaction, passing it this connection.
+    ///
+    /// Commits once `f` returns, and rolls back and re-raises if it errors.
+    ///
+    /// # Errors
+    ///
+    /// Errors if a transaction is already in progress, or with the error raised by `f`.
+    pub fn transaction(&self, f: &LuaFunction) -> LuaResult<LuaMultiValue> {
+        if !lock_connection(&self.conn)?.is_autocommit() {
+            return Err(LuaError::external(
+                "Cannot start a transaction, one is already in progress",
+            ));
+        }
+
+        self.exec("BEGIN")?;
+        let result = f
+            .call::<LuaMultiValue>(self.clone())
+            .and_then(|values| self.exec("COMMIT").map(|()| values));
+        if result.is_err() {
+            // A failed COMMIT may also leave the transaction open
+            self.rollback_if_open()?;
+        }
+        result
+    }
+
+    /// Rebuild the database file, reclaiming the space left by deleted rows.
+    ///
+    /// # Errors
+    ///
+    /// Errors if a transaction is in progress, as `VACUUM` cannot run inside one.
+    pub fn vacuum(&self) -> LuaResult<()> {
+        if !lock_connection(&self.conn)?.is_autocommit() {
+            return Err(LuaError::external(
+                "Cannot vacuum, a transaction is in progress",
+            ));
+        }
+        self.exec("VACUUM")
+    }
+
+    /// Refresh query planner statistics that are out of date, with `PRAGMA optimize`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the connection is closed or busy.
+    pub fn optimize(&self) -> LuaResult<()> {
+        self.exec("PRAGMA optimize")
+    }
+
+    /// Gather query planner statistics for `table`, or for every table when `None`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `table` does not exist.
+    pub fn analyze(&self, table: Option<&str>) -> LuaResult<()> {
+        match table {
+            Some(table) => self.exec(&format!("ANALYZE \"{}\"", table.replace('"', "\"\""))),
+            None => self.exec("ANALYZE"),
+        }
+    }
+
+    /// Open a savepoint named `name`, starting a transaction if none is in progress.
+    ///
+    /// Unlike [`SqlConnection::transaction`], savepoints nest, so library code can
+    /// open one whether or not its caller already started a transaction.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `name` is not a plain identifier, or if the statement fails.
+    pub fn savepoint(&self, name: &str) -> LuaResult<()> {
+        validate_identifier("savepoint", name)?;
+        self.exec(&format!("SAVEPOINT {name}"))
+    }
+
+    /// Release the savepoint `name` and every savepoint opened after it,
+    /// keeping their changes. Releasing the outermost savepoint commits.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `name` is not a plain identifier, or if no such savepoint is open.
+    pub fn release_savepoint(&self, name: &str) -> LuaResult<()> {
+        validate_identifier("savepoint", name)?;
+        self.exec(&format!("RELEASE SAVEPOINT {name}"))
+    }
+
+    /// Undo every change made since the savepoint `name` was opened.
+    ///
+    /// The savepoint itself stays open, and must still be released.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `name` is not a plain identifier, or if no such savepoint is open.
+    pub fn rollback_to(&self, name: &str) -> LuaResult<()> {
+        validate_identifier("savepoint", name)?;
+        self.exec(&format!("ROLLBACK TO SAVEPOINT {name}"))
+    }
+
+    /// Copy the whole database into a new file at `dest_path`, replacing
+    /// whatever database the file held before.
+    ///
+    /// Pages are copied in a single step, so the source is read-locked for
+    /// the duration and writers wait for the copy instead of restarting it.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the destination cannot be opened or written.
+    pub fn backup(&self, dest_path: &str) -> LuaResult<bool> {
+        let mut dest = Connection::open(dest_path).into_lua_err()?;
+        let source = lock_connection(&self.conn)?;
+        run_backup(&source, &mut dest)
+    }
+
+    /// Copy the whole database into the main database of `other`,
+    /// replacing its contents.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `other` shares this connection's handle, if either
+    /// connection is closed or busy, or if the copy fails.
+    pub fn backup_to(&self, other: &SqlConnection) -> LuaResult<bool> {
+        if Arc::ptr_eq(&self.conn, &other.conn) {
+            return Err(LuaError::external(
+                "Cannot back up a connection into itself, use a duplicate or another database",
+            ));
+        }
+        let source = lock_connection(&self.conn)?;
+        let mut dest = lock_connection(&other.conn)?;
+        run_backup(&source, &mut dest)
+    }
+
+    fn rollback_if_open(&self) -> LuaResult<()> {
+        if self.is_closed() {
+            // Closing already rolled back whatever was in progress
+            return Ok(());
+        }
+        let conn = lock_connection(&self.conn)?;
+        if !conn.is_autocommit() {
+            conn.execute_batch("ROLLBACK").into_lua_err()?;
+        }
+        Ok(())
+    }
+
+    /// Close the underlying handle, releasing the database file.
+    ///
+    /// Every clone of this connection and every statement prepared from it
+    /// shares the handle, and errors once it is closed. Duplicates have
+    /// their own handle and stay open. Closing twice does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the connection is in use, or if closing the handle fails,
+    /// in which case it stays open.
+    pub fn close(&self) -> LuaResult<()> {
+        let mut guard = self.conn.try_lock().ok_or_else(|| {
+            LuaError::external(
+                "Database connection is busy, it may not be closed from inside forEach",
+            )
+        })?;
+        let Some(conn) = guard.take() else {
+            return Ok(());
+        };
+        if let Err((conn, err)) = conn.close() {
+            *guard = Some(conn);
+            return Err(err).into_lua_err();
+        }
+        self.attached.lock().clear();
+        Ok(())
+    }
+
+    /// Whether [`SqlConnection::close`] has been called on this handle.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.conn.try_lock().is_some_and(|conn| conn.is_none())
+    }
+
+    /// Run a read-only query inside a deferred read transaction on a new handle,
+    /// returning a [`SqlSnapshot`] that yields its rows one at a time.
+    ///
+    /// In WAL mode the snapshot is consistent and does not block writers,
+    /// in other journal modes it holds a shared lock until it is exhausted or closed.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the database cannot be duplicated, or if the query fails or writes.
+    pub fn snapshot_query(
+        &self,
+        lua: &Lua,
+        sql: &str,
+        params: &[LuaValue],
+    ) -> LuaResult<SqlSnapshot> {
+        let params = params
+            .iter()
+            .map(|v| lua_to_sql(lua, v))
+            .collect::<LuaResult<_>>()?;
+        let conn = self
+            .duplicate()?
+            .conn
+            .lock()
+            .take()
+            .ok_or_else(|| LuaError::external("Database connection is closed"))?;
+        SqlSnapshot::start(conn, sql.to_owned(), params)
     }
 
     /// Prepare a statement for repeated execution.
+    ///
+    /// The compiled statement is taken from, and returned to, the connection's
+    /// statement cache, so preparing the same SQL again does not recompile it.
     pub fn prepare(&self, sql: &str) -> LuaResult<SqlStatement> {
-        SqlStatement::new(Arc::clone(&self.conn), sql.to_owned())
+        SqlStatement::new(
+            Arc::clone(&self.conn),
+            sql.to_owned(),
+            self.hooks.clone(),
+            self.options.retry_busy,
+        )
+    }
+}
+
+/// Run `stmt` and read its rows into an array of row tables,
+/// stopping after `max_rows` rows when given.
+pub(crate) fn collect_rows(
+    lua: &Lua,
+    stmt: &mut Statement<'_>,
+    params: &[&dyn rusqlite::ToSql],
+    options: &SqlQueryOptions,
+    max_rows: Option<usize>,
+) -> LuaResult<LuaTable> {
+    let column_names: Vec<String> = stmt
+        .column_names()
+        .iter()
+        .map(|s| (*s).to_owned())
+        .collect();
+
+    if let Some(unknown) = options
+        .dates
+        .keys()
+        .find(|column| !column_names.contains(column))
+    {
+        return Err(LuaError::external(format!(
+            "Date hint given for column '{unknown}', which is not in the result"
+        )));
+    }
+    let date_hints: Vec<_> = column_names
+        .iter()
+        .map(|name| options.dates.get(name).copied())
+        .collect();
+
+    let mut rows = stmt.query(params).into_lua_err()?;
+    let result = lua.create_table()?;
+    let mut idx = 1;
+
+    while max_rows.is_none_or(|max| idx <= max)
+        && let Some(row) = rows.next().into_lua_err()?
+    {
+        let row_table = lua.create_table()?;
+        for (i, name) in column_names.iter().enumerate() {
+            let value = match date_hints[i] {
+                Some(hint) => {
+                    let raw = row.get_ref(i).into_lua_err()?;
+                    datetime::read_column(lua, raw, hint, name)?
+                }
+                None => crate::value::sql_to_lua(lua, row, i, options.blobs_as_buffer)?,
+            };
+            row_table.set(name.as_str(), value)?;
+        }
+        result.set(idx, row_table)?;
+        idx += 1;
     }
+
+    Ok(result)
+}
+
+/// Read a `{ sql, params }` entry of a script, converting its parameters up front.
+fn script_entry(lua: &Lua, index: usize, entry: LuaValue) -> LuaResult<(String, Vec<SqlValue>)> {
+    let LuaValue::Table(entry) = entry else {
+        return Err(LuaError::external(format!(
+            "Script entry {index} must be a {{ sql, params }} table, got {}",
+            entry.type_name()
+        )));
+    };
+    let sql = match entry.get::<Option<String>>("sql")? {
+        Some(sql) => sql,
+        None => entry.get::<Option<String>>(1)?.ok_or_else(|| {
+            LuaError::external(format!("Script entry {index} is missing its SQL"))
+        })?,
+    };
+    let params = match entry.get::<Option<LuaTable>>("params")? {
+        Some(params) => Some(params),
+        None => entry.get::<Option<LuaTable>>(2)?,
+    };
+    let params = params
+        .map(|t| {
+            t.sequence_values::<LuaValue>()
+                .map(|v| lua_to_sql(lua, &v?))
+                .collect::<LuaResult<Vec<_>>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+    Ok((sql, params))
+}
+
+/// Copy every page of `source` into `dest` in one step.
+///
+/// Returns `true` once the backup is complete.
+fn run_backup(source: &Connection, dest: &mut Connection) -> LuaResult<bool> {
+    let backup = Backup::new(source, dest).into_lua_err()?;
+    match backup.step(-1).into_lua_err()? {
+        StepResult::Done => Ok(true),
+        StepResult::Busy | StepResult::Locked => Err(LuaError::external(
+            "Cannot back up, the database is locked by another connection",
+        )),
+        // A negative page count copies every page at once, so nothing else is expected
+        _ => Err(LuaError::external(
+            "Backup stopped before every page was copied",
+        )),
+    }
+}
+
+/// Lock a connection for exclusive use.
+///
+/// Lua runs on a single thread, so the lock can only be held already when a
+/// callback (such as one passed to `stmt:forEach`) tries to use the connection
+/// that is invoking it. Error out instead of deadlocking in that case.
+pub(crate) fn lock_connection(
+    conn: &Mutex<Option<Connection>>,
+) -> LuaResult<MappedMutexGuard<'_, Connection>> {
+    let guard = conn.try_lock().ok_or_else(|| {
+        LuaError::external("Database connection is busy, it may not be used from inside forEach")
+    })?;
+    MutexGuard::try_map(guard, Option::as_mut)
+        .map_err(|_| LuaError::external("Database connection is closed"))
+}
+
+/// Run `f`, retrying up to `retry_busy` times with a short backoff while the
+/// database is busy or locked.
+///
+/// The backoff sleeps the current thread, so the Lua scheduler is blocked while waiting.
+pub(crate) fn with_busy_retry<T>(
+    retry_busy: u32,
+    mut f: impl FnMut() -> LuaResult<T>,
+) -> LuaResult<T> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(err) if attempt < retry_busy && is_busy_error(&err) => {
+                attempt += 1;
+                std::thread::sleep(BUSY_RETRY_BACKOFF * attempt);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether `err` means another connection holds a conflicting lock.
+fn is_busy_error(err: &LuaError) -> bool {
+    let code = match err {
+        LuaError::WithContext { cause, .. } => return is_busy_error(cause),
+        LuaError::ExternalError(inner) => inner
+            .downcast_ref::<rusqlite::Error>()
+            .and_then(rusqlite::Error::sqlite_error_code),
+        _ => None,
+    };
+    matches!(
+        code,
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Schema names are interpolated into ATTACH / DETACH, so only allow plain identifiers.
+fn validate_schema_name(schema: &str) -> LuaResult<()> {
+    validate_identifier("schema", schema)?;
+    if schema.eq_ignore_ascii_case("main") || schema.eq_ignore_ascii_case("temp") {
+        return Err(LuaError::external(format!(
+            "Schema name '{schema}' is reserved"
+        )));
+    }
+    Ok(())
+}
+
+/// Names interpolated into statements, such as savepoints, may only be plain identifiers.
+fn validate_identifier(kind: &str, name: &str) -> LuaResult<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(LuaError::external(format!(
+            "Invalid {kind} name '{name}', expected letters, digits and underscores"
+        )));
+    }
+    Ok(())
 }
 
 impl Clone for SqlConnection {
@@ -94,6 +924,10 @@ impl Clone for SqlConnection {
         Self {
             conn: Arc::clone(&self.conn),
             path: self.path.clone(),
+            open_path: Arc::clone(&self.open_path),
+            options: self.options,
+            hooks: self.hooks.clone(),
+            attached: Arc::clone(&self.attached),
         }
     }
 }
@@ -101,29 +935,311 @@ impl Clone for SqlConnection {
 impl LuaUserData for SqlConnection {
     fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
         fields.add_field_method_get("path", |_, this| Ok(this.path.clone()));
+        fields.add_field_method_get("isClosed", |_, this| Ok(this.is_closed()));
+
+        // Methods running SQL raise SQLite failures as { message, code, name } tables
+
+        // query(sql: string, params: SqlParams?, options: SqlQueryOptions?) -> {rows} | number
+        // CRITICAL: Params are REQUIRED for any user input to prevent SQL injection
+        fields.add_field(
+            "query",
+            StructuredMethod::new(
+                "SqlConnection.query",
+                structured_error,
+                |lua, this: &Self, (sql, params, options): (String, SqlParams, SqlQueryOptions)| {
+                    this.query(lua, &sql, &params, &options)
+                },
+            ),
+        );
+
+        // queryOne(sql: string, params: SqlParams?, options: SqlQueryOptions?) -> row?
+        // Returns the first row, erroring on extra rows when options.strict is set
+        fields.add_field(
+            "queryOne",
+            StructuredMethod::new(
+                "SqlConnection.queryOne",
+                structured_error,
+                |lua, this: &Self, (sql, params, options): (String, SqlParams, SqlQueryOptions)| {
+                    this.query_one(lua, &sql, &params, &options)
+                },
+            ),
+        );
+
+        // queryWithMeta(sql: string, params: SqlParams?, options: SqlQueryOptions?) -> { columns, rows }
+        // Columns are { name, declType } tables in result order
+        fields.add_field(
+            "queryWithMeta",
+            StructuredMethod::new(
+                "SqlConnection.queryWithMeta",
+                structured_error,
+                |lua, this: &Self, (sql, params, options): (String, SqlParams, SqlQueryOptions)| {
+                    this.query_with_meta(lua, &sql, &params, &options)
+                },
+            ),
+        );
+
+        // executeReturning(sql: string, params: SqlParams?, options: SqlQueryOptions?) -> {rows}
+        // Always returns rows, for INSERT/UPDATE/DELETE ... RETURNING
+        fields.add_field(
+            "executeReturning",
+            StructuredMethod::new(
+                "SqlConnection.executeReturning",
+                structured_error,
+                |lua, this: &Self, (sql, params, options): (String, SqlParams, SqlQueryOptions)| {
+                    this.execute_returning(lua, &sql, &params, &options)
+                },
+            ),
+        );
+
+        // executeScript(statements: {{ sql: string, params: {any}? }}) -> {number}
+        // Runs every statement in one transaction, rolling all of them back on failure
+        fields.add_field(
+            "executeScript",
+            StructuredMethod::new(
+                "SqlConnection.executeScript",
+                structured_error,
+                |lua, this: &Self, statements: LuaTable| this.execute_script(lua, &statements),
+            ),
+        );
+
+        // queryMany(sql: string, rows: {SqlParams}) -> number
+        // Prepares once and runs every row in one transaction, rolling all of them back on failure
+        fields.add_field(
+            "queryMany",
+            StructuredMethod::new(
+                "SqlConnection.queryMany",
+                structured_error,
+                |lua, this: &Self, (sql, rows): (String, LuaTable)| {
+                    this.query_many(lua, &sql, &rows)
+                },
+            ),
+        );
+
+        // exec(sql: string) -> () - For schema operations only
+        fields.add_field(
+            "exec",
+            StructuredMethod::new(
+                "SqlConnection.exec",
+                structured_error,
+                |_, this: &Self, sql: String| this.exec(&sql),
+            ),
+        );
     }
 
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        // query(sql: string, params: {any}?) -> {rows} | number
-        // CRITICAL: Params are REQUIRED for any user input to prevent SQL injection
+        // lastInsertRowId() -> number
+        methods.add_method("lastInsertRowId", |_, this, ()| this.last_insert_rowid());
+
+        // attach(path: string, schema: string) -> ()
+        methods.add_method("attach", |_, this, (path, schema): (String, String)| {
+            this.attach(&path, &schema)
+        });
+
+        // detach(schema: string) -> ()
+        methods.add_method("detach", |_, this, schema: String| this.detach(&schema));
+
+        // tables() -> {string}
+        methods.add_method("tables", |lua, this, ()| this.tables(lua));
+
+        // columns(table: string) -> {SqlColumnInfo}
+        methods.add_method("columns", |lua, this, table: String| {
+            this.columns(lua, &table)
+        });
+
+        // indexes(table: string) -> {SqlIndexInfo}
+        methods.add_method("indexes", |lua, this, table: String| {
+            this.indexes(lua, &table)
+        });
+
+        // duplicate() -> SqlConnection - Independent handle to the same database
+        methods.add_method("duplicate", |_, this, ()| this.duplicate());
+
+        // snapshotQuery(sql: string, params: {any}?) -> SqlSnapshot
+        // Rows are read lazily inside a read transaction on a separate handle
         methods.add_method(
-            "query",
+            "snapshotQuery",
             |lua, this, (sql, params): (String, Option<LuaTable>)| {
                 let params: Vec<LuaValue> = params
                     .map(|t| t.sequence_values().collect::<LuaResult<_>>())
                     .transpose()?
                     .unwrap_or_default();
-                this.query(lua, &sql, params)
+                this.snapshot_query(lua, &sql, &params)
             },
         );
 
-        // exec(sql: string) -> () - For schema operations only
-        methods.add_method("exec", |_, this, sql: String| this.exec(&sql));
-
         // prepare(sql: string) -> SqlStatement
         methods.add_method("prepare", |_, this, sql: String| this.prepare(&sql));
 
-        // close() - Connection is closed on drop
-        methods.add_method("close", |_, _, ()| Ok(()));
+        // prepareCached(sql: string) -> SqlStatement
+        // Statements always come from the connection's cache, this just makes that explicit
+        methods.add_method("prepareCached", |_, this, sql: String| this.prepare(&sql));
+
+        // transaction(fn: (conn) -> ...any) -> ...any
+        // Commits when fn returns, rolls back and re-raises when it errors
+        methods.add_method("transaction", |_, this, f: LuaFunction| {
+            this.transaction(&f)
+        });
+
+        // vacuum() -> () - Errors inside a transaction
+        methods.add_method("vacuum", |_, this, ()| this.vacuum());
+
+        // optimize() -> ()
+        methods.add_method("optimize", |_, this, ()| this.optimize());
+
+        // analyze(table: string?) -> ()
+        methods.add_method("analyze", |_, this, table: Option<String>| {
+            this.analyze(table.as_deref())
+        });
+
+        // backup(destPath: string) -> boolean
+        methods.add_method("backup", |_, this, dest_path: String| {
+            this.backup(&dest_path)
+        });
+
+        // backupTo(other: SqlConnection) -> boolean
+        methods.add_method(
+            "backupTo",
+            |_, this, other: LuaUserDataRef<SqlConnection>| this.backup_to(&other),
+        );
+
+        // savepoint(name: string) -> ()
+        methods.add_method("savepoint", |_, this, name: String| this.savepoint(&name));
+
+        // releaseSavepoint(name: string) -> ()
+        methods.add_method("releaseSavepoint", |_, this, name: String| {
+            this.release_savepoint(&name)
+        });
+
+        // rollbackTo(name: string) -> ()
+        methods.add_method("rollbackTo", |_, this, name: String| {
+            this.rollback_to(&name)
+        });
+
+        // registerFunction(name: string, nargs: number, fn: (...any) -> any) -> ()
+        methods.add_method(
+            "registerFunction",
+            |lua, this, (name, n_args, function): (String, i32, LuaFunction)| {
+                this.register_function(lua, &name, n_args, function)
+            },
+        );
+
+        // onSlowQuery(thresholdMs: number, fn: ((sql, elapsedMs) -> ())?) -> ()
+        methods.add_method(
+            "onSlowQuery",
+            |_, this, (threshold_ms, callback): (f64, Option<LuaFunction>)| {
+                let hook = callback
+                    .map(|f| SlowQueryHook::new(threshold_ms, f))
+                    .transpose()?;
+                this.hooks.set_slow_query(hook);
+                Ok(())
+            },
+        );
+
+        // close() - Closes the handle for every clone, errors on later use
+        methods.add_method("close", |_, this, ()| this.close());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Hold the write lock on `path` from another thread for `hold`, signalling once it is taken.
+    fn hold_write_lock(path: &str, hold: Duration) -> thread::JoinHandle<()> {
+        let path = path.to_owned();
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch("BEGIN IMMEDIATE; INSERT INTO items (n) VALUES (0);")
+                .unwrap();
+            locked_tx.send(()).unwrap();
+            thread::sleep(hold);
+            conn.execute_batch("COMMIT").unwrap();
+        });
+        locked_rx.recv().unwrap();
+        handle
+    }
+
+    fn open_without_busy_timeout(path: &str, retry_busy: u32) -> SqlConnection {
+        let options = SqlOpenOptions {
+            retry_busy,
+            ..SqlOpenOptions::default()
+        };
+        let conn = SqlConnection::open(path, &options).unwrap();
+        conn.conn
+            .lock()
+            .as_ref()
+            .unwrap()
+            .busy_timeout(Duration::ZERO)
+            .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_retry_busy_waits_for_writer() {
+        let path =
+            std::env::temp_dir().join(format!("lune-sql-retry-busy-{}.db", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let lua = Lua::new();
+
+        let setup = SqlConnection::open(&path, &SqlOpenOptions::default()).unwrap();
+        setup
+            .exec("PRAGMA journal_mode = WAL; CREATE TABLE items (n INTEGER);")
+            .unwrap();
+
+        // Without retries the contended write fails straight away
+        let writer = hold_write_lock(&path, Duration::from_millis(100));
+        let plain = open_without_busy_timeout(&path, 0);
+        let err = plain
+            .query(
+                &lua,
+                "INSERT INTO items (n) VALUES (1)",
+                &SqlParams::default(),
+                &SqlQueryOptions::default(),
+            )
+            .unwrap_err();
+        assert!(is_busy_error(&err));
+        writer.join().unwrap();
+
+        // With retries it succeeds once the other writer commits
+        let writer = hold_write_lock(&path, Duration::from_millis(100));
+        let retrying = open_without_busy_timeout(&path, 20);
+        let affected = retrying
+            .query(
+                &lua,
+                "INSERT INTO items (n) VALUES (2)",
+                &SqlParams::default(),
+                &SqlQueryOptions::default(),
+            )
+            .unwrap();
+        assert!(matches!(affected, LuaValue::Integer(1)));
+        writer.join().unwrap();
+
+        // Prepared statements retry like the connection they came from
+        let stmt = retrying
+            .prepare("INSERT INTO items (n) VALUES (?)")
+            .unwrap();
+        let writer = hold_write_lock(&path, Duration::from_millis(100));
+        let affected = stmt.execute(&lua, vec![LuaValue::Integer(3)]).unwrap();
+        assert!(matches!(affected, LuaValue::Integer(1)));
+        writer.join().unwrap();
+
+        // Other errors are not retried
+        let err = retrying
+            .query(
+                &lua,
+                "INSERT INTO missing (n) VALUES (3)",
+                &SqlParams::default(),
+                &SqlQueryOptions::default(),
+            )
+            .unwrap_err();
+        assert!(!is_busy_error(&err));
+
+        drop((setup, plain, retrying));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
     }
 }
@@ -0,0 +1,156 @@
+//! Date and time conversions for columns read with a date hint.
+//!
+//! There is no date storage class, timestamps are stored as ISO-8601 text
+//! or as unix epoch seconds by convention. Naive timestamps are taken to be
+//! UTC, matching the built-in date functions.
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike, Utc};
+use mlua::prelude::*;
+use rusqlite::types::ValueRef;
+
+const NAIVE_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// How a hinted column is handed back to Lua.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateHint {
+    /// A `{ year, month, day, hour, min, sec }` table, in UTC.
+    Table,
+    /// Seconds since the unix epoch.
+    Epoch,
+}
+
+impl DateHint {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "table" => Some(Self::Table),
+            "epoch" => Some(Self::Epoch),
+            _ => None,
+        }
+    }
+}
+
+/// The current time as an ISO-8601 UTC string, such as `2024-01-31T12:00:00Z`.
+pub fn now_iso() -> String {
+    Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Format `dt` as ISO-8601 UTC text, keeping fractional seconds only when present.
+pub fn to_iso(dt: &DateTime<Utc>) -> String {
+    dt.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string()
+}
+
+/// Read a date and time given to `sql.datetime`, either as unix epoch seconds
+/// or as a `{ year, month, day, hour, min, sec }` table in UTC.
+pub fn from_lua_value(value: &LuaValue) -> LuaResult<DateTime<Utc>> {
+    let parsed = match value {
+        LuaValue::Integer(i) => DateTime::from_timestamp(*i, 0),
+        LuaValue::Number(n) => from_epoch(*n),
+        LuaValue::Table(t) => from_table(t)?,
+        _ => {
+            return Err(LuaError::external(format!(
+                "sql.datetime expected unix seconds or a date table, got {}",
+                value.type_name()
+            )));
+        }
+    };
+    parsed.ok_or_else(|| LuaError::external("sql.datetime was given an invalid date/time"))
+}
+
+/// Missing time fields default to midnight, as with dates stored without a time.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn from_table(table: &LuaTable) -> LuaResult<Option<DateTime<Utc>>> {
+    let hour = table.get::<Option<u32>>("hour")?.unwrap_or(0);
+    let min = table.get::<Option<u32>>("min")?.unwrap_or(0);
+    let sec = table.get::<Option<f64>>("sec")?.unwrap_or(0.0);
+    if !(0.0..60.0).contains(&sec) {
+        return Ok(None);
+    }
+    let whole = sec.floor();
+    let nanos = ((sec - whole) * 1e9).round() as u32;
+    Ok(
+        NaiveDate::from_ymd_opt(table.get("year")?, table.get("month")?, table.get("day")?)
+            .and_then(|date| date.and_hms_nano_opt(hour, min, whole as u32, nanos.min(999_999_999)))
+            .map(|naive| naive.and_utc()),
+    )
+}
+
+/// Parse ISO-8601 text in any of the forms the built-in date functions accept.
+fn parse_text(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    let naive = text.strip_suffix('Z').unwrap_or(text);
+    NAIVE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(naive, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(naive, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .map(|naive| naive.and_utc())
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn from_epoch(secs: f64) -> Option<DateTime<Utc>> {
+    if !secs.is_finite() {
+        return None;
+    }
+    let whole = secs.floor();
+    let nanos = ((secs - whole) * 1e9).round() as u32;
+    DateTime::from_timestamp(whole as i64, nanos.min(999_999_999))
+}
+
+/// Convert a raw column value to the representation requested by `hint`.
+///
+/// Text is parsed as ISO-8601, while integers and reals are taken to be
+/// unix epoch seconds. `NULL` stays `nil`.
+pub fn read_column(
+    lua: &Lua,
+    value: ValueRef<'_>,
+    hint: DateHint,
+    column: &str,
+) -> LuaResult<LuaValue> {
+    #[allow(clippy::cast_precision_loss)]
+    let parsed = match value {
+        ValueRef::Null => return Ok(LuaValue::Nil),
+        ValueRef::Integer(i) => DateTime::from_timestamp(i, 0),
+        ValueRef::Real(r) => from_epoch(r),
+        ValueRef::Text(t) => std::str::from_utf8(t).ok().and_then(parse_text),
+        ValueRef::Blob(_) => None,
+    };
+    let Some(dt) = parsed else {
+        return Err(LuaError::external(format!(
+            "Column '{column}' does not hold a valid date/time"
+        )));
+    };
+
+    let nanos = dt.timestamp_subsec_nanos();
+    match hint {
+        DateHint::Epoch if nanos == 0 => Ok(LuaValue::Integer(dt.timestamp())),
+        #[allow(clippy::cast_precision_loss)]
+        DateHint::Epoch => Ok(LuaValue::Number(
+            dt.timestamp() as f64 + f64::from(nanos) / 1e9,
+        )),
+        DateHint::Table => {
+            let table = lua.create_table()?;
+            table.set("year", dt.year())?;
+            table.set("month", dt.month())?;
+            table.set("day", dt.day())?;
+            table.set("hour", dt.hour())?;
+            table.set("min", dt.minute())?;
+            if nanos == 0 {
+                table.set("sec", dt.second())?;
+            } else {
+                table.set("sec", f64::from(dt.second()) + f64::from(nanos) / 1e9)?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+    }
+}
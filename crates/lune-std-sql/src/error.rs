@@ -0,0 +1,121 @@
+//! Structured errors for failed database calls.
+//!
+//! These are raised to Lua as `{ message, code, name }` tables so that
+//! scripts can branch on the extended result code, for example to
+//! ignore a duplicate key with `sql.errorCodes.CONSTRAINT_UNIQUE`.
+
+use mlua::prelude::*;
+use rusqlite::ffi;
+
+/// Extended result codes exposed as `sql.errorCodes`, without their `SQLITE_` prefix.
+pub const ERROR_CODES: &[(&str, i32)] = &[
+    ("ERROR", ffi::SQLITE_ERROR),
+    ("BUSY", ffi::SQLITE_BUSY),
+    ("LOCKED", ffi::SQLITE_LOCKED),
+    ("READONLY", ffi::SQLITE_READONLY),
+    ("IOERR", ffi::SQLITE_IOERR),
+    ("CORRUPT", ffi::SQLITE_CORRUPT),
+    ("FULL", ffi::SQLITE_FULL),
+    ("CANTOPEN", ffi::SQLITE_CANTOPEN),
+    ("MISMATCH", ffi::SQLITE_MISMATCH),
+    ("NOTADB", ffi::SQLITE_NOTADB),
+    ("CONSTRAINT", ffi::SQLITE_CONSTRAINT),
+    ("CONSTRAINT_CHECK", ffi::SQLITE_CONSTRAINT_CHECK),
+    ("CONSTRAINT_FOREIGNKEY", ffi::SQLITE_CONSTRAINT_FOREIGNKEY),
+    ("CONSTRAINT_NOTNULL", ffi::SQLITE_CONSTRAINT_NOTNULL),
+    ("CONSTRAINT_PRIMARYKEY", ffi::SQLITE_CONSTRAINT_PRIMARYKEY),
+    ("CONSTRAINT_TRIGGER", ffi::SQLITE_CONSTRAINT_TRIGGER),
+    ("CONSTRAINT_UNIQUE", ffi::SQLITE_CONSTRAINT_UNIQUE),
+];
+
+/// A failed database call, with the extended result code it reported.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum DatabaseError {
+    #[error("{message}")]
+    Sqlite { message: String, code: i32 },
+}
+
+impl DatabaseError {
+    /// Find a database failure inside a (possibly wrapped) Lua error.
+    ///
+    /// Context added around the failure, such as which statement of a
+    /// script failed, is kept at the start of the message.
+    #[must_use]
+    pub fn from_lua_error(err: &LuaError) -> Option<Self> {
+        match err {
+            LuaError::CallbackError { cause, .. } => Self::from_lua_error(cause),
+            LuaError::WithContext { context, cause } => {
+                let Self::Sqlite { message, code } = Self::from_lua_error(cause)?;
+                Some(Self::Sqlite {
+                    message: format!("{context}: {message}"),
+                    code,
+                })
+            }
+            _ => {
+                let sqlite_err = err.downcast_ref::<rusqlite::Error>()?;
+                let code = match sqlite_err {
+                    rusqlite::Error::SqliteFailure(failure, _) => failure.extended_code,
+                    // Syntax errors and the like, which also point at the offending SQL
+                    rusqlite::Error::SqlInputError { error, .. } => error.extended_code,
+                    _ => return None,
+                };
+                Some(Self::Sqlite {
+                    message: sqlite_err.to_string(),
+                    code,
+                })
+            }
+        }
+    }
+
+    /// Name of the result code, such as `SQLITE_CONSTRAINT_UNIQUE`, if it is a known one.
+    #[must_use]
+    pub fn code_name(&self) -> Option<String> {
+        let Self::Sqlite { code, .. } = self;
+        ERROR_CODES
+            .iter()
+            .find(|(_, known)| known == code)
+            .map(|(name, _)| format!("SQLITE_{name}"))
+    }
+
+    /// Convert into the `{ message, code, name }` table raised to Lua.
+    ///
+    /// # Errors
+    ///
+    /// Errors when out of memory.
+    pub fn into_lua_table(self, lua: &Lua) -> LuaResult<LuaTable> {
+        let name = self.code_name();
+        let Self::Sqlite { message, code } = self;
+        let table = lua.create_table()?;
+        table.set("message", message.as_str())?;
+        table.set("code", code)?;
+        table.set("name", name)?;
+
+        let meta = lua.create_table()?;
+        meta.set(
+            "__tostring",
+            lua.create_function(move |_, _: LuaValue| Ok(message.clone()))?,
+        )?;
+        table.set_metatable(Some(meta))?;
+
+        Ok(table)
+    }
+}
+
+/// The `sql.errorCodes` table, mapping names to extended result codes.
+pub fn error_codes(lua: &Lua) -> LuaResult<LuaTable> {
+    let table = lua.create_table_with_capacity(0, ERROR_CODES.len())?;
+    for (name, code) in ERROR_CODES {
+        table.set(*name, *code)?;
+    }
+    table.set_readonly(true);
+    Ok(table)
+}
+
+/// Convert a database failure raised by a wrapped function into its table.
+///
+/// Passed to [`lune_utils::structured_errors::StructuredMethod`].
+pub fn structured_error(lua: &Lua, err: &LuaError) -> LuaResult<Option<LuaTable>> {
+    DatabaseError::from_lua_error(err)
+        .map(|db_err| db_err.into_lua_table(lua))
+        .transpose()
+}
@@ -0,0 +1,71 @@
+//! Lua functions registered as SQL scalar functions.
+
+use mlua::WeakLua;
+use mlua::prelude::*;
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::{Connection, types::Value as SqlValue};
+
+use crate::value::{lua_to_sql, value_ref_to_lua};
+
+/// Largest number of arguments a SQL function can take.
+const MAX_FUNCTION_ARGS: i32 = 127;
+
+/// A Lua function callable from SQL.
+///
+/// The function lives in the Lua registry, and only a weak handle to the Lua
+/// state is kept, so a connection that is never closed does not keep it alive.
+struct LuaScalarFunction {
+    lua: WeakLua,
+    key: LuaRegistryKey,
+}
+
+// SAFETY: SQLite requires the callback to be `Send`, but it is only ever invoked
+// while a statement on the connection is stepped, and connections holding Lua
+// functions are only used from the thread that owns the Lua state.
+unsafe impl Send for LuaScalarFunction {}
+
+impl LuaScalarFunction {
+    fn call(&self, ctx: &Context<'_>) -> LuaResult<SqlValue> {
+        let lua = self
+            .lua
+            .try_upgrade()
+            .ok_or_else(|| LuaError::external("Lua state was closed"))?;
+        let function: LuaFunction = lua.registry_value(&self.key)?;
+
+        let args = (0..ctx.len())
+            .map(|i| value_ref_to_lua(&lua, ctx.get_raw(i)))
+            .collect::<LuaResult<LuaMultiValue>>()?;
+        let result: LuaValue = function.call(args)?;
+        lua_to_sql(&lua, &result)
+    }
+}
+
+/// Register `function` on `conn` as the SQL function `name` taking `n_args` arguments,
+/// or any number of arguments when `n_args` is -1.
+///
+/// Arguments are converted like query results and the return value like a
+/// query parameter. Errors raised by the function fail the statement calling it.
+pub fn register(
+    lua: &Lua,
+    conn: &Connection,
+    name: &str,
+    n_args: i32,
+    function: LuaFunction,
+) -> LuaResult<()> {
+    if !(-1..=MAX_FUNCTION_ARGS).contains(&n_args) {
+        return Err(LuaError::external(format!(
+            "Function argument count must be between -1 and {MAX_FUNCTION_ARGS}, got {n_args}"
+        )));
+    }
+
+    let scalar = LuaScalarFunction {
+        lua: lua.weak(),
+        key: lua.create_registry_value(function)?,
+    };
+    conn.create_scalar_function(name, n_args, FunctionFlags::SQLITE_UTF8, move |ctx| {
+        scalar
+            .call(ctx)
+            .map_err(|err| rusqlite::Error::UserFunctionError(err.to_string().into()))
+    })
+    .into_lua_err()
+}
@@ -0,0 +1,51 @@
+//! Connection-level hooks invoked around query execution.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use mlua::prelude::*;
+
+/// Slow query hook registered through `conn:onSlowQuery`.
+pub struct SlowQueryHook {
+    threshold: Duration,
+    callback: LuaFunction,
+}
+
+impl SlowQueryHook {
+    pub fn new(threshold_ms: f64, callback: LuaFunction) -> LuaResult<Self> {
+        if !threshold_ms.is_finite() || threshold_ms < 0.0 {
+            return Err(LuaError::external(format!(
+                "Slow query threshold must be a non-negative number, got {threshold_ms}"
+            )));
+        }
+        Ok(Self {
+            threshold: Duration::from_secs_f64(threshold_ms / 1000.0),
+            callback,
+        })
+    }
+}
+
+/// Hooks shared between a connection and the statements prepared from it.
+#[derive(Clone, Default)]
+pub struct SqlHooks {
+    slow_query: Rc<RefCell<Option<SlowQueryHook>>>,
+}
+
+impl SqlHooks {
+    pub fn set_slow_query(&self, hook: Option<SlowQueryHook>) {
+        *self.slow_query.borrow_mut() = hook;
+    }
+
+    /// Invoke the slow query callback if `elapsed` exceeds its threshold.
+    ///
+    /// Must be called after the connection lock has been released,
+    /// since the callback is free to run queries of its own.
+    pub fn report_query(&self, sql: &str, elapsed: Duration) -> LuaResult<()> {
+        let callback = match &*self.slow_query.borrow() {
+            Some(hook) if elapsed >= hook.threshold => hook.callback.clone(),
+            _ => return Ok(()),
+        };
+        callback.call::<()>((sql, elapsed.as_secs_f64() * 1000.0))
+    }
+}
@@ -9,10 +9,21 @@ use lune_utils::TableBuilder;
 use mlua::prelude::*;
 
 mod connection;
+mod datetime;
+mod error;
+mod functions;
+mod hooks;
+mod options;
+mod registry;
+mod schema;
+mod snapshot;
 mod statement;
 mod value;
 
 pub use connection::SqlConnection;
+pub use error::DatabaseError;
+pub use options::{SqlOpenOptions, SqlQueryOptions};
+pub use snapshot::SqlSnapshot;
 
 const TYPEDEFS: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/types.d.luau"));
 
@@ -28,16 +39,49 @@ pub fn typedefs() -> String {
 ///
 /// Errors when out of memory.
 pub fn module(lua: Lua) -> LuaResult<LuaTable> {
+    let error_codes = error::error_codes(&lua)?;
     TableBuilder::new(lua)?
         .with_function("open", sql_open)?
+        .with_function("openWithFlags", sql_open)?
         .with_function("memory", sql_memory)?
+        .with_function("sharedMemory", sql_shared_memory)?
+        .with_function("registerType", sql_register_type)?
+        .with_function("int", sql_int)?
+        .with_function("real", sql_real)?
+        .with_function("datetime", sql_datetime)?
+        .with_function("now", sql_now)?
+        .with_value("errorCodes", error_codes)?
         .build_readonly()
 }
 
-fn sql_open(_: &Lua, path: String) -> LuaResult<SqlConnection> {
-    SqlConnection::open(&path)
+fn sql_open(_: &Lua, (path, options): (String, SqlOpenOptions)) -> LuaResult<SqlConnection> {
+    SqlConnection::open(&path, &options)
 }
 
 fn sql_memory(_: &Lua, (): ()) -> LuaResult<SqlConnection> {
     SqlConnection::memory()
 }
+
+fn sql_shared_memory(_: &Lua, (): ()) -> LuaResult<SqlConnection> {
+    SqlConnection::shared_memory()
+}
+
+fn sql_int(_: &Lua, value: LuaValue) -> LuaResult<value::SqlTyped> {
+    value::SqlTyped::int(&value)
+}
+
+fn sql_real(_: &Lua, value: LuaValue) -> LuaResult<value::SqlTyped> {
+    value::SqlTyped::real(&value)
+}
+
+fn sql_datetime(_: &Lua, value: LuaValue) -> LuaResult<value::SqlTyped> {
+    value::SqlTyped::datetime(&value)
+}
+
+fn sql_register_type(lua: &Lua, (type_name, adapter): (String, LuaTable)) -> LuaResult<()> {
+    registry::SqlTypeRegistry::register(lua, &type_name, &adapter)
+}
+
+fn sql_now(_: &Lua, (): ()) -> LuaResult<String> {
+    Ok(datetime::now_iso())
+}
@@ -0,0 +1,196 @@
+//! Options for opening `SQLite` connections and running queries.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use mlua::prelude::*;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::datetime::DateHint;
+
+/// Options accepted by `sql.open` and `sql.openWithFlags`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SqlOpenOptions {
+    /// Interpret the path as a `file:` URI. Detected from the path when unset.
+    pub uri: Option<bool>,
+    /// How many times to retry a statement that fails with `SQLITE_BUSY` or `SQLITE_LOCKED`.
+    pub retry_busy: u32,
+    /// Switch the journal mode to WAL, or back to DELETE when `false`.
+    pub wal: Option<bool>,
+    /// How long to wait for a lock before failing with `SQLITE_BUSY`.
+    pub busy_timeout_ms: Option<u32>,
+    /// Enforce foreign key constraints, which are off by default.
+    pub foreign_keys: Option<bool>,
+    /// Open the database without write access.
+    pub read_only: bool,
+    /// Create the database file when it does not exist. Defaults to `true`.
+    pub create: Option<bool>,
+    /// Skip the mutex the database engine keeps for each handle. Defaults to `true`,
+    /// since each handle is already guarded by a lock on the Rust side.
+    pub no_mutex: Option<bool>,
+    /// Open the database in shared-cache mode.
+    pub shared_cache: bool,
+}
+
+impl SqlOpenOptions {
+    /// Whether the given path should be opened as a URI filename.
+    #[must_use]
+    pub fn is_uri(&self, path: &str) -> bool {
+        self.uri.unwrap_or_else(|| path.starts_with("file:"))
+    }
+
+    /// Whether the given path names an in-memory database rather than a file.
+    #[must_use]
+    pub fn is_memory(&self, path: &str) -> bool {
+        path.is_empty()
+            || path == ":memory:"
+            || (self.is_uri(path)
+                && (path.contains("mode=memory") || path.starts_with("file::memory:")))
+    }
+
+    /// `SQLite` open flags for the given path.
+    ///
+    /// Read-only handles never create the file, whatever `create` says.
+    #[must_use]
+    pub fn flags(&self, path: &str) -> OpenFlags {
+        let mut flags = if self.read_only {
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+        } else if self.create.unwrap_or(true) {
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+        } else {
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+        };
+        flags |= if self.no_mutex.unwrap_or(true) {
+            OpenFlags::SQLITE_OPEN_NO_MUTEX
+        } else {
+            OpenFlags::SQLITE_OPEN_FULL_MUTEX
+        };
+        if self.shared_cache {
+            flags |= OpenFlags::SQLITE_OPEN_SHARED_CACHE;
+        }
+        if self.is_uri(path) {
+            flags |= OpenFlags::SQLITE_OPEN_URI;
+        }
+        flags
+    }
+
+    /// Apply the pragmas requested by the options to a newly opened handle.
+    ///
+    /// # Errors
+    ///
+    /// Errors if a pragma fails, or if the journal mode could not be changed,
+    /// such as when switching a read-only database to WAL.
+    pub fn configure(&self, conn: &Connection) -> LuaResult<()> {
+        // Set first, so that changing the journal mode below waits on other connections
+        if let Some(ms) = self.busy_timeout_ms {
+            conn.busy_timeout(Duration::from_millis(u64::from(ms)))
+                .into_lua_err()?;
+        }
+        if let Some(foreign_keys) = self.foreign_keys {
+            conn.pragma_update(None, "foreign_keys", foreign_keys)
+                .into_lua_err()?;
+        }
+        if let Some(wal) = self.wal {
+            let wanted = if wal { "wal" } else { "delete" };
+            // The pragma reports the resulting mode, and does not fail when it is left unchanged
+            let mode: String = conn
+                .pragma_update_and_check(None, "journal_mode", wanted, |row| row.get(0))
+                .into_lua_err()?;
+            if !mode.eq_ignore_ascii_case(wanted) {
+                return Err(LuaError::external(format!(
+                    "Could not set the journal mode to {wanted}, the database is in {mode} mode"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromLua for SqlOpenOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Nil = value {
+            Ok(Self::default())
+        } else if let LuaValue::Table(tab) = value {
+            let mut this = Self::default();
+
+            if let Some(uri) = tab.get::<Option<bool>>("uri")? {
+                this.uri = Some(uri);
+            }
+
+            if let Some(retry_busy) = tab.get::<Option<u32>>("retryBusy")? {
+                this.retry_busy = retry_busy;
+            }
+
+            this.wal = tab.get::<Option<bool>>("wal")?;
+            this.busy_timeout_ms = tab.get::<Option<u32>>("busyTimeoutMs")?;
+            this.foreign_keys = tab.get::<Option<bool>>("foreignKeys")?;
+            this.read_only = tab.get::<Option<bool>>("readOnly")?.unwrap_or_default();
+            this.create = tab.get::<Option<bool>>("create")?;
+            this.no_mutex = tab.get::<Option<bool>>("noMutex")?;
+            this.shared_cache = tab.get::<Option<bool>>("sharedCache")?.unwrap_or_default();
+
+            Ok(this)
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("SqlOpenOptions"),
+                message: None,
+            })
+        }
+    }
+}
+
+/// Options accepted by `query`, `queryOne`, `queryWithMeta` and `executeReturning`.
+#[derive(Debug, Default, Clone)]
+pub struct SqlQueryOptions {
+    /// Columns to read back as dates, and in which form.
+    pub dates: HashMap<String, DateHint>,
+    /// Return `{ affected, lastRowId }` instead of a count for statements without rows.
+    pub with_row_id: bool,
+    /// Return BLOB columns as buffers rather than strings.
+    pub blobs_as_buffer: bool,
+    /// Error from `queryOne` when the query yields more than one row.
+    pub strict: bool,
+}
+
+impl FromLua for SqlQueryOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let LuaValue::Nil = value {
+            Ok(Self::default())
+        } else if let LuaValue::Table(tab) = value {
+            let mut this = Self::default();
+
+            if let Some(dates) = tab.get::<Option<LuaTable>>("dates")? {
+                for pair in dates.pairs::<String, String>() {
+                    let (column, hint) = pair?;
+                    let Some(hint) = DateHint::from_str(&hint) else {
+                        return Err(LuaError::runtime(format!(
+                            "Invalid date hint '{hint}' for column '{column}', expected 'table' or 'epoch'"
+                        )));
+                    };
+                    this.dates.insert(column, hint);
+                }
+            }
+
+            if let Some(with_row_id) = tab.get::<Option<bool>>("withRowId")? {
+                this.with_row_id = with_row_id;
+            }
+
+            if let Some(blobs_as_buffer) = tab.get::<Option<bool>>("blobsAsBuffer")? {
+                this.blobs_as_buffer = blobs_as_buffer;
+            }
+
+            if let Some(strict) = tab.get::<Option<bool>>("strict")? {
+                this.strict = strict;
+            }
+
+            Ok(this)
+        } else {
+            Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("SqlQueryOptions"),
+                message: None,
+            })
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Registry of Lua type adapters used for custom SQL value marshalling.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use mlua::{AppDataRef, prelude::*};
+use parking_lot::Mutex;
+
+/// Prefix marking a text value produced by a registered type adapter.
+///
+/// Values are stored as `\x01<type name>\x01<payload>`.
+const TAG_MARKER: char = '\u{1}';
+
+struct SqlTypeAdapter {
+    to_sql: LuaRegistryKey,
+    from_sql: LuaRegistryKey,
+}
+
+#[derive(Clone, Default)]
+pub struct SqlTypeRegistry {
+    adapters: Arc<Mutex<HashMap<String, SqlTypeAdapter>>>,
+}
+
+impl SqlTypeRegistry {
+    fn get_or_create(lua: &Lua) -> AppDataRef<'_, Self> {
+        if lua.app_data_ref::<Self>().is_none() {
+            lua.set_app_data(Self::default());
+        }
+        lua.app_data_ref::<Self>()
+            .expect("Missing SqlTypeRegistry in app data")
+    }
+
+    /// Register an adapter for values whose metatable has the given `__type` or `__name`.
+    pub fn register(lua: &Lua, type_name: &str, adapter: &LuaTable) -> LuaResult<()> {
+        if type_name.is_empty() || type_name.contains(TAG_MARKER) {
+            return Err(LuaError::external(format!(
+                "Invalid SQL type name '{type_name}'"
+            )));
+        }
+
+        let to_sql: LuaFunction = adapter
+            .get("toSql")
+            .map_err(|_| LuaError::external("SQL type adapter is missing a 'toSql' function"))?;
+        let from_sql: LuaFunction = adapter
+            .get("fromSql")
+            .map_err(|_| LuaError::external("SQL type adapter is missing a 'fromSql' function"))?;
+
+        let adapter = SqlTypeAdapter {
+            to_sql: lua.create_registry_value(to_sql)?,
+            from_sql: lua.create_registry_value(from_sql)?,
+        };
+
+        let registry = Self::get_or_create(lua);
+        registry
+            .adapters
+            .lock()
+            .insert(type_name.to_owned(), adapter);
+        Ok(())
+    }
+
+    /// Serialize a table or userdata using its registered adapter, if any.
+    pub fn to_sql(lua: &Lua, value: &LuaValue) -> LuaResult<Option<String>> {
+        let Some(type_name) = metatable_type_name(value)? else {
+            return Ok(None);
+        };
+
+        let to_sql = {
+            let Some(registry) = lua.app_data_ref::<Self>() else {
+                return Ok(None);
+            };
+            let adapters = registry.adapters.lock();
+            let Some(adapter) = adapters.get(&type_name) else {
+                return Ok(None);
+            };
+            lua.registry_value::<LuaFunction>(&adapter.to_sql)?
+        };
+
+        let payload: LuaString = to_sql
+            .call(value.clone())
+            .map_err(|e| LuaError::external(format!("toSql for type '{type_name}' failed: {e}")))?;
+        Ok(Some(format!(
+            "{TAG_MARKER}{type_name}{TAG_MARKER}{}",
+            payload.to_str()?
+        )))
+    }
+
+    /// Reconstruct a value from tagged text, if it was produced by a registered adapter.
+    pub fn from_sql(lua: &Lua, text: &str) -> LuaResult<Option<LuaValue>> {
+        let Some(rest) = text.strip_prefix(TAG_MARKER) else {
+            return Ok(None);
+        };
+        let Some((type_name, payload)) = rest.split_once(TAG_MARKER) else {
+            return Ok(None);
+        };
+
+        let from_sql = {
+            let Some(registry) = lua.app_data_ref::<Self>() else {
+                return Ok(None);
+            };
+            let adapters = registry.adapters.lock();
+            let Some(adapter) = adapters.get(type_name) else {
+                return Ok(None);
+            };
+            lua.registry_value::<LuaFunction>(&adapter.from_sql)?
+        };
+
+        from_sql
+            .call(payload)
+            .map(Some)
+            .map_err(|e| LuaError::external(format!("fromSql for type '{type_name}' failed: {e}")))
+    }
+}
+
+/// Name used to match a value against registered adapters, from `__type` or `__name`.
+fn metatable_type_name(value: &LuaValue) -> LuaResult<Option<String>> {
+    let (type_name, name) = match value {
+        LuaValue::Table(t) => match t.metatable() {
+            Some(mt) => (mt.raw_get("__type")?, mt.raw_get("__name")?),
+            None => return Ok(None),
+        },
+        LuaValue::UserData(ud) => match ud.metatable() {
+            Ok(mt) => (mt.get("__type")?, mt.get("__name")?),
+            Err(_) => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let type_name: Option<String> = type_name;
+    let name: Option<String> = name;
+    Ok(type_name.or(name))
+}
@@ -0,0 +1,97 @@
+//! Schema introspection through `sqlite_master` and the table-valued pragmas.
+
+use mlua::prelude::*;
+use rusqlite::{Connection, types::Value as SqlValue};
+
+/// Names of all user tables, sorted alphabetically.
+pub fn tables(lua: &Lua, conn: &Connection) -> LuaResult<LuaTable> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT name FROM sqlite_master \
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )
+        .into_lua_err()?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .into_lua_err()?
+        .collect::<Result<Vec<_>, _>>()
+        .into_lua_err()?;
+    lua.create_sequence_from(names)
+}
+
+/// Column descriptors for `table`, in declaration order.
+pub fn columns(lua: &Lua, conn: &Connection, table: &str) -> LuaResult<LuaTable> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT name, type, \"notnull\", dflt_value, pk \
+             FROM pragma_table_info(?1) ORDER BY cid",
+        )
+        .into_lua_err()?;
+    let mut rows = stmt.query([table]).into_lua_err()?;
+
+    let result = lua.create_table()?;
+    while let Some(row) = rows.next().into_lua_err()? {
+        let column = lua.create_table()?;
+        column.set("name", row.get::<_, String>(0).into_lua_err()?)?;
+        column.set("type", row.get::<_, String>(1).into_lua_err()?)?;
+        column.set("notNull", row.get::<_, bool>(2).into_lua_err()?)?;
+        column.set("primaryKey", row.get::<_, i64>(4).into_lua_err()? > 0)?;
+        // Defaults are reported as the SQL expression text, not an evaluated value
+        if let SqlValue::Text(default) = row.get::<_, SqlValue>(3).into_lua_err()? {
+            column.set("default", default)?;
+        }
+        result.push(column)?;
+    }
+
+    if result.raw_len() == 0 {
+        return Err(no_such_table(table));
+    }
+    Ok(result)
+}
+
+/// Index descriptors for `table`, including the columns each index covers.
+pub fn indexes(lua: &Lua, conn: &Connection, table: &str) -> LuaResult<LuaTable> {
+    if !table_exists(conn, table)? {
+        return Err(no_such_table(table));
+    }
+
+    let mut list = conn
+        .prepare("SELECT name, \"unique\", origin FROM pragma_index_list(?1) ORDER BY name")
+        .into_lua_err()?;
+    let mut info = conn
+        .prepare("SELECT name FROM pragma_index_info(?1) ORDER BY seqno")
+        .into_lua_err()?;
+
+    let mut rows = list.query([table]).into_lua_err()?;
+    let result = lua.create_table()?;
+    while let Some(row) = rows.next().into_lua_err()? {
+        let name: String = row.get(0).into_lua_err()?;
+        let columns = info
+            .query_map([&name], |row| row.get::<_, Option<String>>(0))
+            .into_lua_err()?
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>, _>>()
+            .into_lua_err()?;
+
+        let index = lua.create_table()?;
+        index.set("name", name)?;
+        index.set("unique", row.get::<_, bool>(1).into_lua_err()?)?;
+        index.set("origin", row.get::<_, String>(2).into_lua_err()?)?;
+        index.set("columns", lua.create_sequence_from(columns)?)?;
+        result.push(index)?;
+    }
+    Ok(result)
+}
+
+fn table_exists(conn: &Connection, table: &str) -> LuaResult<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type IN ('table', 'view') AND name = ?1)",
+        [table],
+        |row| row.get(0),
+    )
+    .into_lua_err()
+}
+
+fn no_such_table(table: &str) -> LuaError {
+    LuaError::external(format!("No such table '{table}'"))
+}
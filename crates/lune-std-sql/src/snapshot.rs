@@ -0,0 +1,179 @@
+//! Read-only snapshots for iterating over a query while other connections write.
+
+use mlua::prelude::*;
+use rusqlite::{Connection, params_from_iter, types::Value as SqlValue};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::JoinHandle;
+
+use crate::value::value_ref_to_lua;
+
+/// Rows of a query read inside a deferred read transaction.
+///
+/// The transaction runs on its own handle, on a background thread that reads
+/// one row ahead of the iterator, so rows are only fetched as they are asked
+/// for. In WAL mode the snapshot stays consistent without blocking writers.
+pub struct SqlSnapshot {
+    columns: Vec<String>,
+    reader: Option<(RowReceiver, JoinHandle<()>)>,
+}
+
+type RowReceiver = Receiver<rusqlite::Result<Vec<SqlValue>>>;
+
+impl SqlSnapshot {
+    /// Begin a read transaction on `conn` and start reading rows of `sql`.
+    ///
+    /// Returns once the first row has been stepped, which is when the
+    /// database pins the snapshot, so later writes are never seen by the iterator.
+    pub(crate) fn start(conn: Connection, sql: String, params: Vec<SqlValue>) -> LuaResult<Self> {
+        let (header_tx, header_rx) = sync_channel(1);
+        let (row_tx, row_rx) = sync_channel(0);
+
+        let handle = std::thread::spawn(move || {
+            let mut header_tx = Some(header_tx);
+            let result = read_rows(&conn, &sql, &params, &mut header_tx, &row_tx);
+            // Ends the read transaction once the rows are exhausted or the reader is gone
+            if !conn.is_autocommit() {
+                let _ = conn.execute_batch("ROLLBACK");
+            }
+            if let Err(err) = result {
+                match header_tx {
+                    Some(tx) => drop(tx.send(Err(err))),
+                    None => drop(row_tx.send(Err(err))),
+                }
+            }
+        });
+
+        let columns = header_rx
+            .recv()
+            .map_err(|_| LuaError::external("Snapshot reader stopped unexpectedly"))?
+            .into_lua_err()?;
+
+        Ok(Self {
+            columns,
+            reader: Some((row_rx, handle)),
+        })
+    }
+
+    /// Read the next row, or `None` once the snapshot is exhausted or closed.
+    ///
+    /// # Errors
+    ///
+    /// Errors if reading the row fails, which also closes the snapshot.
+    pub fn next(&mut self, lua: &Lua) -> LuaResult<Option<LuaTable>> {
+        let Some((rows, _)) = &self.reader else {
+            return Ok(None);
+        };
+        let values = match rows.recv() {
+            Ok(Ok(values)) => values,
+            Ok(Err(err)) => {
+                self.close();
+                return Err(err).into_lua_err();
+            }
+            Err(_) => {
+                self.close();
+                return Ok(None);
+            }
+        };
+
+        let row = lua.create_table()?;
+        for (name, value) in self.columns.iter().zip(&values) {
+            row.set(name.as_str(), value_ref_to_lua(lua, value.into())?)?;
+        }
+        Ok(Some(row))
+    }
+
+    /// Stop reading and end the read transaction. Closing twice does nothing.
+    pub fn close(&mut self) {
+        if let Some((rows, handle)) = self.reader.take() {
+            // Dropping the receiver wakes the reader, which then rolls back
+            drop(rows);
+            let _ = handle.join();
+        }
+    }
+
+    /// Whether the snapshot has been exhausted or closed.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.reader.is_none()
+    }
+}
+
+fn read_rows(
+    conn: &Connection,
+    sql: &str,
+    params: &[SqlValue],
+    header_tx: &mut Option<SyncSender<rusqlite::Result<Vec<String>>>>,
+    row_tx: &SyncSender<rusqlite::Result<Vec<SqlValue>>>,
+) -> rusqlite::Result<()> {
+    conn.execute_batch("BEGIN DEFERRED")?;
+    let mut stmt = conn.prepare(sql)?;
+    if !stmt.readonly() {
+        // Displayed as "Query is not read-only"
+        return Err(rusqlite::Error::InvalidQuery);
+    }
+    let columns: Vec<String> = stmt
+        .column_names()
+        .iter()
+        .map(|s| (*s).to_owned())
+        .collect();
+    let column_count = columns.len();
+
+    let mut rows = stmt.query(params_from_iter(params))?;
+    let mut next = read_row(rows.next()?, column_count)?;
+
+    if let Some(tx) = header_tx.take()
+        && tx.send(Ok(columns)).is_err()
+    {
+        return Ok(());
+    }
+    while let Some(values) = next {
+        if row_tx.send(Ok(values)).is_err() {
+            // The snapshot was closed before being exhausted
+            return Ok(());
+        }
+        next = read_row(rows.next()?, column_count)?;
+    }
+    Ok(())
+}
+
+fn read_row(
+    row: Option<&rusqlite::Row<'_>>,
+    count: usize,
+) -> rusqlite::Result<Option<Vec<SqlValue>>> {
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let values = (0..count)
+        .map(|i| row.get::<_, SqlValue>(i))
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(Some(values))
+}
+
+impl Drop for SqlSnapshot {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl LuaUserData for SqlSnapshot {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("isClosed", |_, this| Ok(this.is_closed()));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // next() -> {[string]: any}? - nil once exhausted, which ends the transaction
+        methods.add_method_mut("next", |lua, this, ()| this.next(lua));
+
+        // close() - Ends the read transaction early
+        methods.add_method_mut("close", |_, this, ()| {
+            this.close();
+            Ok(())
+        });
+
+        // for row in snapshot do ... end
+        methods.add_meta_function(LuaMetaMethod::Iter, |_, ud: LuaAnyUserData| {
+            let next: LuaFunction = ud.get("next")?;
+            Ok((next, ud))
+        });
+    }
+}
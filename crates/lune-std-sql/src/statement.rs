@@ -1,35 +1,70 @@
 //! Prepared statement wrapper.
 
+use lune_utils::structured_errors::StructuredMethod;
 use mlua::prelude::*;
-use parking_lot::Mutex;
-use rusqlite::Connection;
+use rusqlite::StatementStatus;
 use std::sync::Arc;
+use std::time::Instant;
 
+use crate::connection::{SharedConnection, lock_connection, with_busy_retry};
+use crate::error::structured_error;
+use crate::hooks::SqlHooks;
 use crate::value::lua_to_sql;
 
 /// Prepared SQL statement for repeated execution.
+///
+/// The compiled statement lives in the connection's statement cache,
+/// and column names are resolved once when the statement is created.
 pub struct SqlStatement {
-    conn: Arc<Mutex<Connection>>,
+    conn: SharedConnection,
     sql: String,
+    columns: Arc<[String]>,
+    hooks: SqlHooks,
+    /// `retryBusy` of the connection the statement was prepared on
+    retry_busy: u32,
 }
 
 impl SqlStatement {
-    pub fn new(conn: Arc<Mutex<Connection>>, sql: String) -> LuaResult<Self> {
-        // Validate SQL by preparing it
-        {
-            let c = conn.lock();
-            c.prepare(&sql).into_lua_err()?;
-        }
-        Ok(Self { conn, sql })
+    pub fn new(
+        conn: SharedConnection,
+        sql: String,
+        hooks: SqlHooks,
+        retry_busy: u32,
+    ) -> LuaResult<Self> {
+        // Validate SQL by preparing it, and grab column names while we're at it
+        let columns = {
+            let c = lock_connection(&conn)?;
+            let stmt = c.prepare_cached(&sql).into_lua_err()?;
+            stmt.column_names()
+                .iter()
+                .map(|s| (*s).to_owned())
+                .collect()
+        };
+        Ok(Self {
+            conn,
+            sql,
+            columns,
+            hooks,
+            retry_busy,
+        })
     }
 
+    /// Execute the statement, retrying while the database is busy like the
+    /// connection it was prepared on.
     pub fn execute(&self, lua: &Lua, params: Vec<LuaValue>) -> LuaResult<LuaValue> {
-        let conn = self.conn.lock();
-        let mut stmt = conn.prepare(&self.sql).into_lua_err()?;
+        let start = Instant::now();
+        let result = with_busy_retry(self.retry_busy, || self.execute_inner(lua, &params))?;
+        self.hooks.report_query(&self.sql, start.elapsed())?;
+        Ok(result)
+    }
+
+    fn execute_inner(&self, lua: &Lua, params: &[LuaValue]) -> LuaResult<LuaValue> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn.prepare_cached(&self.sql).into_lua_err()?;
 
         let param_values: Vec<_> = params
-            .into_iter()
-            .map(|v| lua_to_sql(&v))
+            .iter()
+            .map(|v| lua_to_sql(lua, v))
             .collect::<LuaResult<_>>()?;
 
         let param_refs: Vec<&dyn rusqlite::ToSql> = param_values
@@ -37,21 +72,18 @@ impl SqlStatement {
             .map(|v| v as &dyn rusqlite::ToSql)
             .collect();
 
-        if self.sql.trim().to_uppercase().starts_with("SELECT") {
-            let column_names: Vec<String> = stmt
-                .column_names()
-                .iter()
-                .map(|s| (*s).to_owned())
-                .collect();
-
+        if self.columns.is_empty() {
+            let affected = stmt.execute(param_refs.as_slice()).into_lua_err()?;
+            Ok(LuaValue::Integer(affected as i64))
+        } else {
             let mut rows = stmt.query(param_refs.as_slice()).into_lua_err()?;
             let result = lua.create_table()?;
             let mut idx = 1;
 
             while let Some(row) = rows.next().into_lua_err()? {
                 let row_table = lua.create_table()?;
-                for (i, name) in column_names.iter().enumerate() {
-                    let value = crate::value::sql_to_lua(lua, row, i)?;
+                for (i, name) in self.columns.iter().enumerate() {
+                    let value = crate::value::sql_to_lua(lua, row, i, false)?;
                     row_table.set(name.as_str(), value)?;
                 }
                 result.set(idx, row_table)?;
@@ -59,22 +91,117 @@ impl SqlStatement {
             }
 
             Ok(LuaValue::Table(result))
-        } else {
-            let affected = stmt.execute(param_refs.as_slice()).into_lua_err()?;
-            Ok(LuaValue::Integer(affected as i64))
         }
     }
+
+    /// Execute the statement, calling `callback` with each row as it is read.
+    ///
+    /// Rows are never collected into a single table, and iteration
+    /// stops early if the callback returns `false`.
+    pub fn for_each(
+        &self,
+        lua: &Lua,
+        params: Vec<LuaValue>,
+        callback: &LuaFunction,
+    ) -> LuaResult<()> {
+        let start = Instant::now();
+        self.for_each_inner(lua, params, callback)?;
+        self.hooks.report_query(&self.sql, start.elapsed())?;
+        Ok(())
+    }
+
+    fn for_each_inner(
+        &self,
+        lua: &Lua,
+        params: Vec<LuaValue>,
+        callback: &LuaFunction,
+    ) -> LuaResult<()> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn.prepare_cached(&self.sql).into_lua_err()?;
+
+        let param_values: Vec<_> = params
+            .into_iter()
+            .map(|v| lua_to_sql(lua, &v))
+            .collect::<LuaResult<_>>()?;
+
+        let param_refs: Vec<&dyn rusqlite::ToSql> = param_values
+            .iter()
+            .map(|v| v as &dyn rusqlite::ToSql)
+            .collect();
+
+        let mut rows = stmt.query(param_refs.as_slice()).into_lua_err()?;
+        while let Some(row) = rows.next().into_lua_err()? {
+            let row_table = lua.create_table()?;
+            for (i, name) in self.columns.iter().enumerate() {
+                let value = crate::value::sql_to_lua(lua, row, i, false)?;
+                row_table.set(name.as_str(), value)?;
+            }
+            let keep_going: LuaValue = callback.call(row_table)?;
+            if let LuaValue::Boolean(false) = keep_going {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How many times the compiled statement in the connection's cache has run.
+    ///
+    /// Every statement prepared from the same SQL shares this count, while the
+    /// compiled statement stays in the cache.
+    pub fn run_count(&self) -> LuaResult<i32> {
+        let conn = lock_connection(&self.conn)?;
+        let stmt = conn.prepare_cached(&self.sql).into_lua_err()?;
+        Ok(stmt.get_status(StatementStatus::Run))
+    }
+
+    /// Clear any parameter bindings left on the cached statement.
+    pub fn reset(&self) -> LuaResult<()> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn.prepare_cached(&self.sql).into_lua_err()?;
+        stmt.clear_bindings();
+        Ok(())
+    }
 }
 
 impl LuaUserData for SqlStatement {
-    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("sql", |_, this| Ok(this.sql.clone()));
+        fields.add_field_method_get("columns", |_, this| Ok(this.columns.to_vec()));
+        fields.add_field_method_get("runCount", |_, this| this.run_count());
+
         // execute(params: {any}?) -> {rows} | number
-        methods.add_method("execute", |lua, this, params: Option<LuaTable>| {
-            let params: Vec<LuaValue> = params
-                .map(|t| t.sequence_values().collect::<LuaResult<_>>())
-                .transpose()?
-                .unwrap_or_default();
-            this.execute(lua, params)
-        });
+        // Raises SQLite failures as { message, code, name } tables
+        fields.add_field(
+            "execute",
+            StructuredMethod::new(
+                "SqlStatement.execute",
+                structured_error,
+                |lua, this: &Self, params: Option<LuaTable>| {
+                    let params: Vec<LuaValue> = params
+                        .map(|t| t.sequence_values().collect::<LuaResult<_>>())
+                        .transpose()?
+                        .unwrap_or_default();
+                    this.execute(lua, params)
+                },
+            ),
+        );
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // forEach(params: {any}?, fn: (row) -> boolean?) -> ()
+        methods.add_method(
+            "forEach",
+            |lua, this, (params, callback): (Option<LuaTable>, LuaFunction)| {
+                let params: Vec<LuaValue> = params
+                    .map(|t| t.sequence_values().collect::<LuaResult<_>>())
+                    .transpose()?
+                    .unwrap_or_default();
+                this.for_each(lua, params, &callback)
+            },
+        );
+
+        // reset() - Clear bindings so the statement can be reused
+        methods.add_method("reset", |_, this, ()| this.reset());
     }
 }
@@ -1,16 +1,37 @@
 //! Value conversion between Lua and SQL types.
 
+use chrono::{DateTime, Utc};
 use mlua::prelude::*;
-use rusqlite::{Row, types::Value as SqlValue};
+use rusqlite::{
+    Row, Statement,
+    types::{Value as SqlValue, ValueRef},
+};
+
+use crate::datetime;
+use crate::registry::SqlTypeRegistry;
 
 /// Convert Lua value to SQL value.
-pub fn lua_to_sql(value: &LuaValue) -> LuaResult<SqlValue> {
+///
+/// Built-in conversions take priority, tables and userdata
+/// fall back to adapters registered through `sql.registerType`.
+pub fn lua_to_sql(lua: &Lua, value: &LuaValue) -> LuaResult<SqlValue> {
     match value {
         LuaValue::Nil => Ok(SqlValue::Null),
         LuaValue::Boolean(b) => Ok(SqlValue::Integer(i64::from(*b))),
         LuaValue::Integer(i) => Ok(SqlValue::Integer(*i)),
-        LuaValue::Number(n) => Ok(SqlValue::Real(*n)),
+        LuaValue::Number(n) => {
+            Ok(integral_to_i64(*n).map_or(SqlValue::Real(*n), SqlValue::Integer))
+        }
         LuaValue::String(s) => Ok(SqlValue::Text(s.to_str()?.to_owned())),
+        LuaValue::Buffer(b) => Ok(SqlValue::Blob(b.to_vec())),
+        LuaValue::UserData(ud) if ud.is::<SqlTyped>() => Ok(ud.borrow::<SqlTyped>()?.to_sql()),
+        LuaValue::Table(_) | LuaValue::UserData(_) => match SqlTypeRegistry::to_sql(lua, value)? {
+            Some(tagged) => Ok(SqlValue::Text(tagged)),
+            None => Err(LuaError::external(format!(
+                "Cannot convert {:?} to SQL value, no SQL type is registered for it",
+                value.type_name()
+            ))),
+        },
         _ => Err(LuaError::external(format!(
             "Cannot convert {:?} to SQL value",
             value.type_name()
@@ -18,20 +39,215 @@ pub fn lua_to_sql(value: &LuaValue) -> LuaResult<SqlValue> {
     }
 }
 
-/// Convert SQL value from row to Lua value.
-pub fn sql_to_lua(lua: &Lua, row: &Row, idx: usize) -> LuaResult<LuaValue> {
-    use rusqlite::types::ValueRef;
+/// Parameters for a statement, given either as an array bound by position,
+/// or as a table with string keys bound to `:name`, `@name` or `$name`.
+#[derive(Debug, Clone)]
+pub enum SqlParams {
+    Positional(Vec<LuaValue>),
+    Named(Vec<(String, LuaValue)>),
+}
+
+impl Default for SqlParams {
+    fn default() -> Self {
+        Self::Positional(Vec::new())
+    }
+}
+
+impl SqlParams {
+    /// Convert the parameters into values for every placeholder of `stmt`, in order.
+    ///
+    /// Placeholders missing from named parameters are bound to NULL.
+    ///
+    /// # Errors
+    ///
+    /// Errors if a value cannot be converted, or if a name matches no placeholder.
+    pub fn to_sql(&self, lua: &Lua, stmt: &Statement<'_>) -> LuaResult<Vec<SqlValue>> {
+        match self {
+            Self::Positional(values) => values.iter().map(|v| lua_to_sql(lua, v)).collect(),
+            Self::Named(values) => {
+                let mut bound = vec![SqlValue::Null; stmt.parameter_count()];
+                for (name, value) in values {
+                    let index = named_parameter_index(stmt, name)?.ok_or_else(|| {
+                        LuaError::external(format!("No parameter named '{name}' in the query"))
+                    })?;
+                    bound[index - 1] = lua_to_sql(lua, value)?;
+                }
+                Ok(bound)
+            }
+        }
+    }
+}
+
+/// Find the placeholder for `name`, which may be written with or without its prefix.
+fn named_parameter_index(stmt: &Statement<'_>, name: &str) -> LuaResult<Option<usize>> {
+    if name.starts_with([':', '@', '$']) {
+        return stmt.parameter_index(name).into_lua_err();
+    }
+    for prefix in [':', '@', '$'] {
+        if let Some(index) = stmt
+            .parameter_index(&format!("{prefix}{name}"))
+            .into_lua_err()?
+        {
+            return Ok(Some(index));
+        }
+    }
+    Ok(None)
+}
+
+impl FromLua for SqlParams {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let tab = match value {
+            LuaValue::Nil => return Ok(Self::default()),
+            LuaValue::Table(tab) => tab,
+            _ => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: String::from("SqlParams"),
+                    message: None,
+                });
+            }
+        };
+
+        let mut named = Vec::new();
+        let mut has_positional = false;
+        for pair in tab.pairs::<LuaValue, LuaValue>() {
+            match pair? {
+                (LuaValue::String(key), value) => named.push((key.to_str()?.to_owned(), value)),
+                (LuaValue::Integer(_) | LuaValue::Number(_), _) => has_positional = true,
+                (key, _) => {
+                    return Err(LuaError::external(format!(
+                        "Parameter keys must be strings or array indices, got {}",
+                        key.type_name()
+                    )));
+                }
+            }
+        }
+
+        match (has_positional, named.is_empty()) {
+            (true, false) => Err(LuaError::external(
+                "Cannot mix positional and named parameters in one call",
+            )),
+            (false, false) => Ok(Self::Named(named)),
+            _ => Ok(Self::Positional(
+                tab.sequence_values().collect::<LuaResult<_>>()?,
+            )),
+        }
+    }
+}
 
-    let value_ref = row.get_ref(idx).into_lua_err()?;
+/// Convert a whole-valued number within the `i64` range to an integer.
+///
+/// Luau numbers are doubles, so integers above 2^53 may already have been
+/// rounded to a neighbouring whole number before they reach this point.
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn integral_to_i64(n: f64) -> Option<i64> {
+    // i64::MAX rounds up to 2^63 as a double, which is itself out of range
+    (n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+}
 
+/// Convert SQL value from row to Lua value.
+///
+/// Blobs are returned as strings, or as buffers when `blobs_as_buffer` is set.
+pub fn sql_to_lua(lua: &Lua, row: &Row, idx: usize, blobs_as_buffer: bool) -> LuaResult<LuaValue> {
+    match row.get_ref(idx).into_lua_err()? {
+        ValueRef::Blob(b) if blobs_as_buffer => Ok(LuaValue::Buffer(lua.create_buffer(b)?)),
+        value_ref => value_ref_to_lua(lua, value_ref),
+    }
+}
+
+/// Convert a borrowed SQL value to a Lua value.
+pub fn value_ref_to_lua(lua: &Lua, value_ref: ValueRef<'_>) -> LuaResult<LuaValue> {
     match value_ref {
         ValueRef::Null => Ok(LuaValue::Nil),
         ValueRef::Integer(i) => Ok(LuaValue::Integer(i)),
         ValueRef::Real(r) => Ok(LuaValue::Number(r)),
         ValueRef::Text(t) => {
             let s = std::str::from_utf8(t).into_lua_err()?;
+            if let Some(value) = SqlTypeRegistry::from_sql(lua, s)? {
+                return Ok(value);
+            }
             Ok(LuaValue::String(lua.create_string(s)?))
         }
         ValueRef::Blob(b) => Ok(LuaValue::String(lua.create_string(b)?)),
     }
 }
+
+/// A value wrapped by `sql.int`, `sql.real` or `sql.datetime` to pick how it is stored explicitly.
+#[derive(Debug, Clone, Copy)]
+pub enum SqlTyped {
+    Int(i64),
+    Real(f64),
+    /// Stored as ISO-8601 UTC text, which the built-in date functions understand.
+    DateTime(DateTime<Utc>),
+}
+
+impl SqlTyped {
+    /// Wrap `value` as an INTEGER, erroring if it is not integral or out of range.
+    pub fn int(value: &LuaValue) -> LuaResult<Self> {
+        match value {
+            LuaValue::Integer(i) => Ok(Self::Int(*i)),
+            LuaValue::Number(n) => integral_to_i64(*n).map(Self::Int).ok_or_else(|| {
+                LuaError::external(format!(
+                    "sql.int expected an integer in the 64-bit range, got {n}"
+                ))
+            }),
+            _ => Err(LuaError::external(format!(
+                "sql.int expected a number, got {}",
+                value.type_name()
+            ))),
+        }
+    }
+
+    /// Wrap `value` as a REAL.
+    pub fn real(value: &LuaValue) -> LuaResult<Self> {
+        match value {
+            #[allow(clippy::cast_precision_loss)]
+            LuaValue::Integer(i) => Ok(Self::Real(*i as f64)),
+            LuaValue::Number(n) => Ok(Self::Real(*n)),
+            _ => Err(LuaError::external(format!(
+                "sql.real expected a number, got {}",
+                value.type_name()
+            ))),
+        }
+    }
+
+    /// Wrap unix epoch seconds or a date table as a date and time.
+    pub fn datetime(value: &LuaValue) -> LuaResult<Self> {
+        datetime::from_lua_value(value).map(Self::DateTime)
+    }
+
+    fn to_sql(self) -> SqlValue {
+        match self {
+            Self::Int(i) => SqlValue::Integer(i),
+            Self::Real(r) => SqlValue::Real(r),
+            Self::DateTime(dt) => SqlValue::Text(datetime::to_iso(&dt)),
+        }
+    }
+}
+
+impl LuaUserData for SqlTyped {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("value", |_, this| {
+            Ok(match *this {
+                Self::Int(i) => LuaValue::Integer(i),
+                Self::Real(r) => LuaValue::Number(r),
+                // Unix epoch seconds, matching what sql.datetime accepts
+                #[allow(clippy::cast_precision_loss)]
+                Self::DateTime(dt) => match dt.timestamp_subsec_nanos() {
+                    0 => LuaValue::Integer(dt.timestamp()),
+                    nanos => LuaValue::Number(dt.timestamp() as f64 + f64::from(nanos) / 1e9),
+                },
+            })
+        });
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(match *this {
+                Self::Int(i) => format!("sql.int({i})"),
+                Self::Real(r) => format!("sql.real({r})"),
+                Self::DateTime(dt) => format!("sql.datetime({})", datetime::to_iso(&dt)),
+            })
+        });
+    }
+}
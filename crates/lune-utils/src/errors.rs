@@ -2,6 +2,8 @@
 //!
 //! All errors follow zero-panic policy - no `.unwrap()` or `.expect()` in production.
 
+use std::io::ErrorKind;
+
 use thiserror::Error;
 
 /// Root error type for Lune runtime.
@@ -42,6 +44,14 @@ pub enum NetworkError {
     #[error("Connection reset by peer")]
     ConnectionReset,
 
+    #[error("Failed to connect to {host}:{port}: {source}")]
+    ConnectFailed {
+        host: String,
+        port: u16,
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("DNS resolution failed for {domain}")]
     DnsResolutionFailed { domain: String },
 
@@ -70,6 +80,116 @@ pub enum NetworkError {
     TlsError(String),
 }
 
+impl NetworkError {
+    /// Classify an error from connecting to `host:port`.
+    #[must_use]
+    pub fn from_connect(host: &str, port: u16, source: std::io::Error) -> Self {
+        match source.kind() {
+            ErrorKind::ConnectionRefused => Self::ConnectionRefused {
+                host: host.to_owned(),
+                port,
+            },
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => Self::ConnectionReset,
+            _ => Self::ConnectFailed {
+                host: host.to_owned(),
+                port,
+                source,
+            },
+        }
+    }
+
+    /// Classify an error from binding or listening on `address`.
+    #[must_use]
+    pub fn from_bind(address: &str, source: std::io::Error) -> Self {
+        Self::BindFailed {
+            address: address.to_owned(),
+            source,
+        }
+    }
+
+    /// Classify an error from writing to a socket.
+    #[must_use]
+    pub fn from_send(source: std::io::Error) -> Self {
+        if is_reset(source.kind()) {
+            Self::ConnectionReset
+        } else {
+            Self::SendFailed { source }
+        }
+    }
+
+    /// Classify an error from reading from a socket.
+    #[must_use]
+    pub fn from_receive(source: std::io::Error) -> Self {
+        if is_reset(source.kind()) {
+            Self::ConnectionReset
+        } else {
+            Self::ReceiveFailed { source }
+        }
+    }
+
+    /// Name of the variant, such as `"ConnectionRefused"`.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::BindFailed { .. } => "BindFailed",
+            Self::ConnectionRefused { .. } => "ConnectionRefused",
+            Self::ConnectionReset => "ConnectionReset",
+            Self::ConnectFailed { .. } => "ConnectFailed",
+            Self::DnsResolutionFailed { .. } => "DnsResolutionFailed",
+            Self::Timeout { .. } => "Timeout",
+            Self::SendFailed { .. } => "SendFailed",
+            Self::ReceiveFailed { .. } => "ReceiveFailed",
+            Self::InvalidAddress(_) => "InvalidAddress",
+            Self::HttpError { .. } => "HttpError",
+            Self::TlsError(_) => "TlsError",
+        }
+    }
+
+    /// The underlying OS error kind, if this error came from one.
+    #[must_use]
+    pub fn io_kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::BindFailed { source, .. }
+            | Self::ConnectFailed { source, .. }
+            | Self::SendFailed { source }
+            | Self::ReceiveFailed { source } => Some(source.kind()),
+            Self::ConnectionRefused { .. } => Some(ErrorKind::ConnectionRefused),
+            Self::ConnectionReset => Some(ErrorKind::ConnectionReset),
+            Self::Timeout { .. } => Some(ErrorKind::TimedOut),
+            _ => None,
+        }
+    }
+
+    /// Whether retrying the same operation later may succeed.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self.io_kind(),
+            Some(
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::TimedOut
+                    | ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+            )
+        )
+    }
+}
+
+impl From<NetworkError> for mlua::Error {
+    fn from(e: NetworkError) -> Self {
+        mlua::Error::external(e)
+    }
+}
+
+fn is_reset(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+    )
+}
+
 /// Package installation errors.
 #[derive(Error, Debug)]
 pub enum InstallError {
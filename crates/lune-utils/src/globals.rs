@@ -2,8 +2,12 @@
 //!
 //! Provides additional math functions and colored warn output.
 
+use std::cmp::Ordering;
+
 use mlua::prelude::*;
 
+use crate::newtypes::{Version, VersionReq};
+
 /// Inject extended globals into the Lua state.
 ///
 /// # Errors
@@ -13,6 +17,7 @@ pub fn inject_globals(lua: &Lua) -> LuaResult<()> {
     inject_math_extensions(lua)?;
     inject_colored_warn(lua)?;
     inject_uuid(lua)?;
+    inject_semver(lua)?;
     Ok(())
 }
 
@@ -79,12 +84,86 @@ fn inject_math_extensions(lua: &Lua) -> LuaResult<()> {
         })?,
     )?;
 
+    // math.gcd(a, b)
+    math.set(
+        "gcd",
+        lua.create_function(|_, (a, b): (f64, f64)| {
+            let a = to_integer("gcd", a)?;
+            let b = to_integer("gcd", b)?;
+            Ok(gcd(a.unsigned_abs(), b.unsigned_abs()) as f64)
+        })?,
+    )?;
+
+    // math.lcm(a, b)
+    math.set(
+        "lcm",
+        lua.create_function(|_, (a, b): (f64, f64)| {
+            let a = to_integer("lcm", a)?;
+            let b = to_integer("lcm", b)?;
+            let (a, b) = (a.unsigned_abs(), b.unsigned_abs());
+            if a == 0 || b == 0 {
+                return Ok(0.0);
+            }
+            Ok((a / gcd(a, b)) as f64 * b as f64)
+        })?,
+    )?;
+
+    // math.factorial(n)
+    math.set(
+        "factorial",
+        lua.create_function(|_, n: f64| {
+            let n = to_integer("factorial", n)?;
+            if n < 0 {
+                return Err(LuaError::runtime(
+                    "math.factorial expects a non-negative integer",
+                ));
+            }
+            // Past 170! the result no longer fits in a double and becomes inf
+            if n > 170 {
+                return Ok(f64::INFINITY);
+            }
+            Ok((2..=n).fold(1.0, |acc, i| acc * i as f64))
+        })?,
+    )?;
+
+    // math.isNan(value)
+    math.set(
+        "isNan",
+        lua.create_function(|_, value: f64| Ok(value.is_nan()))?,
+    )?;
+
+    // math.isFinite(value)
+    math.set(
+        "isFinite",
+        lua.create_function(|_, value: f64| Ok(value.is_finite()))?,
+    )?;
+
     // math.tau
     math.set("tau", std::f64::consts::TAU)?;
 
     Ok(())
 }
 
+/// Largest integer a double can hold exactly, 2^53.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Check that `value` is an integer that a double can hold exactly.
+fn to_integer(name: &str, value: f64) -> LuaResult<i64> {
+    if value.fract() != 0.0 || value.abs() > MAX_SAFE_INTEGER {
+        return Err(LuaError::runtime(format!(
+            "math.{name} expects integers, got {value}"
+        )));
+    }
+    Ok(value as i64)
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
 fn inject_colored_warn(lua: &Lua) -> LuaResult<()> {
     let globals = lua.globals();
 
@@ -134,3 +213,54 @@ fn inject_uuid(lua: &Lua) -> LuaResult<()> {
 
     Ok(())
 }
+
+fn inject_semver(lua: &Lua) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    // Create semver table
+    let semver_table = lua.create_table()?;
+
+    // semver.parse(version) -> { major, minor, patch, pre, build }
+    semver_table.set(
+        "parse",
+        lua.create_function(|lua, version: String| {
+            let version = Version::parse(&version).into_lua_err()?;
+            let inner = version.inner();
+            let table = lua.create_table()?;
+            table.set("major", inner.major)?;
+            table.set("minor", inner.minor)?;
+            table.set("patch", inner.patch)?;
+            table.set("pre", inner.pre.as_str())?;
+            table.set("build", inner.build.as_str())?;
+            Ok(table)
+        })?,
+    )?;
+
+    // semver.satisfies(version, range) -> boolean
+    semver_table.set(
+        "satisfies",
+        lua.create_function(|_, (version, range): (String, String)| {
+            let version = Version::parse(&version).into_lua_err()?;
+            let req = VersionReq::parse(&range).into_lua_err()?;
+            Ok(req.matches(&version))
+        })?,
+    )?;
+
+    // semver.compare(a, b) -> -1 | 0 | 1
+    semver_table.set(
+        "compare",
+        lua.create_function(|_, (a, b): (String, String)| {
+            let a = Version::parse(&a).into_lua_err()?;
+            let b = Version::parse(&b).into_lua_err()?;
+            Ok(match a.cmp(&b) {
+                Ordering::Less => -1,
+                Ordering::Equal => 0,
+                Ordering::Greater => 1,
+            })
+        })?,
+    )?;
+
+    globals.set("semver", semver_table)?;
+
+    Ok(())
+}
@@ -9,6 +9,7 @@ pub mod globals;
 pub mod newtypes;
 pub mod path;
 pub mod process;
+pub mod structured_errors;
 
 pub use self::errors::{
     DatabaseError, InstallError, LuneError, LuneResult, NetworkError, ValidationError,
@@ -0,0 +1,146 @@
+//! Raising Rust errors to Lua as plain tables.
+//!
+//! Errors raised from Rust always cross into Lua as opaque userdata,
+//! so libraries that want scripts to branch on error fields wrap their
+//! functions in a small Luau shim that converts known errors to tables.
+
+use std::{future::Future, marker::PhantomData};
+
+use mlua::prelude::*;
+
+/// Turns a Lua error into the table raised in its place, or `None` to raise it unchanged.
+pub type ErrorConverter = fn(&Lua, &LuaError) -> LuaResult<Option<LuaTable>>;
+
+const STRUCTURED_ERRORS_LUA: &str = r"
+local results = table.pack(pcall(inner, ...))
+if not results[1] then
+    error(convert(results[2]), 2)
+end
+return table.unpack(results, 2, results.n)
+";
+
+/**
+    Wraps `inner` so that errors it raises are passed through `convert` before reaching Lua.
+
+    Luau allows yielding across `pcall`, so this also works for async functions.
+
+    # Errors
+
+    Errors when out of memory.
+*/
+pub fn with_structured_errors(
+    lua: &Lua,
+    name: &str,
+    inner: LuaFunction,
+    convert: ErrorConverter,
+) -> LuaResult<LuaFunction> {
+    let convert = lua.create_function(move |lua, value: LuaValue| match &value {
+        LuaValue::Error(err) => match convert(lua, err)? {
+            Some(table) => Ok(LuaValue::Table(table)),
+            None => Ok(value),
+        },
+        _ => Ok(value),
+    })?;
+
+    let globals = lua.globals();
+    let env = lua.create_table()?;
+    env.set("pcall", globals.get::<LuaFunction>("pcall")?)?;
+    env.set("error", globals.get::<LuaFunction>("error")?)?;
+    env.set("table", globals.get::<LuaTable>("table")?)?;
+    env.set("inner", inner)?;
+    env.set("convert", convert)?;
+
+    lua.load(STRUCTURED_ERRORS_LUA)
+        .set_name(name)
+        .set_environment(env)
+        .into_function()
+}
+
+/**
+    A userdata method whose errors are passed through an [`ErrorConverter`].
+
+    Registered as a field holding a wrapped function rather than as a method,
+    since methods are called straight from Rust and cannot be wrapped.
+*/
+pub struct StructuredMethod<T, A, R, F> {
+    name: &'static str,
+    convert: ErrorConverter,
+    method: F,
+    _marker: PhantomData<fn(&T, A) -> R>,
+}
+
+impl<T, A, R, F> StructuredMethod<T, A, R, F>
+where
+    F: Fn(&Lua, &T, A) -> LuaResult<R>,
+{
+    pub fn new(name: &'static str, convert: ErrorConverter, method: F) -> Self {
+        Self {
+            name,
+            convert,
+            method,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, A, R, F> IntoLua for StructuredMethod<T, A, R, F>
+where
+    T: LuaUserData + 'static,
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&Lua, &T, A) -> LuaResult<R> + 'static,
+{
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let method = self.method;
+        let inner = lua.create_function(move |lua, (this, args): (LuaUserDataRef<T>, A)| {
+            method(lua, &this, args)
+        })?;
+        with_structured_errors(lua, self.name, inner, self.convert).map(LuaValue::Function)
+    }
+}
+
+/**
+    An async userdata method whose errors are passed through an [`ErrorConverter`].
+
+    The async counterpart of [`StructuredMethod`].
+*/
+pub struct StructuredAsyncMethod<T, A, R, F> {
+    name: &'static str,
+    convert: ErrorConverter,
+    method: F,
+    _marker: PhantomData<fn(&T, A) -> R>,
+}
+
+impl<T, A, R, F, Fut> StructuredAsyncMethod<T, A, R, F>
+where
+    T: 'static,
+    F: Fn(Lua, LuaUserDataRef<T>, A) -> Fut,
+    Fut: Future<Output = LuaResult<R>>,
+{
+    pub fn new(name: &'static str, convert: ErrorConverter, method: F) -> Self {
+        Self {
+            name,
+            convert,
+            method,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, A, R, F, Fut> IntoLua for StructuredAsyncMethod<T, A, R, F>
+where
+    T: LuaUserData + 'static,
+    A: FromLuaMulti + 'static,
+    R: IntoLuaMulti + 'static,
+    F: Fn(Lua, LuaUserDataRef<T>, A) -> Fut + 'static,
+    Fut: Future<Output = LuaResult<R>> + 'static,
+{
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let method = self.method;
+        let inner =
+            lua.create_async_function(move |lua, (this, args): (LuaUserDataRef<T>, A)| {
+                method(lua, this, args)
+            })?;
+        with_structured_errors(lua, self.name, inner, self.convert).map(LuaValue::Function)
+    }
+}
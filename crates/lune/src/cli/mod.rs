@@ -21,7 +21,11 @@ pub struct Cli {
     #[arg(long)]
     pub init: bool,
 
-    /// Install packages. Without args: reads lune.config.json. With args: installs specified packages
+    /// Write or refresh standard library type definitions in the given directory
+    #[arg(long, value_name = "DIR")]
+    pub typedefs: Option<std::path::PathBuf>,
+
+    /// Install packages. Without args: reads lune.config.json. With args: installs specified packages, by registry name or as github:owner/repo, with an optional @version or #<commit sha>
     #[arg(short, long, num_args = 0..)]
     pub install: Option<Vec<String>>,
 
@@ -33,6 +37,14 @@ pub struct Cli {
     #[arg(long = "updpkg")]
     pub update_packages: bool,
 
+    /// Allow packages to run their postInstall scripts during install/update
+    #[arg(long = "allow-scripts")]
+    pub allow_scripts: bool,
+
+    /// Fail to install packages that have no Luau entry point, instead of warning
+    #[arg(long)]
+    pub strict: bool,
+
     /// List installed packages
     #[arg(long = "listpkg")]
     pub list_packages: bool,
@@ -66,9 +78,12 @@ impl Default for Cli {
     fn default() -> Self {
         Self {
             init: false,
+            typedefs: None,
             install: None,
             uninstall: None,
             update_packages: false,
+            allow_scripts: false,
+            strict: false,
             list_packages: false,
             package_info: None,
             script: None,
@@ -110,16 +125,21 @@ impl Cli {
     }
 
     pub async fn run(self) -> Result<ExitCode> {
-        // Priority: --init > --install > --uninstall > --updpkg > --listpkg > --info > --list > --build > --repl > script
+        // Priority: --init > --typedefs > --install > --uninstall > --updpkg > --listpkg > --info > --list > --build > --repl > script
 
         // Mode: Init project
         if self.init {
             return installer::run_init();
         }
 
+        // Mode: Write type definitions
+        if let Some(dir) = self.typedefs {
+            return installer::run_typedefs(&dir);
+        }
+
         // Mode: Installation
         if let Some(packages) = self.install {
-            return installer::run_install(packages).await;
+            return installer::run_install(packages, self.allow_scripts, self.strict).await;
         }
 
         // Mode: Uninstall packages
@@ -129,7 +149,7 @@ impl Cli {
 
         // Mode: Update packages
         if self.update_packages {
-            return installer::run_update().await;
+            return installer::run_update(self.allow_scripts, self.strict).await;
         }
 
         // Mode: List installed packages
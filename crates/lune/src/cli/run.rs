@@ -3,6 +3,7 @@ use std::{env, io::stdin, process::ExitCode};
 use anyhow::{Context, Result};
 use blocking::Unblock;
 use clap::Parser;
+use directories::UserDirs;
 use futures_lite::prelude::*;
 
 use lune::Runtime;
@@ -29,11 +30,26 @@ impl RunCommand {
             .ok()
             .is_some_and(|s| matches!(s.as_str(), "0" | "false" | "off"));
 
+        // Check if the user has explicitly disabled the bytecode cache (on by default)
+        let cache_disabled = env::var("LUNE_BYTECODE_CACHE")
+            .ok()
+            .is_some_and(|s| matches!(s.as_str(), "0" | "false" | "off"));
+
         // Create a new lune runtime with all globals & run the script
         let mut rt = Runtime::new()?
             .with_args(self.script_args)
             .with_jit(!jit_disabled);
 
+        // Skip compiling scripts that have not changed since they were last run
+        if !cache_disabled && let Some(user_dirs) = UserDirs::new() {
+            let cache_dir = user_dirs
+                .home_dir()
+                .join(".lune")
+                .join("cache")
+                .join("bytecode");
+            rt = rt.with_bytecode_cache(cache_dir);
+        }
+
         // Figure out if we should run stdin or run a file,
         // reading from stdin is marked by passing a single "-"
         // (dash) as the script name to run to the cli
@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use lune_std::LuneStandardLibrary;
+
+/**
+    Writes the type definitions for every standard library into `dir`,
+    creating the directory if needed and overwriting any existing files.
+
+    Returns the number of type definition files written.
+*/
+pub fn write_typedefs(dir: &Path) -> Result<usize> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+
+    let mut written = 0;
+    for lib in LuneStandardLibrary::ALL {
+        let typedef_file = dir.join(format!("{}.luau", lib.name()));
+        std::fs::write(&typedef_file, lib.typedefs())
+            .with_context(|| format!("Failed to write {}", typedef_file.display()))?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_typedefs_covers_all_libraries() {
+        let dir = std::env::temp_dir().join(format!("lune-typedefs-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let written = write_typedefs(&dir).unwrap();
+        assert_eq!(written, LuneStandardLibrary::ALL.len());
+
+        for lib in LuneStandardLibrary::ALL {
+            let path = dir.join(format!("{}.luau", lib.name()));
+            let contents = std::fs::read_to_string(&path).unwrap();
+            assert!(!contents.trim().is_empty(), "{} is empty", path.display());
+        }
+
+        // Refreshing an existing directory overwrites in place
+        assert_eq!(write_typedefs(&dir).unwrap(), written);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_fs as fs;
+use mlua::Compiler as LuaCompiler;
+
+/**
+    Where compiled bytecode came from, returned by [`BytecodeCache::load_or_compile`].
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BytecodeSource {
+    /// The bytecode was read from the cache, skipping compilation.
+    Cache,
+    /// The source was compiled, and the bytecode stored in the cache.
+    Compiled,
+}
+
+/**
+    A directory of compiled Luau bytecode, keyed by a hash of the source.
+
+    The runtime version is part of the hash, so upgrading Lune never
+    loads bytecode compiled by another version, and a changed source
+    simply hashes to a different entry.
+*/
+#[derive(Debug, Clone)]
+pub struct BytecodeCache {
+    dir: PathBuf,
+    version: String,
+}
+
+impl BytecodeCache {
+    pub fn new(dir: impl Into<PathBuf>, version: impl Into<String>) -> Self {
+        Self {
+            dir: dir.into(),
+            version: version.into(),
+        }
+    }
+
+    /**
+        Returns the bytecode for `source`, from the cache if it was compiled before.
+
+        Returns `None` if the source does not compile, so that it can be loaded as
+        text instead and the syntax error reported the same way as without a cache.
+        Failing to read or write the cache is never an error, it is only skipped.
+    */
+    pub async fn load_or_compile(&self, source: &[u8]) -> Option<(Vec<u8>, BytecodeSource)> {
+        let path = self.entry_path(source);
+
+        if let Ok(bytecode) = fs::read(&path).await
+            && is_bytecode(&bytecode)
+        {
+            return Some((bytecode, BytecodeSource::Cache));
+        }
+
+        // Same options as loading the source as text, so line info and
+        // error messages do not change depending on the cache being used
+        let bytecode = LuaCompiler::new().compile(source).ok()?;
+        if !is_bytecode(&bytecode) {
+            return None;
+        }
+
+        if let Err(e) = self.store(&path, &bytecode).await {
+            tracing::debug!(
+                "Failed to write bytecode cache entry {}: {e}",
+                path.display()
+            );
+        }
+
+        Some((bytecode, BytecodeSource::Compiled))
+    }
+
+    fn entry_path(&self, source: &[u8]) -> PathBuf {
+        let mut hasher = Fnv128::new();
+        hasher.write(self.version.as_bytes());
+        hasher.write(&[0]);
+        hasher.write(source);
+        self.dir.join(format!("{:032x}.luauc", hasher.finish()))
+    }
+
+    async fn store(&self, path: &Path, bytecode: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        // Written under a temporary name first, so that a run that is interrupted,
+        // or another run of the same script, never reads a partially written entry
+        let temp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&temp, bytecode).await?;
+        fs::rename(&temp, path).await
+    }
+}
+
+/**
+    Luau bytecode starts with its version byte, while failed compilations
+    start with a zero byte followed by the error message.
+*/
+fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.first().is_some_and(|&b| b != 0 && b < b'\n')
+}
+
+/**
+    128-bit FNV-1a, which unlike the std hashers is stable
+    across builds and so can be used for file names on disk.
+*/
+struct Fnv128(u128);
+
+impl Fnv128 {
+    const OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+    fn new() -> Self {
+        Self(Self::OFFSET)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u128::from(b);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn finish(&self) -> u128 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache(name: &str, version: &str) -> BytecodeCache {
+        let dir =
+            std::env::temp_dir().join(format!("lune-bytecode-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        BytecodeCache::new(dir, version)
+    }
+
+    #[test]
+    fn second_load_reads_from_cache() {
+        let cache = temp_cache("hit", "1.0.0");
+        let source = b"return 1 + 1";
+
+        let (compiled, from) = async_io::block_on(cache.load_or_compile(source)).unwrap();
+        assert_eq!(from, BytecodeSource::Compiled);
+
+        let (cached, from) = async_io::block_on(cache.load_or_compile(source)).unwrap();
+        assert_eq!(from, BytecodeSource::Cache);
+        assert_eq!(cached, compiled);
+
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn changes_invalidate_entries() {
+        let cache = temp_cache("invalidate", "1.0.0");
+        async_io::block_on(cache.load_or_compile(b"return 1")).unwrap();
+
+        let (_, from) = async_io::block_on(cache.load_or_compile(b"return 2")).unwrap();
+        assert_eq!(from, BytecodeSource::Compiled, "changed source");
+
+        let upgraded = BytecodeCache::new(&cache.dir, "1.0.1");
+        let (_, from) = async_io::block_on(upgraded.load_or_compile(b"return 1")).unwrap();
+        assert_eq!(from, BytecodeSource::Compiled, "changed runtime version");
+
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn syntax_errors_are_not_cached() {
+        let cache = temp_cache("syntax", "1.0.0");
+        assert!(async_io::block_on(cache.load_or_compile(b"return +")).is_none());
+        assert!(!cache.dir.exists());
+    }
+
+    #[test]
+    fn corrupt_entries_are_recompiled() {
+        let cache = temp_cache("corrupt", "1.0.0");
+        let source = b"return true";
+        std::fs::create_dir_all(&cache.dir).unwrap();
+        std::fs::write(cache.entry_path(source), b"garbage").unwrap();
+
+        let (bytecode, from) = async_io::block_on(cache.load_or_compile(source)).unwrap();
+        assert_eq!(from, BytecodeSource::Compiled);
+        assert_eq!(std::fs::read(cache.entry_path(source)).unwrap(), bytecode);
+
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+}
@@ -17,7 +17,10 @@ use lune_utils::{
 use mlua::prelude::*;
 use mlua_luau_scheduler::{Functions, Scheduler};
 
-use super::{RuntimeError, RuntimeResult};
+use super::{
+    RuntimeError, RuntimeResult,
+    bytecode::{BytecodeCache, BytecodeSource},
+};
 
 /**
     Values returned by running a Lune runtime until completion.
@@ -69,6 +72,7 @@ pub struct Runtime {
     args: ProcessArgs,
     env: ProcessEnv,
     jit: ProcessJitEnablement,
+    bytecode_cache: Option<BytecodeCache>,
 }
 
 impl Runtime {
@@ -144,6 +148,7 @@ impl Runtime {
             args,
             env,
             jit,
+            bytecode_cache: None,
         })
     }
 
@@ -190,6 +195,20 @@ impl Runtime {
         self
     }
 
+    /**
+        Caches compiled bytecode for files run using [`run_file`] in the given directory.
+
+        Entries are keyed by a hash of the file contents and the Lune version, so
+        running an unchanged file again loads its bytecode instead of compiling it.
+
+        [`run_file`]: Runtime::run_file
+    */
+    #[must_use]
+    pub fn with_bytecode_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.bytecode_cache = Some(BytecodeCache::new(dir, env!("CARGO_PKG_VERSION")));
+        self
+    }
+
     /**
         Adds a custom library to the runtime, making it available through `require`.
 
@@ -327,7 +346,16 @@ impl Runtime {
             })?;
 
         let module_name = format!("{FILE_CHUNK_PREFIX}{module_path}");
-        let module_contents = strip_shebang(contents);
+        let mut module_contents = strip_shebang(contents);
+
+        if let Some(cache) = &self.bytecode_cache
+            && let Some((bytecode, source)) = cache.load_or_compile(&module_contents).await
+        {
+            if source == BytecodeSource::Cache {
+                tracing::debug!("Loaded bytecode for {module_path} from cache");
+            }
+            module_contents = bytecode;
+        }
 
         self.run_inner(module_name, module_contents).await
     }
@@ -7,6 +7,8 @@ use console::set_colors_enabled;
 use console::set_colors_enabled_stderr;
 
 use lune_utils::path::clean_path;
+#[cfg(feature = "std-process")]
+use mlua::prelude::*;
 
 use crate::Runtime;
 
@@ -75,9 +77,11 @@ create_tests! {
     global_version: "globals/_VERSION",
     global_coroutine: "globals/coroutine",
     global_error: "globals/error",
+    global_math: "globals/math",
     global_pcall: "globals/pcall",
     global_type: "globals/type",
     global_typeof: "globals/typeof",
+    global_semver: "globals/semver",
     global_warn: "globals/warn",
 }
 
@@ -134,8 +138,20 @@ create_tests! {
     net_socket_wss_rw: "net/socket/wss_rw",
 
     net_tcp_basic: "net/tcp/basic",
+    net_tcp_bind_addr: "net/tcp/bind_addr",
+    net_tcp_errors: "net/tcp/errors",
+    net_tcp_free_port: "net/tcp/free_port",
     net_tcp_info: "net/tcp/info",
+    net_tcp_ipv6: "net/tcp/ipv6",
+    net_tcp_read_to_end: "net/tcp/read_to_end",
+    net_tcp_read_until: "net/tcp/read_until",
+    net_tcp_serve_limit: "net/tcp/serve_limit",
+    net_tcp_socket_options: "net/tcp/socket_options",
+    net_tcp_stats: "net/tcp/stats",
     net_tcp_tls: "net/tcp/tls",
+    net_tcp_tls_connect: "net/tcp/tls_connect",
+    net_tcp_udp_multicast: "net/tcp/udp_multicast",
+    net_tcp_writev: "net/tcp/writev",
 
     net_url_encode: "net/url/encode",
     net_url_decode: "net/url/decode",
@@ -157,6 +173,33 @@ create_tests! {
     process_spawn_non_blocking: "process/create/non_blocking",
     process_spawn_status: "process/create/status",
     process_spawn_stream: "process/create/stream",
+    process_spawn_handle_basic: "process/spawn/basic",
+    process_spawn_handle_kill: "process/spawn/kill",
+    process_spawn_handle_stream: "process/spawn/stream",
+}
+
+#[cfg(feature = "std-process")]
+#[test]
+fn process_args_env_echo() -> Result<()> {
+    async_io::block_on(async {
+        // This mirrors what the run command does with script arguments,
+        // making sure they are visible to the script alongside the env
+        let mut rt = Runtime::new()?
+            .with_args(["first", "second"])
+            .with_env([("LUNE_TEST_ECHO", "echoed")]);
+
+        let script = r#"
+            local process = require("@lune/process")
+            return process.args[1], process.env.LUNE_TEST_ECHO
+        "#;
+        let returned = rt.run_custom("echo", script).await?;
+
+        let values = returned.values.into_vec();
+        assert!(matches!(values.first(), Some(LuaValue::String(s)) if s == "first"));
+        assert!(matches!(values.get(1), Some(LuaValue::String(s)) if s == "echoed"));
+
+        Ok(())
+    })
 }
 
 #[cfg(feature = "std-regex")]
@@ -268,3 +311,77 @@ create_tests! {
     task_spawn: "task/spawn",
     task_wait: "task/wait",
 }
+
+#[cfg(feature = "std-sql")]
+create_tests! {
+    sql_open_uri: "sql/open_uri",
+    sql_statement_reuse: "sql/statement_reuse",
+    sql_slow_query: "sql/slow_query",
+    sql_attach: "sql/attach",
+    sql_register_type: "sql/register_type",
+    sql_for_each: "sql/for_each",
+    sql_execute_returning: "sql/execute_returning",
+    sql_affected_rows: "sql/affected_rows",
+    sql_execute_script: "sql/execute_script",
+    sql_typed_values: "sql/typed_values",
+    sql_schema: "sql/schema",
+    sql_integer_binding: "sql/integer_binding",
+    sql_duplicate: "sql/duplicate",
+    sql_prepare_cached: "sql/prepare_cached",
+    sql_datetime_hints: "sql/datetime_hints",
+    sql_transaction: "sql/transaction",
+    sql_close: "sql/close",
+    sql_snapshot: "sql/snapshot",
+    sql_maintenance: "sql/maintenance",
+    sql_named_params: "sql/named_params",
+    sql_last_insert_rowid: "sql/last_insert_rowid",
+    sql_blobs_as_buffer: "sql/blobs_as_buffer",
+    sql_open_options: "sql/open_options",
+    sql_register_function: "sql/register_function",
+    sql_savepoint: "sql/savepoint",
+    sql_error_codes: "sql/error_codes",
+    sql_backup: "sql/backup",
+    sql_query_many: "sql/query_many",
+    sql_datetime_binding: "sql/datetime_binding",
+    sql_query_one: "sql/query_one",
+    sql_query_with_meta: "sql/query_with_meta",
+    sql_open_flags: "sql/open_flags",
+}
+
+#[cfg(feature = "std-ffi")]
+create_tests! {
+    ffi_string_array: "ffi/string_array",
+    ffi_struct_endian: "ffi/struct_endian",
+    ffi_buffer_align: "ffi/buffer_align",
+    ffi_buffer_as_array: "ffi/buffer_as_array",
+    ffi_buffer_from_ptr: "ffi/buffer_from_ptr",
+    ffi_buffer_uninit: "ffi/buffer_uninit",
+    ffi_arg_direction: "ffi/arg_direction",
+    ffi_bool_returns: "ffi/bool_returns",
+    ffi_wrap: "ffi/wrap",
+    ffi_callback_struct: "ffi/callback_struct",
+    ffi_callback_many_args: "ffi/callback_many_args",
+    ffi_callback_out_params: "ffi/callback_out_params",
+    ffi_callback_as_pointer: "ffi/callback_as_pointer",
+    ffi_load_errors: "ffi/load_errors",
+    ffi_list_exports: "ffi/list_exports",
+    ffi_symbolicate: "ffi/symbolicate",
+    ffi_integer_reads: "ffi/integer_reads",
+    ffi_offset_of: "ffi/offset_of",
+    ffi_scratch_reentrancy: "ffi/scratch_reentrancy",
+    ffi_writer: "ffi/writer",
+    ffi_reader: "ffi/reader",
+    ffi_types_constructors: "ffi/types_constructors",
+    ffi_call_once: "ffi/call_once",
+    ffi_typed_pointer_len: "ffi/typed_pointer_len",
+    ffi_struct_bitfields: "ffi/struct_bitfields",
+    ffi_struct_class: "ffi/struct_class",
+    ffi_struct_pointers: "ffi/struct_pointers",
+    ffi_struct_layout: "ffi/struct_layout",
+    ffi_struct_nested: "ffi/struct_nested",
+    ffi_union: "ffi/union",
+    ffi_call_variadic: "ffi/call_variadic",
+    ffi_view_luau_buffer: "ffi/view_luau_buffer",
+    ffi_arg_errors: "ffi/arg_errors",
+    ffi_cdef: "ffi/cdef",
+}
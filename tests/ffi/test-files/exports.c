@@ -0,0 +1,8 @@
+// Fixture for tests/ffi/list_exports.luau, exporting one function and one global variable.
+// Compiled by the test itself with: cc -shared -fPIC -O2 -nostdlib -o <out>.so exports.c
+
+int exported_counter = 42;
+
+int exported_add(int a, int b) {
+	return a + b;
+}